@@ -0,0 +1,100 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    path::Path,
+};
+
+/*
+    NTFS files can have more content than what is read from them, in
+    alternate data streams with names of their own, i.e. the
+    Zone.Identifier windows marks downloaded files with. With --ads every
+    stream is stored as an entry of its own right after its file, named
+    file:stream and with the name of the stream under KEY in its meta.
+*/
+
+/// The meta key of entries that are a stream of the entry before them.
+pub const KEY: &str = "kzip.ads";
+
+/// The names of the alternate data streams of the file at `path`, without
+/// the unnamed one that is its content.
+#[cfg(windows)]
+pub fn list(path: &Path) -> io::Result<Vec<String>> {
+    use std::{ffi::c_void, os::windows::ffi::OsStrExt};
+
+    const FIND_STREAM_INFO_STANDARD: u32 = 0;
+    const ERROR_HANDLE_EOF: i32 = 38;
+    const INVALID_HANDLE_VALUE: isize = -1;
+
+    // WIN32_FIND_STREAM_DATA, the name is MAX_PATH + 36 characters long
+    #[repr(C)]
+    struct FindStreamData {
+        _stream_size: i64,
+        stream_name: [u16; 296],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FindFirstStreamW(
+            file_name: *const u16,
+            info_level: u32,
+            find_stream_data: *mut FindStreamData,
+            flags: u32,
+        ) -> *mut c_void;
+        fn FindNextStreamW(find_stream: *mut c_void, find_stream_data: *mut FindStreamData) -> i32;
+        fn FindClose(find_file: *mut c_void) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut data = FindStreamData {
+        _stream_size: 0,
+        stream_name: [0; 296],
+    };
+    let handle =
+        unsafe { FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+    if handle as isize == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        // directories and files on other file systems have no streams
+        return match err.raw_os_error() {
+            Some(ERROR_HANDLE_EOF) => Ok(Vec::new()),
+            _ => Err(err),
+        };
+    }
+
+    let mut names = Vec::new();
+    loop {
+        let length = data.stream_name.iter().position(|c| *c == 0).unwrap_or(296);
+        // streams are called :name:$DATA, the content itself ::$DATA
+        let name = String::from_utf16_lossy(&data.stream_name[..length]);
+        if let Some(name) = name
+            .strip_prefix(':')
+            .and_then(|name| name.strip_suffix(":$DATA"))
+            .filter(|name| !name.is_empty())
+        {
+            names.push(name.to_string());
+        }
+        if unsafe { FindNextStreamW(handle, &mut data) } == 0 {
+            break;
+        }
+    }
+    let err = io::Error::last_os_error();
+    unsafe { FindClose(handle) };
+
+    match err.raw_os_error() {
+        Some(ERROR_HANDLE_EOF) => Ok(names),
+        _ => Err(err),
+    }
+}
+
+/// Other systems have no alternate data streams.
+#[cfg(not(windows))]
+pub fn list(_path: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// Where the stream `name` of the file at `path` is read and written.
+pub fn path(path: &OsStr, name: &str) -> OsString {
+    let mut path = path.to_os_string();
+    path.push(":");
+    path.push(name);
+    path
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, path::Path, process::exit};
+
+use crate::{
+    filter::{self, Filter},
+    list_files,
+    utils::{format_byte, format_ratio, option_value, positionals},
+};
+
+/// `kzip analyze -i dir [--top 20]`
+///
+/// Finds the files kzip would only store once, without compressing anything,
+/// to tell whether an archive of `dir` gains from it. Only files with the
+/// same size as another one are hashed.
+pub fn run(args: &[String]) {
+    let mut value_options = vec!["--input", "-i", "--top"];
+    value_options.extend(filter::OPTIONS);
+    let input = match option_value(args, &["--input", "-i"])
+        .or_else(|| positionals(&args[2..], &value_options).first().cloned())
+    {
+        Some(input) => input,
+        None => {
+            println!("kzip: analyze needs an input, i.e. kzip analyze -i dir");
+            exit(1);
+        }
+    };
+    let top = match option_value(args, &["--top"]).map(|top| top.parse::<usize>()) {
+        None => 20,
+        Some(Ok(top)) => top,
+        Some(Err(_)) => {
+            println!("kzip: --top has to be a number");
+            exit(1);
+        }
+    };
+
+    let filter = match Filter::from_args(args, Path::new(&input)) {
+        Ok(filter) => filter,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let mut files = Vec::new();
+    list_files(Path::new(&input), &filter, &mut files);
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    // files with a size no other file has can't be the same as another one,
+    // and empty files have nothing to store
+    let mut by_size: HashMap<u64, Vec<_>> = HashMap::new();
+    for (path, size) in &files {
+        if *size > 0 {
+            by_size.entry(*size).or_default().push(path);
+        }
+    }
+
+    let mut hashed = 0;
+    let mut hashed_bytes = 0;
+    let mut by_hash: HashMap<String, (u64, Vec<_>)> = HashMap::new();
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        for path in paths {
+            match sha256::try_digest(Path::new(path)) {
+                Ok(hash) => by_hash
+                    .entry(hash)
+                    .or_insert((size, Vec::new()))
+                    .1
+                    .push(path),
+                Err(err) => {
+                    println!("kzip: could not read {}: {err}", path.to_string_lossy());
+                    continue;
+                }
+            }
+            hashed += 1;
+            hashed_bytes += size;
+        }
+    }
+
+    // (size of every copy, copies), the most space wasted first
+    let mut groups: Vec<_> = by_hash
+        .into_values()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+    for (_, paths) in &mut groups {
+        paths.sort();
+    }
+    groups.sort_by(|(a_size, a), (b_size, b)| {
+        let saved = |size: &u64, paths: &Vec<_>| size * (paths.len() as u64 - 1);
+        saved(b_size, b)
+            .cmp(&saved(a_size, a))
+            .then_with(|| a[0].cmp(b[0]))
+    });
+
+    let duplicates: usize = groups.iter().map(|(_, paths)| paths.len() - 1).sum();
+    let saved: u64 = groups
+        .iter()
+        .map(|(size, paths)| size * (paths.len() as u64 - 1))
+        .sum();
+
+    println!("Files: {}", files.len());
+    println!("Input: {}", format_byte(total as f64));
+    println!(
+        "Hashed: {hashed} file(s) with the same size as another ({})",
+        format_byte(hashed_bytes as f64)
+    );
+
+    if !groups.is_empty() && top > 0 {
+        println!();
+        if groups.len() > top {
+            println!("The {top} of {} group(s) that save the most:", groups.len());
+        }
+        for (size, paths) in groups.iter().take(top) {
+            println!(
+                "{} copies of {}, {} saved",
+                paths.len(),
+                format_byte(*size as f64),
+                format_byte((size * (paths.len() as u64 - 1)) as f64)
+            );
+            for path in paths {
+                println!("  {}", path.to_string_lossy());
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "Duplicates: {duplicates} file(s) in {} group(s)",
+        groups.len()
+    );
+    println!(
+        "Saved by duplicates: {} ({} of the input)",
+        format_byte(saved as f64),
+        format_ratio(saved, total)
+    );
+    exit(0);
+}
@@ -0,0 +1,2091 @@
+// with only the reader or the writer built, parts of the format go unused
+#![cfg_attr(
+    not(all(feature = "reader", feature = "writer")),
+    allow(dead_code, unused_imports)
+)]
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{self, Path},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "writer")]
+use bytebuffer::ByteBuffer;
+#[cfg(feature = "writer")]
+use sha256::digest;
+
+#[cfg(feature = "encryption")]
+use crate::crypto::Password;
+#[cfg(feature = "index")]
+use crate::sidecar;
+use crate::{
+    codec::Codec,
+    normalize::nfc,
+    options::{CreateOptions, ExtractOptions},
+    special::Special,
+    vfs::{self, Vfs},
+};
+
+// magic number = cat
+pub const MAGIC: [u8; 3] = [12, 10, 116];
+
+// longest name an entry can have, well above the 4096 bytes most systems
+// allow for a path, and the 32767 characters windows allows for long paths
+pub const MAX_NAME_LENGTH: usize = 32 * 1024;
+
+// what `ArchiveReader::open_strict` allows, archives kzip writes stay well
+// within these but crafted ones don't have to
+pub(crate) const STRICT_NAME_LENGTH: usize = 4096;
+pub(crate) const STRICT_ENTRIES: u32 = 10_000_000;
+pub(crate) const STRICT_META_PAIRS: u32 = 1024;
+// flags, a name length and the modified date, the smallest an entry can be
+const MIN_ENTRY_LENGTH: u64 = 1 + 4 + 8;
+// the salt, nonce and tag every encrypted entry starts with, like
+// `crypto::OVERHEAD`, which is only there with the encryption feature
+pub(crate) const ENCRYPTION_OVERHEAD: u64 = 16 + 12 + 32;
+
+// entry flags, older archives only ever have DUPLICATE set
+const DUPLICATE: u8 = 1;
+const NO_CREATED_AT: u8 = 1 << 1;
+// the name is not valid UTF-8, it is stored as the bytes the filesystem gave
+const RAW_NAME: u8 = 1 << 2;
+// the content isn't compressed with zlib at its best level, the codec follows
+// the dates
+const CODEC: u8 = 1 << 3;
+// a fifo, socket or device node, the content is the node as `Special` stores it
+const SPECIAL: u8 = 1 << 4;
+// key/value pairs set with `kzip meta` follow the dates
+const META: u8 = 1 << 5;
+// the content is compressed and then encrypted, see crypto.rs
+const ENCRYPTED: u8 = 1 << 6;
+const KNOWN_FLAGS: u8 = DUPLICATE | NO_CREATED_AT | RAW_NAME | CODEC | SPECIAL | META | ENCRYPTED;
+
+/// A single file stored inside of a .kzip archive.
+///
+/// Duplicate entries point at the data of the file they duplicate, so
+/// `offset` and the lengths are always usable for reading the content.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The name for showing and looking up, with anything that isn't UTF-8
+    /// replaced. `os_name` is the name to create the file with.
+    pub name: String,
+    pub raw_name: Option<Vec<u8>>,
+    pub created_at: Option<u64>,
+    pub modified: u64,
+    pub unpacked_length: u64,
+    pub length: u64,
+    pub offset: u64,
+    /// Where the header of the entry starts.
+    pub header_offset: u64,
+    pub duplicate_of: Option<usize>,
+    pub codec: Codec,
+    /// The entry is a special file, read it with `read_special`.
+    pub special: bool,
+    /// The content can only be read with the password it was written with.
+    pub encrypted: bool,
+    /// Key/value pairs set with `kzip meta`.
+    pub meta: Vec<(String, String)>,
+}
+
+impl Entry {
+    pub fn is_duplicate(&self) -> bool {
+        self.duplicate_of.is_some()
+    }
+
+    pub fn os_name(&self) -> OsString {
+        match &self.raw_name {
+            Some(raw_name) => name_from_bytes(raw_name.to_vec()),
+            None => OsString::from(&self.name),
+        }
+    }
+
+    /// What the content of the entry is encrypted for, see
+    /// `encryption_header`.
+    #[cfg(feature = "encryption")]
+    fn encryption_header(&self) -> Vec<u8> {
+        let name = self.raw_name.as_deref().unwrap_or(self.name.as_bytes());
+        encryption_header(name, self.special, self.codec, self.unpacked_length)
+    }
+}
+
+/// The part of an entry's header encrypted content is tied to, so it can't
+/// be given to another entry: its name as it is read back, with `/` between
+/// directories on every system, whether it is special, the codec and how
+/// long it unpacks to. The dates and meta aren't, `kzip touch` and `kzip
+/// meta` change them without the password.
+#[cfg(feature = "encryption")]
+fn encryption_header(name: &[u8], special: bool, codec: Codec, unpacked_length: u64) -> Vec<u8> {
+    let mut header: Vec<u8> = name
+        .iter()
+        .map(|byte| if *byte == b'\\' { b'/' } else { *byte })
+        .collect();
+    header.push(special as u8);
+    let (id, level) = codec.id();
+    header.extend([id, level]);
+    header.extend(unpacked_length.to_be_bytes());
+    header
+}
+
+/// What is stored about a file apart from its content.
+pub struct Header<'a> {
+    pub name: &'a OsStr,
+    pub created_at: Option<u64>,
+    pub modified: u64,
+    pub meta: &'a [(String, String)],
+}
+
+/// How long reading or writing an archive took, by what was done.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    /// Reading the archive, or the files going into it.
+    pub read: Duration,
+    /// Hashing content to find duplicates.
+    pub hash: Duration,
+    /// Compressing, or decompressing when reading.
+    pub compress: Duration,
+    /// Writing the archive, or the files coming out of it.
+    pub write: Duration,
+}
+
+/// How far reading or writing an archive got, handed to the `on_progress`
+/// callbacks after every entry.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress<'a> {
+    /// The entry that was just read or added.
+    pub name: &'a str,
+    /// How many entries are done, and how many there are when that is
+    /// known. Writers know from the amount they were created with.
+    pub entries: u64,
+    pub total_entries: Option<u64>,
+    /// How many uncompressed bytes are done, and how many there are when
+    /// that is known, which is only the case for readers.
+    pub bytes: u64,
+    pub total_bytes: Option<u64>,
+}
+
+type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+/// The error reading or writing stops with once the flag given to
+/// `cancel_on` is set, as the inner error of an `io::Error`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Whether `err` is because of a cancellation.
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|err| err.is::<Cancelled>())
+    }
+
+    fn check(cancelled: &Option<Arc<AtomicBool>>) -> io::Result<()> {
+        match cancelled {
+            Some(cancelled) if cancelled.load(Ordering::Relaxed) => {
+                Err(io::Error::other(Cancelled))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// What `ArchiveReader::extract_with` does with an entry.
+#[cfg(feature = "reader")]
+pub enum Extract<'a> {
+    /// Leaves the entry out and goes on with the next one.
+    Skip,
+    /// Decompresses the content of the entry into the writer.
+    To(Box<dyn Write + 'a>),
+    /// Leaves this entry and every one after it out.
+    Stop,
+}
+
+/// Reads and lists archives.
+///
+/// Content is read at the offset of each entry without moving through the
+/// file, so `try_clone` gives handles that can read entries on other threads
+/// at the same time.
+#[cfg(feature = "reader")]
+pub struct ArchiveReader {
+    file: File,
+    pub entries: Vec<Entry>,
+    /// The version of kzip the archive was written with.
+    pub version: String,
+    /// Key/value pairs about the whole archive, i.e. the build it is from.
+    pub meta: Vec<(String, String)>,
+    /// For reading encrypted entries.
+    #[cfg(feature = "encryption")]
+    pub password: Option<Password>,
+    /// Things in the archive this version of kzip doesn't understand, but
+    /// that don't stop it from being read.
+    pub warnings: Vec<String>,
+    /// How long reading entries took so far.
+    pub timings: Timings,
+    on_progress: Option<ProgressCallback>,
+    // what was read so far and the size of every entry, for on_progress
+    read_entries: u64,
+    read_bytes: u64,
+    total_bytes: u64,
+    cancelled: Option<Arc<AtomicBool>>,
+    // names, and how they are composed in NFC, to the first entry with
+    // them, made the first time an entry is looked up for as many entries
+    index: OnceLock<(usize, HashMap<String, usize>)>,
+    // the SHA-256 of every entry when they come from `sidecar`
+    #[cfg(feature = "index")]
+    hashes: Vec<Option<[u8; 32]>>,
+}
+
+#[cfg(feature = "reader")]
+impl ArchiveReader {
+    /// Opens a .kzip file and reads every entry header, skipping over the
+    /// compressed data.
+    ///
+    /// The archive stays locked for reading until the reader is dropped, so
+    /// opening one that is still being written waits until it is finished.
+    pub fn open(input: &str) -> io::Result<ArchiveReader> {
+        ArchiveReader::open_with(input, false)
+    }
+
+    /// Like `open`, for archives that come from somewhere else. Names longer
+    /// than 4096 bytes or with `..` in them, more than 10 million entries,
+    /// more than 1024 meta pairs on an entry and flags kzip doesn't know are
+    /// errors instead of being read.
+    pub fn open_strict(input: &str) -> io::Result<ArchiveReader> {
+        ArchiveReader::open_with(input, true)
+    }
+
+    /// Like `open`, or `open_strict` when `strict`, with the entries from the
+    /// index next to the archive when it was written for the archive as it
+    /// is now, so only that is read. The archive is read as usual when there
+    /// is no such index or it can't be read.
+    #[cfg(feature = "index")]
+    pub fn open_indexed(input: &str, strict: bool) -> io::Result<ArchiveReader> {
+        let mut file = File::open(input)?;
+        file.lock_shared()?;
+        let index = match sidecar::read(input, &file, strict) {
+            Ok(Some(index)) => index,
+            _ => {
+                // checking the index reads from the archive
+                file.rewind()?;
+                return ArchiveReader::read_from(file, strict);
+            }
+        };
+
+        Ok(ArchiveReader {
+            file,
+            entries: index.entries,
+            version: index.version,
+            meta: index.meta,
+            #[cfg(feature = "encryption")]
+            password: None,
+            warnings: index.warnings,
+            timings: Timings::default(),
+            on_progress: None,
+            read_entries: 0,
+            read_bytes: 0,
+            total_bytes: 0,
+            cancelled: None,
+            index: OnceLock::new(),
+            hashes: index.hashes,
+        })
+    }
+
+    /// The entries of an archive without keeping it open, for listing it.
+    /// Only the headers are read and the content of every entry is skipped,
+    /// or nothing of the archive is when there is a fresh index next to it,
+    /// so this takes about as long for an archive of 100 GB as for one of
+    /// 100 KB with as many files.
+    pub fn headers(input: &str) -> io::Result<Vec<Entry>> {
+        #[cfg(feature = "index")]
+        let reader = ArchiveReader::open_indexed(input, false)?;
+        #[cfg(not(feature = "index"))]
+        let reader = ArchiveReader::open(input)?;
+        Ok(reader.entries)
+    }
+
+    /// The SHA-256 of the content of `entry` when the reader was opened
+    /// from an index, None otherwise and for encrypted entries.
+    #[cfg(feature = "index")]
+    pub fn hash(&self, entry: &Entry) -> Option<String> {
+        let position = self
+            .entries
+            .binary_search_by_key(&entry.header_offset, |entry| entry.header_offset)
+            .ok()?;
+        let hash = self.hashes.get(position)?.as_ref()?;
+        Some(hash.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    /// Like `open`, for changing the archive and writing it again: no other
+    /// kzip process can read or change it until the reader is dropped,
+    /// which fails instead of waiting when one has it open.
+    #[cfg(feature = "writer")]
+    pub fn open_exclusive(input: &str) -> io::Result<ArchiveReader> {
+        loop {
+            let file = OpenOptions::new().read(true).write(true).open(input)?;
+            lock(&file, input)?;
+            // whoever had it locked before can have replaced it in the
+            // meantime, this one would then be gone
+            if is_same_file(&file, input)? {
+                return ArchiveReader::read_from(file, false);
+            }
+        }
+    }
+
+    /// The archive file, locked like the reader was opened, i.e. for moving
+    /// entries inside of it after `open_exclusive`.
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    fn open_with(input: &str, strict: bool) -> io::Result<ArchiveReader> {
+        let file = File::open(input)?;
+        file.lock_shared()?;
+        ArchiveReader::read_from(file, strict)
+    }
+
+    fn read_from(file: File, strict: bool) -> io::Result<ArchiveReader> {
+        let file_length = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+        let mut mk = [0; 3];
+        file.read_exact(&mut mk)?;
+        if mk != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Invalid KZip header",
+            ));
+        }
+
+        // the archive's key/value pairs are lines after the version, which
+        // older versions of kzip read but don't look at
+        let version = read_string(&mut file)?;
+        let meta = version
+            .split('\n')
+            .skip(1)
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let version = version.split('\n').next().unwrap_or_default().to_string();
+        let nof = read_u32(&mut file)?;
+        let remaining = file_length.saturating_sub(file.stream_position()?);
+        if nof as u64 * MIN_ENTRY_LENGTH > remaining {
+            return Err(invalid(format!(
+                "the archive says it has {nof} files, more than fit in it"
+            )));
+        }
+        if strict && nof > STRICT_ENTRIES {
+            return Err(invalid(format!(
+                "the archive has {nof} files, more than the {STRICT_ENTRIES} allowed"
+            )));
+        }
+
+        let mut entries: Vec<Entry> = Vec::new();
+        // index of every non duplicate entry, this is what duplicates point to
+        let mut originals: Vec<usize> = Vec::new();
+        let mut warnings: Vec<String> = Vec::new();
+
+        for _ in 0..nof {
+            let header_offset = file.stream_position()?;
+            let flags = read_u8(&mut file)?;
+            let name_bytes = read_name(&mut file)?;
+            if strict && name_bytes.len() > STRICT_NAME_LENGTH {
+                return Err(invalid(format!(
+                    "an entry name is {} bytes long, more than the {STRICT_NAME_LENGTH} allowed",
+                    name_bytes.len()
+                )));
+            }
+            let (name, raw_name) = if flags & RAW_NAME != 0 {
+                let raw_name = parse_raw_file_path(name_bytes);
+                (
+                    String::from_utf8_lossy(&raw_name).to_string(),
+                    Some(raw_name),
+                )
+            } else {
+                let name = String::from_utf8(name_bytes)
+                    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                (parse_file_path(name), None)
+            };
+            if strict && !vfs::is_inside(&name) {
+                return Err(invalid(format!(
+                    "{name} leads outside of the directory the archive is extracted to"
+                )));
+            }
+            let created_at = if flags & NO_CREATED_AT == 0 {
+                Some(read_u64(&mut file)?)
+            } else {
+                None
+            };
+            let modified = read_u64(&mut file)?;
+            let mut meta = Vec::new();
+            if flags & META != 0 {
+                let pairs = read_u32(&mut file)?;
+                if strict && pairs > STRICT_META_PAIRS {
+                    return Err(invalid(format!(
+                        "{name} has {pairs} meta pairs, more than the {STRICT_META_PAIRS} allowed"
+                    )));
+                }
+                for _ in 0..pairs {
+                    meta.push((read_text(&mut file)?, read_text(&mut file)?));
+                }
+            }
+            if flags & !KNOWN_FLAGS != 0 {
+                let warning = format!(
+                    "{name} has flags this version of kzip doesn't know ({:#04x})",
+                    flags & !KNOWN_FLAGS
+                );
+                if strict {
+                    return Err(invalid(warning));
+                }
+                warnings.push(warning);
+            }
+
+            if flags & DUPLICATE != 0 {
+                let file_index = read_u32(&mut file)? as usize;
+                let original = match originals.get(file_index) {
+                    Some(original) => &entries[*original],
+                    None => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("{name} points to a file that does not exist"),
+                        ))
+                    }
+                };
+
+                entries.push(Entry {
+                    name,
+                    raw_name,
+                    created_at,
+                    modified,
+                    unpacked_length: original.unpacked_length,
+                    length: original.length,
+                    offset: original.offset,
+                    header_offset,
+                    duplicate_of: Some(originals[file_index]),
+                    codec: original.codec,
+                    special: original.special,
+                    encrypted: original.encrypted,
+                    meta,
+                });
+            } else {
+                let codec = if flags & CODEC != 0 {
+                    let id = read_u8(&mut file)?;
+                    let level = read_u8(&mut file)?;
+                    Codec::from_id(id, level).ok_or_else(|| {
+                        io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("{name} is compressed with a codec kzip doesn't know ({id})"),
+                        )
+                    })?
+                } else {
+                    Codec::default()
+                };
+                let unpacked_length = read_u64(&mut file)?;
+                let length = read_u64(&mut file)?;
+                let offset = file.stream_position()?;
+                // lengths that point past the end of the file or that the
+                // codec can't unpack to are errors either way, they would
+                // have kzip allocate or seek whatever the archive says
+                if length > file_length - offset {
+                    return Err(invalid(format!(
+                        "{name} is {length} bytes long, more than is left of the archive"
+                    )));
+                }
+                if !codec.can_unpack(length, unpacked_length) {
+                    return Err(invalid(format!(
+                        "{name} can't unpack from {length} to {unpacked_length} bytes with {}",
+                        codec.name()
+                    )));
+                }
+                if flags & ENCRYPTED != 0 && length < ENCRYPTION_OVERHEAD {
+                    return Err(invalid(format!(
+                        "{name} is {length} bytes long, too short to be encrypted"
+                    )));
+                }
+                file.seek_relative(length as i64)?;
+
+                originals.push(entries.len());
+                entries.push(Entry {
+                    name,
+                    raw_name,
+                    created_at,
+                    modified,
+                    unpacked_length,
+                    length,
+                    offset,
+                    header_offset,
+                    duplicate_of: None,
+                    codec,
+                    special: flags & SPECIAL != 0,
+                    encrypted: flags & ENCRYPTED != 0,
+                    meta,
+                });
+            }
+        }
+
+        Ok(ArchiveReader {
+            file: file.into_inner(),
+            entries,
+            version,
+            meta,
+            #[cfg(feature = "encryption")]
+            password: None,
+            warnings,
+            timings: Timings::default(),
+            on_progress: None,
+            read_entries: 0,
+            read_bytes: 0,
+            total_bytes: 0,
+            cancelled: None,
+            index: OnceLock::new(),
+            #[cfg(feature = "index")]
+            hashes: Vec::new(),
+        })
+    }
+
+    /// Has `callback` called after every entry that is read or extracted.
+    pub fn on_progress(&mut self, callback: impl FnMut(&Progress) + Send + 'static) {
+        self.total_bytes = self.entries.iter().map(|entry| entry.unpacked_length).sum();
+        self.on_progress = Some(Box::new(callback));
+    }
+
+    /// Has `extract_with` stop with `Cancelled` once `cancelled` is set,
+    /// between entries and while their content is written.
+    pub fn cancel_on(&mut self, cancelled: Arc<AtomicBool>) {
+        self.cancelled = Some(cancelled);
+    }
+
+    fn report(&mut self, entry: &Entry) {
+        self.read_entries += 1;
+        self.read_bytes += entry.unpacked_length;
+        if let Some(callback) = self.on_progress.as_mut() {
+            callback(&Progress {
+                name: &entry.name,
+                entries: self.read_entries,
+                total_entries: Some(self.entries.len() as u64),
+                bytes: self.read_bytes,
+                total_bytes: Some(self.total_bytes),
+            });
+        }
+    }
+
+    /// The first entry named `name`, or else the first one whose name is
+    /// only composed differently, i.e. typed on Linux but stored on macOS.
+    /// The names are hashed the first time, so looking up is instant after
+    /// that however many entries there are.
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        let (count, index) = self.index.get_or_init(|| {
+            let mut index = HashMap::with_capacity(self.entries.len());
+            for (i, entry) in self.entries.iter().enumerate() {
+                index.entry(entry.name.clone()).or_insert(i);
+            }
+            // after every name, which goes first
+            for (i, entry) in self.entries.iter().enumerate() {
+                index.entry(nfc(&entry.name)).or_insert(i);
+            }
+            (self.entries.len(), index)
+        });
+        // `entries` is public, if it changed since the names were hashed
+        // they are looked at one by one
+        if *count != self.entries.len() {
+            return self.scan(name);
+        }
+
+        if let Some(entry) = index.get(name).and_then(|i| self.entries.get(*i)) {
+            if entry.name == name {
+                return Some(entry);
+            }
+        }
+        let composed = nfc(name);
+        let i = index.get(&composed)?;
+        match self.entries.get(*i) {
+            Some(entry) if nfc(&entry.name) == composed => Some(entry),
+            _ => self.scan(name),
+        }
+    }
+
+    fn scan(&self, name: &str) -> Option<&Entry> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.name == name) {
+            return Some(entry);
+        }
+
+        let name = nfc(name);
+        self.entries.iter().find(|entry| nfc(&entry.name) == name)
+    }
+
+    /// Reads and decompresses the content of an entry.
+    pub fn read(&mut self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let bytes = self.read_decrypted(entry)?;
+
+        let started = Instant::now();
+        let content = entry.codec.decompress(&bytes, entry.unpacked_length)?;
+        self.timings.compress += started.elapsed();
+        self.report(entry);
+        Ok(content)
+    }
+
+    /// Reads the compressed content of an entry, decrypting it if needed.
+    fn read_decrypted(&mut self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let bytes = self.read_raw(entry)?;
+        if !entry.encrypted {
+            return Ok(bytes);
+        }
+
+        #[cfg(feature = "encryption")]
+        return match self.password.as_mut() {
+            Some(password) => password
+                .decrypt(&bytes, &entry.encryption_header())
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", entry.name))),
+            None => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is encrypted and there is no password", entry.name),
+            )),
+        };
+        #[cfg(not(feature = "encryption"))]
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} is encrypted, kzip was built without encryption",
+                entry.name
+            ),
+        ))
+    }
+
+    /// Reads the content of an entry as it is stored, still compressed.
+    pub fn read_raw(&mut self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let started = Instant::now();
+        let mut bytes = vec![0; entry.length as usize];
+        At::new(&self.file, entry).read_exact(&mut bytes)?;
+        self.timings.read += started.elapsed();
+
+        Ok(bytes)
+    }
+
+    /// Like `read_raw`, for storing the entry as `name`. Encrypted content
+    /// is tied to the name of its entry, so for another name it is
+    /// encrypted again, which needs the password.
+    pub fn read_raw_as(&mut self, entry: &Entry, name: &OsStr) -> io::Result<Vec<u8>> {
+        let bound_name = parse_raw_file_path(name_to_bytes(name).into_owned());
+        let renamed = entry.raw_name.as_deref().unwrap_or(entry.name.as_bytes()) != bound_name;
+        if !entry.encrypted || !renamed {
+            return self.read_raw(entry);
+        }
+
+        #[cfg(feature = "encryption")]
+        {
+            if self.password.is_none() {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!(
+                        "{} is encrypted, renaming it needs the password",
+                        entry.name
+                    ),
+                ));
+            }
+            let content = self.read_decrypted(entry)?;
+            let header = encryption_header(
+                &bound_name,
+                entry.special,
+                entry.codec,
+                entry.unpacked_length,
+            );
+            let password = self.password.as_mut().unwrap();
+            password.encrypt(&content, &header)
+        }
+        #[cfg(not(feature = "encryption"))]
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} is encrypted, kzip was built without encryption",
+                entry.name
+            ),
+        ))
+    }
+
+    /// Reads the node a special entry stands for.
+    pub fn read_special(&mut self, entry: &Entry) -> io::Result<Special> {
+        Special::from_bytes(&self.read(entry)?)
+    }
+
+    /// Returns a reader that decompresses an entry as it is read, so large
+    /// files never have to fit into memory.
+    pub fn stream(&mut self, entry: &Entry) -> io::Result<Box<dyn Read + '_>> {
+        if entry.encrypted {
+            // the tag at the end has to be checked before anything is used
+            let bytes = self.read_decrypted(entry)?;
+            return Ok(entry.codec.decoder(io::Cursor::new(bytes)));
+        }
+        Ok(entry
+            .codec
+            .decoder(BufReader::new(At::new(&self.file, entry))))
+    }
+
+    /// Another handle to the same archive with its own timings, for reading
+    /// entries on another thread. The password and `on_progress` aren't
+    /// carried over, `cancel_on` is.
+    pub fn try_clone(&self) -> io::Result<ArchiveReader> {
+        Ok(ArchiveReader {
+            file: self.file.try_clone()?,
+            entries: self.entries.clone(),
+            version: self.version.clone(),
+            meta: self.meta.clone(),
+            #[cfg(feature = "encryption")]
+            password: None,
+            warnings: self.warnings.clone(),
+            timings: Timings::default(),
+            on_progress: None,
+            read_entries: 0,
+            read_bytes: 0,
+            total_bytes: 0,
+            cancelled: self.cancelled.clone(),
+            index: self.index.clone(),
+            #[cfg(feature = "index")]
+            hashes: self.hashes.clone(),
+        })
+    }
+
+    /// Goes through the entries in order and lets `visit` decide where the
+    /// content of each goes, instead of to files like `kzip -x` does. The
+    /// content of special entries is the node `read_special` reads, check
+    /// `entry.special` to handle them. An error from `visit` stops it.
+    ///
+    /// Returns how many entries were written. Writing only README.md to
+    /// stdout is `Extract::To(Box::new(io::stdout()))` for it and
+    /// `Extract::Skip` for everything else.
+    pub fn extract_with<'a>(
+        &mut self,
+        mut visit: impl FnMut(&Entry) -> io::Result<Extract<'a>>,
+    ) -> io::Result<usize> {
+        let mut written = 0;
+        for entry in self.entries.clone() {
+            Cancelled::check(&self.cancelled)?;
+            let mut output = match visit(&entry)? {
+                Extract::Skip => continue,
+                Extract::To(output) => output,
+                Extract::Stop => break,
+            };
+
+            let cancelled = self.cancelled.clone();
+            let mut content = self.stream(&entry)?;
+            let mut buffer = vec![0; 64 * 1024];
+            let mut length = 0;
+            loop {
+                Cancelled::check(&cancelled)?;
+                let read = match content.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                };
+                output.write_all(&buffer[..read])?;
+                length += read as u64;
+            }
+            drop(content);
+            if length != entry.unpacked_length {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} is {length} bytes instead of {}",
+                        entry.name, entry.unpacked_length
+                    ),
+                ));
+            }
+            output.flush()?;
+            self.report(&entry);
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Extracts every entry into `root` of `vfs` the way `options` says,
+    /// leaving out special ones and anything in their names that would lead
+    /// out of `root`. Returns how many were written.
+    pub fn extract_into(
+        &mut self,
+        vfs: &mut dyn Vfs,
+        root: &Path,
+        options: &ExtractOptions,
+    ) -> io::Result<usize> {
+        let mut written = 0;
+        for entry in self.entries.clone() {
+            Cancelled::check(&self.cancelled)?;
+            if entry.special {
+                continue;
+            }
+            let path = match vfs::entry_path(root, &entry.name, options.strip_components) {
+                Some(path) => path,
+                None => continue,
+            };
+            if !options.overwrite && vfs.metadata(&path).is_ok() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is already there", path.display()),
+                ));
+            }
+
+            let content = self.read(&entry)?;
+            vfs.write_file(&path, &content)?;
+            if options.preserve_times {
+                vfs.set_modified(&path, entry.modified)?;
+            }
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+/// The stored content of an entry, read at its offset without moving the
+/// position of the file, which handles from `try_clone` share.
+#[cfg(feature = "reader")]
+struct At<'a> {
+    file: &'a File,
+    offset: u64,
+    remaining: u64,
+}
+
+#[cfg(feature = "reader")]
+impl At<'_> {
+    fn new<'a>(file: &'a File, entry: &Entry) -> At<'a> {
+        At {
+            file,
+            offset: entry.offset,
+            remaining: entry.length,
+        }
+    }
+}
+
+#[cfg(feature = "reader")]
+impl Read for At<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.remaining as usize);
+        if length == 0 {
+            return Ok(0);
+        }
+        let read = read_at(self.file, &mut buf[..length], self.offset)?;
+        self.offset += read as u64;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+// without positional reads the handles can only be used one at a time
+#[cfg(not(any(unix, windows)))]
+fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// The content of a file the way it is stored.
+#[cfg(feature = "writer")]
+pub struct Compressed<'a> {
+    /// Tells contents apart for finding duplicates, the hash of the
+    /// uncompressed content, or see `copy`.
+    pub hash: String,
+    pub unpacked_length: u64,
+    pub codec: Codec,
+    pub data: &'a [u8],
+    /// The content is a special file, these are never stored as duplicates.
+    pub special: bool,
+    /// `data` is encrypted, the hash is then only matched against other
+    /// encrypted entries.
+    pub encrypted: bool,
+}
+
+#[cfg(feature = "writer")]
+impl Compressed<'_> {
+    /// The content of an entry as it is stored in another archive, to copy
+    /// it over without compressing or even decompressing it. The same
+    /// stored bytes with the same codec are still the same content, so
+    /// copies are found as duplicates of each other, though not of the same
+    /// content added uncompressed or stored with another codec.
+    pub fn copy<'a>(entry: &Entry, data: &'a [u8]) -> io::Result<Compressed<'a>> {
+        // a content hash is only hex, so this never is one
+        let (id, level) = entry.codec.id();
+        let hash = format!("stored {id} {level} {}", digest(data));
+
+        Ok(Compressed {
+            hash,
+            unpacked_length: entry.unpacked_length,
+            codec: entry.codec,
+            data,
+            special: entry.special,
+            encrypted: entry.encrypted,
+        })
+    }
+}
+
+/// Where and how a file ended up in the archive.
+#[cfg(feature = "writer")]
+pub struct Added {
+    /// Where the entry starts in the archive.
+    pub offset: u64,
+    /// The compressed length, 0 for duplicates.
+    pub length: u64,
+    /// The index of the file with the same content, if there was one.
+    pub duplicate_of: Option<usize>,
+}
+
+#[cfg(feature = "writer")]
+pub struct ArchiveWriter {
+    file: File,
+    buffer: ByteBuffer,
+    // how many bytes were written to the file so far
+    position: u64,
+    count: u32,
+    // where the amount of files is in the header
+    count_offset: u64,
+    // SOURCE_DATE_EPOCH, no stored time is later than it
+    clamp_time: Option<u64>,
+    // content hash -> index of the file that has it, for finding duplicates
+    hashes: HashMap<String, usize>,
+    /// How long adding files took so far, reading them is up to the caller.
+    pub timings: Timings,
+    // the amount of files the archive was created with, and the bytes
+    // added so far, for on_progress
+    total: Option<u32>,
+    added_bytes: u64,
+    on_progress: Option<ProgressCallback>,
+    // the archive, removed when adding is cancelled
+    path: String,
+    cancelled: Option<Arc<AtomicBool>>,
+    options: CreateOptions,
+}
+
+#[cfg(feature = "writer")]
+impl ArchiveWriter {
+    /// Creates a .kzip file and writes its header, `nof` being the amount
+    /// of files that are going to be added. `finish` corrects the amount if
+    /// fewer files ended up being added.
+    ///
+    /// Fails if another kzip process is reading or writing the archive.
+    pub fn create(output: &str, nof: u32) -> io::Result<ArchiveWriter> {
+        ArchiveWriter::create_with(output, nof, &CreateOptions::default())
+    }
+
+    /// Like `create`, with the version, meta and how files are added from
+    /// `options`.
+    pub fn create_with(
+        output: &str,
+        nof: u32,
+        options: &CreateOptions,
+    ) -> io::Result<ArchiveWriter> {
+        let mut version = options.version.clone();
+        for (key, value) in &options.meta {
+            if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{key}={value} can't be stored, keys can't have = and neither can have new lines"),
+                ));
+            }
+            version.push_str(&format!("\n{key}={value}"));
+        }
+        let clamp_time = source_date_epoch()?;
+        // only emptied once it is locked, in case someone else has it open
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(output)?;
+        lock(&file, output)?;
+        file.set_len(0)?;
+        let mut writer = ArchiveWriter {
+            file,
+            buffer: ByteBuffer::new(),
+            position: 0,
+            count: 0,
+            count_offset: (MAGIC.len() + 4 + version.len()) as u64,
+            clamp_time,
+            hashes: HashMap::new(),
+            timings: Timings::default(),
+            total: Some(nof),
+            added_bytes: 0,
+            on_progress: None,
+            path: output.to_string(),
+            cancelled: None,
+            options: options.clone(),
+        };
+
+        writer.buffer.write_bytes(&MAGIC);
+        writer.buffer.write_string(&version);
+        // the amount of files comes right after the magic number and version
+        writer.buffer.write_u32(nof); // amount of files
+        writer.flush_buffer()?;
+
+        Ok(writer)
+    }
+
+    /// Opens an archive whose creation was interrupted to add the rest of the
+    /// files to it. The first `count` entries, which end `length` bytes in,
+    /// are kept and read back to check them and to find duplicates of,
+    /// anything after them is cut off. Returns the entries that were kept.
+    #[cfg(feature = "reader")]
+    pub fn resume(
+        output: &str,
+        count: u32,
+        length: u64,
+    ) -> io::Result<(ArchiveWriter, Vec<Entry>)> {
+        let clamp_time = source_date_epoch()?;
+        let mut file = OpenOptions::new().read(true).write(true).open(output)?;
+        lock(&file, output)?;
+        if file.metadata()?.len() < length {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{output} is shorter than its checkpoint says, it was changed since"),
+            ));
+        }
+        file.set_len(length)?;
+
+        // the header says how many files there were going to be, until
+        // `finish` it has to say how many there are
+        let mut header = BufReader::new(&file);
+        let mut mk = [0; 3];
+        header.read_exact(&mut mk)?;
+        if mk != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Invalid KZip header",
+            ));
+        }
+        let count_offset = (MAGIC.len() + 4 + read_string(&mut header)?.len()) as u64;
+        file.seek(SeekFrom::Start(count_offset))?;
+        file.write_all(&count.to_be_bytes())?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = ArchiveReader::read_from(file.try_clone()?, false)?;
+        let mut hashes = HashMap::new();
+        for entry in reader.entries.clone() {
+            if entry.is_duplicate() {
+                continue;
+            }
+            let key = if entry.special {
+                format!("special {}", hashes.len())
+            } else if entry.encrypted {
+                // without the password only the length can be checked, and
+                // nothing can be found to be the same as it
+                reader.read_raw(&entry)?;
+                format!("encrypted entry {}", hashes.len())
+            } else {
+                digest(reader.read(&entry)?)
+            };
+            hashes.insert(key, hashes.len());
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let writer = ArchiveWriter {
+            file,
+            buffer: ByteBuffer::new(),
+            position: length,
+            count,
+            count_offset,
+            clamp_time,
+            hashes,
+            timings: Timings::default(),
+            // how many files there were going to be isn't kept
+            total: None,
+            added_bytes: 0,
+            on_progress: None,
+            path: output.to_string(),
+            cancelled: None,
+            options: CreateOptions::default(),
+        };
+        Ok((writer, reader.entries))
+    }
+
+    /// Copies the entry `name` of another archive with its dates and meta,
+    /// as it is stored, so nothing is decompressed or compressed again.
+    /// Encrypted entries stay readable with the password they were written
+    /// with.
+    #[cfg(feature = "reader")]
+    pub fn copy_entry(&mut self, reader: &mut ArchiveReader, name: &str) -> io::Result<Added> {
+        let entry = match reader.get(name) {
+            Some(entry) => entry.clone(),
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{name} isn't in the archive"),
+                ))
+            }
+        };
+        let compressed = reader.read_raw(&entry)?;
+        self.add_compressed(
+            &Header {
+                name: &entry.os_name(),
+                created_at: entry.created_at,
+                modified: entry.modified,
+                meta: &entry.meta,
+            },
+            Compressed::copy(&entry, &compressed)?,
+        )
+    }
+
+    /// Adds a file, compressing it unless a file with the same content was
+    /// already added, in which case only a pointer to that file is stored.
+    pub fn add(&mut self, header: &Header, content: &[u8], codec: Codec) -> io::Result<Added> {
+        self.check_cancelled()?;
+        let hash = self.hash(content);
+        if self.options.dedupe && self.hashes.contains_key(&hash) {
+            return self.add_duplicate(header, &hash, content.len() as u64);
+        }
+
+        let started = Instant::now();
+        let encoded_content = codec.compress(content)?;
+        self.timings.compress += started.elapsed();
+        self.add_compressed(
+            header,
+            Compressed {
+                hash,
+                unpacked_length: content.len() as u64,
+                codec,
+                data: &encoded_content,
+                special: false,
+                encrypted: false,
+            },
+        )
+    }
+
+    /// Adds a file that can only be read with the password.
+    #[cfg(feature = "encryption")]
+    pub fn add_encrypted(
+        &mut self,
+        header: &Header,
+        content: &[u8],
+        codec: Codec,
+        password: &mut Password,
+    ) -> io::Result<Added> {
+        self.check_cancelled()?;
+        // never stored as a duplicate, that would give away which encrypted
+        // files have the same content
+        let hash = self.hash(content);
+        let name = parse_raw_file_path(name_to_bytes(header.name).into_owned());
+        let encryption_header = encryption_header(&name, false, codec, content.len() as u64);
+
+        let started = Instant::now();
+        let encrypted = password.encrypt(&codec.compress(content)?, &encryption_header);
+        self.timings.compress += started.elapsed();
+        let encrypted = encrypted?;
+        self.add_compressed(
+            header,
+            Compressed {
+                hash,
+                unpacked_length: content.len() as u64,
+                codec,
+                data: &encrypted,
+                special: false,
+                encrypted: true,
+            },
+        )
+    }
+
+    /// Adds a fifo, socket or device node.
+    pub fn add_special(&mut self, header: &Header, special: Special) -> io::Result<Added> {
+        let content = special.to_bytes();
+        self.add_compressed(
+            header,
+            Compressed {
+                hash: digest(&content),
+                unpacked_length: content.len() as u64,
+                codec: Codec::Store,
+                data: &content,
+                special: true,
+                encrypted: false,
+            },
+        )
+    }
+
+    /// Adds a file that is already compressed, i.e. copied out of another
+    /// archive.
+    pub fn add_compressed(&mut self, header: &Header, compressed: Compressed) -> io::Result<Added> {
+        self.check_cancelled()?;
+        let Compressed {
+            hash,
+            unpacked_length,
+            codec,
+            data: compressed,
+            special,
+            encrypted,
+        } = compressed;
+        // encrypted content is tied to the entry it was written for, and
+        // sharing it would give away which files are the same
+        if self.options.dedupe && !special && !encrypted && self.hashes.contains_key(&hash) {
+            return self.add_duplicate(header, &hash, unpacked_length);
+        }
+
+        let offset = self.position;
+        let flags = if special { SPECIAL } else { 0 };
+        let flags = if encrypted { flags | ENCRYPTED } else { flags };
+        if codec == Codec::default() {
+            self.write_header(flags, header)?;
+        } else {
+            // only written when needed, so archives stay readable by older versions
+            self.write_header(flags | CODEC, header)?;
+            let (id, level) = codec.id();
+            self.buffer.write_u8(id);
+            self.buffer.write_u8(level);
+        }
+        self.buffer.write_u64(unpacked_length);
+        self.buffer.write_u64(compressed.len() as u64);
+        self.buffer.write_bytes(compressed);
+        // duplicates point at the n-th stored entry, so special and
+        // encrypted ones need a place here as well, under a key no content
+        // hash can have
+        // the same goes for content stored again without deduping
+        let key = if special || encrypted || self.hashes.contains_key(&hash) {
+            format!("special {}", self.hashes.len())
+        } else {
+            hash
+        };
+        self.hashes.insert(key, self.hashes.len());
+        self.count += 1;
+        self.flush_buffer()?;
+        self.report(header, unpacked_length);
+
+        Ok(Added {
+            offset,
+            length: compressed.len() as u64,
+            duplicate_of: None,
+        })
+    }
+
+    fn add_duplicate(
+        &mut self,
+        header: &Header,
+        hash: &str,
+        unpacked_length: u64,
+    ) -> io::Result<Added> {
+        // there is a duplicate file found
+        // going to tell kzip this to save some space
+        let offset = self.position;
+        self.write_header(DUPLICATE, header)?;
+        self.buffer.write_u32(self.hashes[hash] as u32);
+        self.count += 1;
+        self.flush_buffer()?;
+        self.report(header, unpacked_length);
+
+        Ok(Added {
+            offset,
+            length: 0,
+            duplicate_of: Some(self.hashes[hash]),
+        })
+    }
+
+    /// Adds every file below `root` of `vfs`, named by their path inside it
+    /// with `/` between directories, in the order of their names, with the
+    /// codec and excludes the writer was created with. Returns how many were
+    /// added, which `vfs::count_files` knows beforehand for `create_with`.
+    pub fn add_tree(&mut self, vfs: &dyn Vfs, root: &Path) -> io::Result<u32> {
+        let mut added = 0;
+        let excludes = self.options.excludes.clone();
+        vfs::walk(vfs, root, &excludes, |path, name, metadata| {
+            let content = vfs.read_file(path)?;
+            let header = Header {
+                name: OsStr::new(name),
+                created_at: metadata.created_at,
+                modified: metadata.modified,
+                meta: &[],
+            };
+            self.add(&header, &content, self.options.codec)?;
+            added += 1;
+            Ok(())
+        })?;
+        Ok(added)
+    }
+
+    /// Has adding files fail with `Cancelled` once `cancelled` is set,
+    /// removing the unfinished archive. Compressing a file isn't stopped
+    /// halfway, it is checked before and after.
+    pub fn cancel_on(&mut self, cancelled: Arc<AtomicBool>) {
+        self.cancelled = Some(cancelled);
+    }
+
+    fn check_cancelled(&mut self) -> io::Result<()> {
+        let result = Cancelled::check(&self.cancelled);
+        if result.is_err() {
+            // nothing can be added anymore, so what is there is of no use
+            let _ = self.file.set_len(0);
+            let _ = fs::remove_file(&self.path);
+        }
+        result
+    }
+
+    /// Has `callback` called after every file that is added.
+    pub fn on_progress(&mut self, callback: impl FnMut(&Progress) + Send + 'static) {
+        self.on_progress = Some(Box::new(callback));
+    }
+
+    fn report(&mut self, header: &Header, unpacked_length: u64) {
+        self.added_bytes += unpacked_length;
+        if let Some(callback) = self.on_progress.as_mut() {
+            callback(&Progress {
+                name: &header.name.to_string_lossy(),
+                entries: self.count as u64,
+                total_entries: self.total.map(u64::from),
+                bytes: self.added_bytes,
+                total_bytes: None,
+            });
+        }
+    }
+
+    fn write_header(&mut self, flags: u8, header: &Header) -> io::Result<()> {
+        let name_bytes = name_to_bytes(header.name);
+        let flags = match str::from_utf8(&name_bytes) {
+            Ok(_) => flags,
+            Err(_) => flags | RAW_NAME,
+        };
+        // only written when needed, so archives stay readable by older versions
+        let flags = if header.meta.is_empty() {
+            flags
+        } else {
+            flags | META
+        };
+        if name_bytes.len() > MAX_NAME_LENGTH {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("the name is longer than {MAX_NAME_LENGTH} bytes"),
+            ));
+        }
+
+        let clamp = |time: u64| {
+            self.clamp_time
+                .map_or(time, |clamp_time| time.min(clamp_time))
+        };
+        let created_at = header.created_at.map(clamp);
+        let modified = clamp(header.modified);
+
+        match created_at {
+            Some(created_at) => {
+                self.buffer.write_u8(flags);
+                self.write_name(&name_bytes);
+                self.buffer.write_u64(created_at);
+            }
+            None => {
+                // not every filesystem knows when a file was created
+                self.buffer.write_u8(flags | NO_CREATED_AT);
+                self.write_name(&name_bytes);
+            }
+        }
+        self.buffer.write_u64(modified);
+        if !header.meta.is_empty() {
+            self.buffer.write_u32(header.meta.len() as u32);
+            for (key, value) in header.meta {
+                self.write_name(key.as_bytes());
+                self.write_name(value.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_name(&mut self, name: &[u8]) {
+        // same layout as write_string, so UTF-8 names read like they always did
+        self.buffer.write_u32(name.len() as u32);
+        self.buffer.write_bytes(name);
+    }
+
+    /// The amount of files added so far.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// How many bytes of the archive were written so far, every entry added
+    /// is whole within them.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Waits until what was written so far is on disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Writes the amount of files that were actually added into the header.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.count_offset))?;
+        self.file.write_all(&self.count.to_be_bytes())?;
+        self.file.flush()
+    }
+
+    /// Like `finish`, but only returns once the whole archive is on disk.
+    pub fn finish_synced(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.count_offset))?;
+        self.file.write_all(&self.count.to_be_bytes())?;
+        self.file.sync_all()
+    }
+
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        let started = Instant::now();
+        self.file.write_all(self.buffer.as_bytes())?;
+        self.timings.write += started.elapsed();
+        self.position += self.buffer.len() as u64;
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    fn hash(&mut self, content: &[u8]) -> String {
+        let started = Instant::now();
+        let hash = digest(content);
+        self.timings.hash += started.elapsed();
+        hash
+    }
+}
+
+/// Locks an archive that is about to be written, failing instead of waiting
+/// when another kzip process has it open.
+#[cfg(feature = "writer")]
+fn lock(file: &File, output: &str) -> io::Result<()> {
+    file.try_lock().map_err(|err| match err {
+        TryLockError::WouldBlock => io::Error::new(
+            ErrorKind::WouldBlock,
+            format!("{output} is being used by another kzip process"),
+        ),
+        TryLockError::Error(err) => err,
+    })
+}
+
+/// Whether `path` still is the file that was opened as `file`.
+#[cfg(all(feature = "writer", unix))]
+fn is_same_file(file: &File, path: &str) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (opened, named) = (file.metadata()?, fs::metadata(path)?);
+    Ok(opened.dev() == named.dev() && opened.ino() == named.ino())
+}
+
+// files that are open can't be replaced on windows
+#[cfg(all(feature = "writer", not(unix)))]
+fn is_same_file(_file: &File, _path: &str) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Reads SOURCE_DATE_EPOCH, which reproducible builds set to the time of the
+/// last change to their sources, see https://reproducible-builds.org/specs/source-date-epoch/
+fn source_date_epoch() -> io::Result<Option<u64>> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) if epoch.is_empty() => Ok(None),
+        Ok(epoch) => epoch.parse().map(Some).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("SOURCE_DATE_EPOCH is {epoch}, it has to be a number of seconds"),
+            )
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Turns a stored name into a relative path using the platform separator,
+/// dropping any leading `../` and `./`.
+pub fn parse_file_path(mut path: String) -> String {
+    path = path.replace('/', path::MAIN_SEPARATOR_STR);
+    path = path.replace('\\', path::MAIN_SEPARATOR_STR);
+    if path.starts_with(&format!("..{}", path::MAIN_SEPARATOR)) {
+        path = path.replace(&format!("..{}", path::MAIN_SEPARATOR), "");
+    }
+
+    if path.starts_with(&format!(".{}", path::MAIN_SEPARATOR)) {
+        path = path.replace(&format!(".{}", path::MAIN_SEPARATOR), "");
+    }
+
+    path
+}
+
+/// `parse_file_path` for a name that is not UTF-8. Every byte is turned into
+/// the char with the same value and back, which leaves the bytes that aren't
+/// separators or dots untouched.
+fn parse_raw_file_path(path: Vec<u8>) -> Vec<u8> {
+    let path: String = path.into_iter().map(char::from).collect();
+    parse_file_path(path).chars().map(|c| c as u8).collect()
+}
+
+#[cfg(unix)]
+fn name_to_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(name.as_bytes())
+}
+
+// other systems only get names that can be shown, which is every name on windows
+// apart from broken UTF-16
+#[cfg(not(unix))]
+fn name_to_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    match name.to_string_lossy() {
+        Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+        Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+    }
+}
+
+#[cfg(unix)]
+fn name_from_bytes(name: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+
+    OsString::from_vec(name)
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(name: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&name).to_string())
+}
+
+pub(crate) fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+pub(crate) fn read_name(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let length = read_u32(reader)?;
+    if length as usize > MAX_NAME_LENGTH {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "an entry name is {length} bytes long, more than the {MAX_NAME_LENGTH} allowed"
+            ),
+        ));
+    }
+
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// A meta key or value, which can't be longer than a name.
+fn read_text(reader: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_name(reader)?).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+pub(crate) fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(reader)?;
+    // read as it comes rather than allocating the length up front
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length as usize {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    String::from_utf8(bytes).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+#[cfg(all(test, feature = "reader", feature = "writer"))]
+mod tests {
+    use super::*;
+    use crate::vfs::{MemoryFs, OsFs};
+    use std::path::PathBuf;
+
+    // a path in a directory of its own for every test run
+    fn temp_path(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().to_string()
+    }
+
+    fn header(name: &str) -> Header<'_> {
+        Header {
+            name: OsStr::new(name),
+            created_at: None,
+            modified: 1_700_000_000,
+            meta: &[],
+        }
+    }
+
+    #[test]
+    fn strict_reading_rejects_names_that_lead_outside() {
+        let path = temp_path("outside.kzip");
+        let mut writer = ArchiveWriter::create(&path, 2).unwrap();
+        writer.add(&header("a"), b"a", Codec::Store).unwrap();
+        writer
+            .add(&header("a/../../x"), b"x", Codec::Store)
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(ArchiveReader::open(&path).unwrap().entries.len(), 2);
+        let err = ArchiveReader::open_strict(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_names_up_to_the_longest_allowed() {
+        let path = temp_path("long-names.kzip");
+        let longest = "n/".repeat(MAX_NAME_LENGTH / 2 - 1) + "nn";
+        let too_long = longest.clone() + "n";
+        assert_eq!(longest.len(), MAX_NAME_LENGTH);
+
+        let mut writer = ArchiveWriter::create(&path, 1).unwrap();
+        writer
+            .add(&header(&longest), b"long", Codec::Store)
+            .unwrap();
+        let err = writer
+            .add(&header(&too_long), b"too long", Codec::Store)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.entries.len(), 1);
+        let mut vfs = MemoryFs::new();
+        let root = Path::new("out");
+        assert_eq!(
+            reader
+                .extract_into(&mut vfs, root, &ExtractOptions::default())
+                .unwrap(),
+            1
+        );
+        let extracted = vfs::entry_path(root, &longest, 0).unwrap();
+        assert_eq!(vfs.files[&extracted], b"long");
+
+        // more than a strict reader takes
+        assert!(ArchiveReader::open_strict(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn adds_and_extracts_deep_trees() {
+        let path = temp_path("deep.kzip");
+        let deep = Path::new("in").join("d/".repeat(2000)).join("f");
+        let mut vfs = MemoryFs::new();
+        vfs.write_file(&deep, b"deep").unwrap();
+        vfs.write_file(Path::new("in/g"), b"shallow").unwrap();
+
+        let root = Path::new("in");
+        assert_eq!(vfs::count_files(&vfs, root, &[]).unwrap(), 2);
+        let mut writer = ArchiveWriter::create(&path, 2).unwrap();
+        assert_eq!(writer.add_tree(&vfs, root).unwrap(), 2);
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let mut out = MemoryFs::new();
+        reader
+            .extract_into(&mut out, Path::new("out"), &ExtractOptions::default())
+            .unwrap();
+        let extracted = Path::new("out").join(deep.strip_prefix(root).unwrap());
+        assert_eq!(out.files[&extracted], b"deep");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_at_symlinks_that_loop() {
+        let root = PathBuf::from(temp_path("loop"));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/f"), b"f").unwrap();
+        std::os::unix::fs::symlink("../..", root.join("a/b/up")).unwrap();
+
+        assert_eq!(vfs::count_files(&OsFs, &root, &[]).unwrap(), 1);
+        let path = temp_path("loop.kzip");
+        let mut writer = ArchiveWriter::create(&path, 1).unwrap();
+        assert_eq!(writer.add_tree(&OsFs, &root).unwrap(), 1);
+        writer.finish().unwrap();
+
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_codec_of_every_entry() {
+        let path = temp_path("codecs.kzip");
+        let content = b"compressible ".repeat(100);
+        let codecs = [Codec::default(), Codec::Store, Codec::Zlib(1)];
+        let mut writer = ArchiveWriter::create(&path, 3).unwrap();
+        for (i, codec) in codecs.iter().enumerate() {
+            let mut content = content.clone();
+            content.push(i as u8);
+            writer
+                .add(&header(&i.to_string()), &content, *codec)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        for (i, codec) in codecs.iter().enumerate() {
+            let entry = reader.entries[i].clone();
+            assert_eq!(entry.codec, *codec);
+            let mut expected = content.clone();
+            expected.push(i as u8);
+            assert_eq!(reader.read(&entry).unwrap(), expected);
+            // archives with only the default codec stay readable by older
+            // versions
+            let flags = bytes[entry.header_offset as usize];
+            assert_eq!(flags & CODEC != 0, *codec != Codec::default());
+        }
+        assert_eq!(reader.entries[1].length, reader.entries[1].unpacked_length);
+        assert!(reader.entries[2].length < reader.entries[2].unpacked_length);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_special_files() {
+        use crate::special::Kind;
+
+        let path = temp_path("special.kzip");
+        let fifo = Special {
+            kind: Kind::Fifo,
+            mode: 0o644,
+            device: 0,
+        };
+        let device = Special {
+            kind: Kind::CharDevice,
+            mode: 0o600,
+            device: 0x0103,
+        };
+        let mut writer = ArchiveWriter::create(&path, 4).unwrap();
+        writer.add_special(&header("fifo"), fifo).unwrap();
+        writer.add_special(&header("fifo2"), fifo).unwrap();
+        writer.add_special(&header("null"), device).unwrap();
+        // a file that happens to hold the same bytes is no special file
+        writer
+            .add(&header("file"), &fifo.to_bytes(), Codec::default())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let entries = reader.entries.clone();
+        assert!(entries.iter().all(|entry| !entry.is_duplicate()));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.special)
+                .collect::<Vec<_>>(),
+            [true, true, true, false]
+        );
+        let read = reader.read_special(&entries[0]).unwrap();
+        assert_eq!((read.kind, read.mode, read.device), (Kind::Fifo, 0o644, 0));
+        let read = reader.read_special(&entries[2]).unwrap();
+        assert_eq!(
+            (read.kind, read.mode, read.device),
+            (Kind::CharDevice, 0o600, 0x0103)
+        );
+        assert_eq!(reader.read(&entries[3]).unwrap(), fifo.to_bytes());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_meta_of_every_entry() {
+        let path = temp_path("entry-meta.kzip");
+        let meta = vec![
+            ("owner".to_string(), "build".to_string()),
+            ("note".to_string(), "has = and\nnew lines".to_string()),
+        ];
+        let mut writer = ArchiveWriter::create(&path, 3).unwrap();
+        writer
+            .add(
+                &Header {
+                    meta: &meta,
+                    ..header("a")
+                },
+                b"a",
+                Codec::Store,
+            )
+            .unwrap();
+        writer.add(&header("b"), b"b", Codec::Store).unwrap();
+        // a duplicate has its own meta
+        writer.add(&header("c"), b"a", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let mut reader = ArchiveReader::open_strict(&path).unwrap();
+        let entries = reader.entries.clone();
+        assert_eq!(entries[0].meta, meta);
+        assert!(entries[1].meta.is_empty());
+        assert!(entries[2].is_duplicate());
+        assert!(entries[2].meta.is_empty());
+        // entries without meta stay readable by older versions
+        let flags: Vec<_> = entries
+            .iter()
+            .map(|entry| bytes[entry.header_offset as usize] & META != 0)
+            .collect();
+        assert_eq!(flags, [true, false, false]);
+        assert_eq!(reader.read(&entries[0]).unwrap(), b"a");
+        assert_eq!(reader.read(&entries[1]).unwrap(), b"b");
+        assert_eq!(reader.read(&entries[2]).unwrap(), b"a");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_meta_of_the_archive() {
+        let path = temp_path("archive-meta.kzip");
+        let meta = vec![
+            ("commit".to_string(), "7e4ffba".to_string()),
+            ("built".to_string(), "a=b".to_string()),
+        ];
+        let options = CreateOptions::new().version("1.2.3").meta(&meta);
+        let mut writer = ArchiveWriter::create_with(&path, 1, &options).unwrap();
+        writer.add(&header("a"), b"a", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open_strict(&path).unwrap();
+        assert_eq!(reader.version, "1.2.3");
+        assert_eq!(reader.meta, meta);
+        let entry = reader.entries[0].clone();
+        assert_eq!(reader.read(&entry).unwrap(), b"a");
+
+        // older versions of kzip read the pairs as lines of the version
+        let bytes = fs::read(&path).unwrap();
+        let length = u32::from_be_bytes(bytes[3..7].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[7..7 + length], b"1.2.3\ncommit=7e4ffba\nbuilt=a=b");
+        drop(reader);
+
+        ArchiveWriter::create(&path, 0).unwrap().finish().unwrap();
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.version, crate::VERSION);
+        assert!(reader.meta.is_empty());
+
+        for pair in [("", "x"), ("a=b", "x"), ("a\nb", "x"), ("a", "x\ny")] {
+            let meta = [(pair.0.to_string(), pair.1.to_string())];
+            let options = CreateOptions::new().meta(&meta);
+            let err = ArchiveWriter::create_with(&path, 0, &options)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lists_headers_without_reading_content() {
+        let path = temp_path("headers.kzip");
+        let mut writer = ArchiveWriter::create(&path, 3).unwrap();
+        writer.add(&header("a"), b"first", Codec::Store).unwrap();
+        writer
+            .add(&header("b"), b"second", Codec::default())
+            .unwrap();
+        writer.add(&header("c"), b"first", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let entries = ArchiveReader::headers(&path).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(entries[1].codec, Codec::default());
+        assert_eq!(entries[1].unpacked_length, 6);
+        assert_eq!(entries[2].duplicate_of, Some(0));
+        // nothing is kept open
+        ArchiveWriter::create(&path, 0).unwrap().finish().unwrap();
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_codecs_that_fail() {
+        use crate::codec::{self, CustomCodec};
+
+        struct Failing;
+        impl CustomCodec for Failing {
+            fn id(&self) -> u8 {
+                250
+            }
+            fn name(&self) -> &str {
+                "failing"
+            }
+            fn encode(&self, _: u8, _: &mut dyn Read, _: &mut dyn Write) -> io::Result<()> {
+                Err(io::Error::other("the plugin failed"))
+            }
+            fn decoder<'a>(&self, _: u8, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+                input
+            }
+        }
+        codec::register(Box::new(Failing)).unwrap();
+
+        let path = temp_path("failing-codec.kzip");
+        let mut writer = ArchiveWriter::create(&path, 2).unwrap();
+        let err = writer
+            .add(&header("a"), b"a", Codec::Custom(250, 0))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "the plugin failed");
+        // nothing of it was written
+        writer.add(&header("b"), b"b", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open_strict(&path).unwrap();
+        assert_eq!(reader.entries.len(), 1);
+        let entry = reader.entries[0].clone();
+        assert_eq!(reader.read(&entry).unwrap(), b"b");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_encrypted_entries_that_are_too_short() {
+        let path = temp_path("short-encrypted.kzip");
+        let mut writer = ArchiveWriter::create(&path, 1).unwrap();
+        writer.add(&header("a"), b"", Codec::default()).unwrap();
+        writer.finish().unwrap();
+        let header_offset = ArchiveReader::open(&path).unwrap().entries[0].header_offset;
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[header_offset as usize] |= ENCRYPTED;
+        fs::write(&path, bytes).unwrap();
+        let err = ArchiveReader::open(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("too short to be encrypted"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn opens_archives_for_one_writer_at_a_time() {
+        let path = temp_path("exclusive.kzip");
+        let mut writer = ArchiveWriter::create(&path, 1).unwrap();
+        writer.add(&header("a"), b"a", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let reader = ArchiveReader::open_exclusive(&path).unwrap();
+        assert_eq!(reader.entries.len(), 1);
+        let err = ArchiveReader::open_exclusive(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        let err = ArchiveWriter::create(&path, 1).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        // the file keeps the lock
+        let file = reader.into_file();
+        assert!(ArchiveReader::open_exclusive(&path).is_err());
+        drop(file);
+        assert!(ArchiveReader::open_exclusive(&path).is_ok());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn copies_entries_as_they_are_stored() {
+        let path = temp_path("copy-from.kzip");
+        let mut writer = ArchiveWriter::create(&path, 3).unwrap();
+        writer.add(&header("a"), b"same", Codec::default()).unwrap();
+        writer.add(&header("b"), b"same", Codec::default()).unwrap();
+        writer.add(&header("c"), b"other", Codec::Store).unwrap();
+        writer.finish().unwrap();
+
+        let copy = temp_path("copy-to.kzip");
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let mut writer = ArchiveWriter::create(&copy, 4).unwrap();
+        for name in ["a", "b", "c"] {
+            writer.copy_entry(&mut reader, name).unwrap();
+        }
+        // content that doesn't even decompress is copied as it is
+        let broken = Entry {
+            codec: Codec::default(),
+            unpacked_length: 4,
+            ..reader.get("c").unwrap().clone()
+        };
+        writer
+            .add_compressed(&header("d"), Compressed::copy(&broken, b"junk").unwrap())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut copied = ArchiveReader::open(&copy).unwrap();
+        let [a, b, c, d] = &copied.entries.clone()[..] else {
+            panic!("expected 4 entries");
+        };
+        assert!(!a.is_duplicate() && b.is_duplicate() && !c.is_duplicate());
+        assert_eq!(copied.read(b).unwrap(), b"same");
+        assert_eq!(copied.read(c).unwrap(), b"other");
+        assert_eq!(copied.read_raw(d).unwrap(), b"junk");
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(copy).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn ties_encrypted_content_to_its_entry() {
+        let path = temp_path("encrypted.kzip");
+        let mut password = Password::new("secret");
+        let mut writer = ArchiveWriter::create(&path, 3).unwrap();
+        for (name, content) in [("a", "same"), ("b", "same"), ("c", "other")] {
+            writer
+                .add_encrypted(
+                    &header(name),
+                    content.as_bytes(),
+                    Codec::default(),
+                    &mut password,
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        reader.password = Some(Password::new("secret"));
+        // sharing content would tell which encrypted files are the same
+        assert!(reader.entries.iter().all(|entry| !entry.is_duplicate()));
+        let a = reader.entries[0].clone();
+        assert_eq!(reader.read(&a).unwrap(), b"same");
+
+        // the content of one entry under the header of another
+        let mut moved = reader.entries[2].clone();
+        moved.name = "a".to_string();
+        let err = reader.read(&moved).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let mut resized = a.clone();
+        resized.special = true;
+        assert!(reader.read(&resized).is_err());
+
+        // renaming encrypts it again for the new name
+        let stored = reader.read_raw_as(&a, OsStr::new("d")).unwrap();
+        let copy = temp_path("encrypted-copy.kzip");
+        let mut writer = ArchiveWriter::create(&copy, 1).unwrap();
+        writer
+            .add_compressed(&header("d"), Compressed::copy(&a, &stored).unwrap())
+            .unwrap();
+        writer.finish().unwrap();
+        let mut renamed = ArchiveReader::open(&copy).unwrap();
+        renamed.password = Some(Password::new("secret"));
+        let d = renamed.entries[0].clone();
+        assert_eq!(renamed.read(&d).unwrap(), b"same");
+
+        // which needs the password
+        reader.password = None;
+        let err = reader.read_raw_as(&a, OsStr::new("d")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(
+            reader.read_raw_as(&a, OsStr::new("a")).unwrap().len() as u64,
+            a.length
+        );
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(copy).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs,
+    io::{self, Read},
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader},
+    crypto::Password,
+    history, meta,
+    pattern::glob_match,
+    rename::rename,
+    rewrite::{self, Change, Content},
+    utils::{password, positionals},
+};
+
+/// `kzip batch archive.kzip [ops.txt]`
+///
+/// Runs one operation per line, read from a file or stdin, and writes the
+/// archive once at the end:
+///
+/// ```text
+/// # comments and empty lines are skipped
+/// add build/app.js dist/app.js
+/// delete 'logs/**'
+/// rename old/path new/path
+/// meta set dist/app.js commit=abc123
+/// meta unset dist/app.js build
+/// ```
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--password-file"]);
+    let (input, ops_file) = match &positionals[..] {
+        [input] => (input, None),
+        [input, ops_file] if ops_file == "-" => (input, None),
+        [input, ops_file] => (input, Some(ops_file)),
+        _ => {
+            println!(
+                "kzip: batch needs an archive and operations, i.e. kzip batch archive.kzip ops.txt"
+            );
+            exit(1);
+        }
+    };
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    let ops = match ops_file {
+        Some(ops_file) => fs::read_to_string(ops_file),
+        None => {
+            let mut ops = String::new();
+            io::stdin().read_to_string(&mut ops).map(|_| ops)
+        }
+    };
+    let ops = match ops {
+        Ok(ops) => ops,
+        Err(err) => {
+            println!("kzip: could not read the operations");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    // encrypted files that are renamed are encrypted again for their name
+    reader.password = match password(args) {
+        Ok(password) => password.as_deref().map(Password::new),
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+    let mut changes: Vec<Change> = reader.entries.iter().map(Change::keep).collect();
+
+    let mut applied = 0;
+    for (number, line) in ops.lines().enumerate() {
+        let words = match words(line) {
+            Ok(words) => words,
+            Err(err) => {
+                println!("kzip: line {}: {err}", number + 1);
+                exit(1);
+            }
+        };
+        if words.is_empty() {
+            continue;
+        }
+        if is_verbose {
+            println!("kzip: {}", line.trim());
+        }
+
+        if let Err(err) = apply(&mut changes, &words) {
+            println!("kzip: line {}: {err}", number + 1);
+            exit(1);
+        }
+        applied += 1;
+    }
+
+    let mut names = HashSet::new();
+    for change in &changes {
+        if !names.insert(&change.name) {
+            println!(
+                "kzip: {} would be in {input} twice",
+                change.name.to_string_lossy()
+            );
+            exit(1);
+        }
+    }
+
+    let count = changes.len();
+    history::record(&mut reader.meta, "batch", applied);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    println!("kzip: Done, {input} has {count} file(s)");
+    exit(0);
+}
+
+fn apply(changes: &mut Vec<Change>, words: &[String]) -> Result<(), String> {
+    let name_of = |change: &Change| change.name.to_string_lossy().to_string();
+
+    match words {
+        [op, file] | [op, file, _] if op == "add" => {
+            let name = parse_file_path(words.get(2).unwrap_or(file).to_string());
+            let content = fs::read(file).map_err(|err| format!("could not read {file}: {err}"))?;
+            let metadata = fs::metadata(file).map_err(|err| format!("{file}: {err}"))?;
+            let created_at = seconds(metadata.created());
+            let modified = seconds(metadata.modified()).unwrap_or_default();
+
+            match changes.iter_mut().find(|change| name_of(change) == name) {
+                Some(change) => {
+                    change.created_at = created_at;
+                    change.modified = modified;
+                    change.content = Content::New(content, Default::default());
+                }
+                None => changes.push(Change::add(
+                    OsString::from(name),
+                    created_at,
+                    modified,
+                    content,
+                )),
+            }
+        }
+        [op, globs @ ..] if op == "delete" && !globs.is_empty() => {
+            let before = changes.len();
+            changes.retain(|change| !globs.iter().any(|glob| glob_match(glob, &name_of(change))));
+            if changes.len() == before {
+                return Err(format!("nothing matches {}", globs.join(" ")));
+            }
+        }
+        [op, old, new] if op == "rename" => {
+            let old = parse_file_path(old.trim_end_matches(['/', '\\']).to_string());
+            let new = parse_file_path(new.trim_end_matches(['/', '\\']).to_string());
+            let mut renamed = false;
+            for change in changes.iter_mut() {
+                if let Some(name) = rename(&change.name, &old, &new) {
+                    change.name = name;
+                    renamed = true;
+                }
+            }
+            if !renamed {
+                return Err(format!("{old} is not in the archive"));
+            }
+        }
+        [op, command, name, rest @ ..] if op == "meta" && !rest.is_empty() => {
+            let name = parse_file_path(name.to_string());
+            let change = changes
+                .iter_mut()
+                .find(|change| name_of(change) == name)
+                .ok_or_else(|| format!("{name} is not in the archive"))?;
+            match command.as_str() {
+                "set" => meta::set(&mut change.meta, rest)?,
+                "unset" => change.meta.retain(|(key, _)| !rest.contains(key)),
+                _ => return Err(format!("unknown meta command {command}, use set or unset")),
+            }
+        }
+        _ => {
+            return Err(format!(
+                "can't run {}, use add, delete, rename or meta",
+                words.join(" ")
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn seconds(time: io::Result<SystemTime>) -> Option<u64> {
+    Some(time.ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Splits a line into words like a shell does, with '' and "" for words
+/// with spaces. Everything after a # that starts a word is a comment.
+fn words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => break,
+            _ => {
+                let mut word = String::new();
+                let mut c = Some(c);
+                while let Some(current) = c {
+                    match current {
+                        c if c.is_whitespace() => break,
+                        '\'' | '"' => {
+                            let mut closed = false;
+                            for inner in chars.by_ref() {
+                                if inner == current {
+                                    closed = true;
+                                    break;
+                                }
+                                word.push(inner);
+                            }
+                            if !closed {
+                                return Err(format!("missing {current}"));
+                            }
+                        }
+                        c => word.push(c),
+                    }
+                    c = chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+
+    Ok(words)
+}
@@ -0,0 +1,221 @@
+use std::{fmt::Write, iter::Peekable, str::Chars};
+
+/// What a job request can hold. Numbers are only whole ones, nothing kzipd
+/// reads has a fraction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value of `key` in an object, None for anything else.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(pairs) => pairs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Reads one JSON value, with nothing but whitespace after it.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars, 0)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        Some(char) => Err(format!("unexpected {char:?} after the value")),
+        None => Ok(value),
+    }
+}
+
+// requests are small, anything nested deeper than this is made up
+const MAX_DEPTH: usize = 32;
+
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("values are nested more than {MAX_DEPTH} deep"));
+    }
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut pairs = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Value::Object(pairs));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                expect(chars, ':')?;
+                pairs.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Value::Object(pairs)),
+                    _ => return Err("expected , or } in an object".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut values = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Value::Array(values));
+            }
+            loop {
+                values.push(parse_value(chars, depth + 1)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Value::Array(values)),
+                    _ => return Err("expected , or ] in an array".to_string()),
+                }
+            }
+        }
+        Some('"') => parse_string(chars).map(Value::String),
+        Some('0'..='9') => {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            digits
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("{digits} is too large"))
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(char) = chars.next_if(char::is_ascii_alphabetic) {
+                word.push(char);
+            }
+            match word.as_str() {
+                "null" => Ok(Value::Null),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "" => Err(format!("unexpected {:?}", chars.peek().unwrap())),
+                word => Err(format!("unexpected {word}")),
+            }
+        }
+        None => Err("the value ends early".to_string()),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => match chars.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('/') => string.push('/'),
+                Some('b') => string.push('\u{8}'),
+                Some('f') => string.push('\u{c}'),
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                Some('u') => string.push(parse_unicode(chars)?),
+                _ => return Err("unknown escape in a string".to_string()),
+            },
+            Some(char) => string.push(char),
+            None => return Err("a string isn't closed".to_string()),
+        }
+    }
+}
+
+// \uXXXX, characters past the first plane come as two of them
+fn parse_unicode(chars: &mut Peekable<Chars>) -> Result<char, String> {
+    let first = parse_hex(chars)?;
+    let code = if (0xD800..0xDC00).contains(&first) {
+        if chars.next() != Some('\\') || chars.next() != Some('u') {
+            return Err("a surrogate isn't followed by another".to_string());
+        }
+        let second = parse_hex(chars)?;
+        0x10000 + ((first - 0xD800) << 10) + (second.wrapping_sub(0xDC00) & 0x3FF)
+    } else {
+        first
+    };
+    char::from_u32(code).ok_or_else(|| format!("\\u{code:x} isn't a character"))
+}
+
+fn parse_hex(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let hex: String = chars.take(4).collect();
+    u32::from_str_radix(&hex, 16).map_err(|_| format!("\\u{hex} isn't hexadecimal"))
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    match chars.next() {
+        Some(char) if char == expected => Ok(()),
+        _ => Err(format!("expected {expected}")),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|char| char.is_ascii_whitespace()).is_some() {}
+}
+
+/// `string` as a JSON string, with its quotes.
+pub fn string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            char if (char as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", char as u32);
+            }
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `value` as a JSON number, or null.
+pub fn number(value: Option<u64>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
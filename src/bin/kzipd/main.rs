@@ -0,0 +1,342 @@
+//! `kzipd --socket /run/kzip.sock`
+//!
+//! Runs archive jobs for other programs without starting kzip for each of
+//! them. Every line sent to the socket is a job as a JSON object, answered
+//! with JSON lines: `progress` while it runs, `entry` for every file a
+//! `list` finds, then `done` or `error`. Jobs on one connection run one
+//! after the other, connections run next to each other.
+//!
+//! ```text
+//! {"job": "create", "input": "dir", "output": "a.kzip", "codec": "zlib", "level": 6,
+//!  "dedupe": true, "exclude": [".git"]}
+//! {"job": "extract", "archive": "a.kzip", "output": "dir", "overwrite": false,
+//!  "strip_components": 1, "preserve_times": true, "trusted": false}
+//! {"job": "list", "archive": "a.kzip"}
+//! ```
+//!
+//! Archives are extracted with the limits of `kzip -x` on name lengths,
+//! entry counts and meta, unless the job says they are `trusted`.
+
+mod json;
+
+use std::process::exit;
+
+fn help() {
+    println!("Command usage: kzipd --socket <PATH>");
+    println!("Options:");
+    println!("  -s, --socket   The unix socket to take jobs from, i.e. /run/kzip.sock");
+    println!("  -h, --help     Shows this");
+    println!("Jobs are JSON objects, one per line, with \"job\" being create, extract or list.");
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut socket = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-s" | "--socket" => {
+                i += 1;
+                match args.get(i) {
+                    Some(path) => socket = Some(path.clone()),
+                    None => {
+                        println!("kzipd: --socket needs a path");
+                        exit(1);
+                    }
+                }
+            }
+            "-h" | "--help" => {
+                help();
+                return;
+            }
+            arg => {
+                println!("kzipd: unknown option {arg}, see kzipd --help");
+                exit(1);
+            }
+        }
+        i += 1;
+    }
+
+    match socket {
+        Some(socket) => serve(&socket),
+        None => help(),
+    }
+}
+
+#[cfg(not(unix))]
+fn serve(_socket: &str) {
+    println!("kzipd: needs unix sockets, which this system doesn't have");
+    exit(1);
+}
+
+#[cfg(unix)]
+fn serve(socket: &str) {
+    use std::{
+        fs,
+        os::unix::net::{UnixListener, UnixStream},
+        thread,
+    };
+
+    // a socket file nobody answers on was left by a kzipd that was killed
+    if fs::symlink_metadata(socket).is_ok() {
+        if UnixStream::connect(socket).is_ok() {
+            println!("kzipd: another kzipd is already taking jobs on {socket}");
+            exit(1);
+        }
+        if let Err(err) = fs::remove_file(socket) {
+            println!("kzipd: could not remove the old socket {socket}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+
+    let listener = match UnixListener::bind(socket) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("kzipd: could not listen on {socket}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+    println!("kzipd: taking jobs on {socket}");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                thread::spawn(move || jobs::connection(stream));
+            }
+            Err(err) => println!("kzipd: could not accept a connection: {err}"),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod jobs {
+    use std::{
+        io::{self, BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use kzip::{
+        archive::{ArchiveReader, ArchiveWriter, Entry, Progress},
+        codec::Codec,
+        options::{CreateOptions, ExtractOptions},
+        vfs::{self, OsFs},
+    };
+
+    use crate::json::{self, Value};
+
+    // the answers of a job, shared with the progress callback. Once the
+    // client is gone, the job is cancelled instead of finishing for nobody.
+    #[derive(Clone)]
+    struct Output {
+        stream: Arc<Mutex<UnixStream>>,
+        gone: Arc<AtomicBool>,
+    }
+
+    impl Output {
+        fn send(&self, line: &str) {
+            let mut stream = self.stream.lock().unwrap();
+            if writeln!(stream, "{line}").is_err() {
+                self.gone.store(true, Ordering::Relaxed);
+            }
+        }
+
+        fn progress(&self, progress: &Progress) {
+            self.send(&format!(
+                "{{\"progress\":{{\"name\":{},\"entries\":{},\"total_entries\":{},\"bytes\":{},\"total_bytes\":{}}}}}",
+                json::string(progress.name),
+                progress.entries,
+                json::number(progress.total_entries),
+                progress.bytes,
+                json::number(progress.total_bytes),
+            ));
+        }
+    }
+
+    pub fn connection(stream: UnixStream) {
+        let reader = match stream.try_clone() {
+            Ok(reader) => BufReader::new(reader),
+            Err(_) => return,
+        };
+        let output = Output {
+            stream: Arc::new(Mutex::new(stream)),
+            gone: Arc::new(AtomicBool::new(false)),
+        };
+
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = json::parse(&line).and_then(|request| run(&request, &output));
+            match result {
+                Ok(done) => output.send(&format!("{{\"done\":{done}}}")),
+                Err(err) => output.send(&format!("{{\"error\":{}}}", json::string(&err))),
+            }
+            if output.gone.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+    }
+
+    // what the job did as a JSON object, or why it couldn't be done
+    fn run(request: &Value, output: &Output) -> Result<String, String> {
+        match request.get("job").and_then(Value::as_str) {
+            Some("create") => create(request, output),
+            Some("extract") => extract(request, output),
+            Some("list") => list(request, output),
+            Some(job) => Err(format!("unknown job {job}, use create, extract or list")),
+            None => Err("the request has no \"job\"".to_string()),
+        }
+    }
+
+    fn create(request: &Value, output: &Output) -> Result<String, String> {
+        let input = string(request, "input")?;
+        let archive = string(request, "output")?;
+
+        let mut options = CreateOptions::new();
+        if let Some(algo) = optional_string(request, "codec")? {
+            let level = request
+                .get("level")
+                .and_then(Value::as_u64)
+                .map(|level| level.to_string());
+            options = options.codec(Codec::parse(algo, level.as_deref())?);
+        }
+        if let Some(dedupe) = optional_bool(request, "dedupe")? {
+            options = options.dedupe(dedupe);
+        }
+        if let Some(excludes) = request.get("exclude") {
+            let excludes = excludes
+                .as_array()
+                .ok_or("\"exclude\" has to be a list of names")?;
+            for exclude in excludes {
+                let exclude = exclude
+                    .as_str()
+                    .ok_or("\"exclude\" has to be a list of names")?;
+                options = options.exclude(exclude);
+            }
+        }
+
+        let input = Path::new(input);
+        let nof = vfs::count_files(&OsFs, input, &options.excludes).map_err(describe)?;
+        let mut writer = ArchiveWriter::create_with(archive, nof, &options)
+            .map_err(|err| format!("could not create {archive}: {err}"))?;
+        writer.cancel_on(output.gone.clone());
+        let progress = output.clone();
+        writer.on_progress(move |step| progress.progress(step));
+
+        let added = writer.add_tree(&OsFs, input).map_err(describe)?;
+        writer.finish().map_err(describe)?;
+        Ok(format!(
+            "{{\"job\":\"create\",\"archive\":{},\"entries\":{added}}}",
+            json::string(archive)
+        ))
+    }
+
+    fn extract(request: &Value, output: &Output) -> Result<String, String> {
+        let archive = string(request, "archive")?;
+        let root = string(request, "output")?;
+
+        let mut options = ExtractOptions::new();
+        if let Some(overwrite) = optional_bool(request, "overwrite")? {
+            options = options.overwrite(overwrite);
+        }
+        if let Some(strip) = request.get("strip_components") {
+            let strip = strip
+                .as_u64()
+                .ok_or("\"strip_components\" has to be a number")?;
+            options = options.strip_components(strip as usize);
+        }
+        if let Some(preserve_times) = optional_bool(request, "preserve_times")? {
+            options = options.preserve_times(preserve_times);
+        }
+
+        let trusted = optional_bool(request, "trusted")?.unwrap_or(false);
+        let opened = if trusted {
+            ArchiveReader::open(archive)
+        } else {
+            ArchiveReader::open_strict(archive)
+        };
+        let mut reader = opened.map_err(|err| format!("could not read {archive}: {err}"))?;
+        reader.cancel_on(output.gone.clone());
+        let progress = output.clone();
+        reader.on_progress(move |step| progress.progress(step));
+
+        let written = reader
+            .extract_into(&mut OsFs, Path::new(root), &options)
+            .map_err(describe)?;
+        Ok(format!(
+            "{{\"job\":\"extract\",\"archive\":{},\"entries\":{written}}}",
+            json::string(archive)
+        ))
+    }
+
+    fn list(request: &Value, output: &Output) -> Result<String, String> {
+        let archive = string(request, "archive")?;
+        let entries = ArchiveReader::headers(archive)
+            .map_err(|err| format!("could not read {archive}: {err}"))?;
+        for entry in &entries {
+            if output.gone.load(Ordering::Relaxed) {
+                break;
+            }
+            output.send(&format!("{{\"entry\":{}}}", entry_json(entry)));
+        }
+        Ok(format!(
+            "{{\"job\":\"list\",\"archive\":{},\"entries\":{}}}",
+            json::string(archive),
+            entries.len()
+        ))
+    }
+
+    fn entry_json(entry: &Entry) -> String {
+        format!(
+            "{{\"name\":{},\"modified\":{},\"created_at\":{},\"unpacked_length\":{},\"length\":{},\"codec\":{},\"duplicate\":{},\"special\":{},\"encrypted\":{}}}",
+            json::string(&entry.name),
+            entry.modified,
+            json::number(entry.created_at),
+            entry.unpacked_length,
+            entry.length,
+            json::string(&entry.codec.name()),
+            entry.is_duplicate(),
+            entry.special,
+            entry.encrypted,
+        )
+    }
+
+    fn string<'a>(request: &'a Value, key: &str) -> Result<&'a str, String> {
+        optional_string(request, key)?.ok_or_else(|| format!("the request has no \"{key}\""))
+    }
+
+    fn optional_string<'a>(request: &'a Value, key: &str) -> Result<Option<&'a str>, String> {
+        match request.get(key) {
+            Some(value) => match value.as_str() {
+                Some(value) => Ok(Some(value)),
+                None => Err(format!("\"{key}\" has to be a string")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn optional_bool(request: &Value, key: &str) -> Result<Option<bool>, String> {
+        match request.get(key) {
+            Some(value) => match value.as_bool() {
+                Some(value) => Ok(Some(value)),
+                None => Err(format!("\"{key}\" has to be true or false")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn describe(err: io::Error) -> String {
+        err.to_string()
+    }
+}
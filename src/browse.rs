@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, IsTerminal, Read, Write},
+    path,
+    process::{exit, Command, Stdio},
+};
+
+use crate::{
+    archive::ArchiveReader,
+    utils::{format_byte, positionals},
+    vfs, write_file,
+};
+
+// how much of a file the preview decompresses
+const PREVIEW_LIMIT: u64 = 1024 * 1024;
+// signals are turned off in raw mode, so ctrl+c arrives as a key
+const CTRL_C: char = '\u{3}';
+
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Char(char),
+    Other,
+}
+
+struct Child {
+    name: String,
+    is_dir: bool,
+}
+
+struct Browser {
+    reader: ArchiveReader,
+    input: String,
+    prefix: String,
+    cursor: usize,
+    scroll: usize,
+    marked: BTreeSet<String>,
+    preview: Option<(String, Vec<String>)>,
+    preview_scroll: usize,
+    status: String,
+}
+
+/// `kzip browse archive.kzip`
+pub fn run(args: &[String]) {
+    let input = match positionals(&args[2..], &[]).first() {
+        Some(input) => input.to_string(),
+        None => {
+            println!("kzip: browse needs an archive, i.e. kzip browse archive.kzip");
+            exit(1);
+        }
+    };
+
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        println!("kzip: browse needs to be run in a terminal");
+        exit(1);
+    }
+
+    let reader = match ArchiveReader::open(&input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    if !set_raw_mode(true) {
+        println!("kzip: browse could not switch the terminal to raw mode");
+        exit(1);
+    }
+    // alternate screen, hide the cursor
+    print!("\x1b[?1049h\x1b[?25l");
+
+    let mut browser = Browser {
+        reader,
+        input,
+        prefix: String::new(),
+        cursor: 0,
+        scroll: 0,
+        marked: BTreeSet::new(),
+        preview: None,
+        preview_scroll: 0,
+        status: String::new(),
+    };
+    let result = browser.run();
+
+    print!("\x1b[?25h\x1b[?1049l");
+    let _ = io::stdout().flush();
+    set_raw_mode(false);
+
+    if let Err(err) = result {
+        println!("kzip: there was an error");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    exit(0);
+}
+
+impl Browser {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.draw()?;
+
+            let key = read_key()?;
+            self.status.clear();
+
+            if self.preview.is_some() {
+                match key {
+                    Key::Up | Key::Char('k') => {
+                        self.preview_scroll = self.preview_scroll.saturating_sub(1)
+                    }
+                    Key::Down | Key::Char('j') => self.preview_scroll += 1,
+                    Key::PageUp => {
+                        self.preview_scroll = self.preview_scroll.saturating_sub(page_size())
+                    }
+                    Key::PageDown | Key::Char(' ') => self.preview_scroll += page_size(),
+                    Key::Char('q') | Key::Char(CTRL_C) => return Ok(()),
+                    _ => self.preview = None,
+                }
+                continue;
+            }
+
+            let children = self.children();
+            match key {
+                Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+                Key::Down | Key::Char('j') => {
+                    self.cursor = (self.cursor + 1).min(children.len().saturating_sub(1))
+                }
+                Key::PageUp => self.cursor = self.cursor.saturating_sub(page_size()),
+                Key::PageDown => {
+                    self.cursor = (self.cursor + page_size()).min(children.len().saturating_sub(1))
+                }
+                Key::Right | Key::Enter | Key::Char('l') => {
+                    if let Some(child) = children.get(self.cursor) {
+                        if child.is_dir {
+                            self.prefix = format!("{}{}", child.name, path::MAIN_SEPARATOR);
+                            self.cursor = 0;
+                            self.scroll = 0;
+                        } else {
+                            self.open_preview(&child.name)?;
+                        }
+                    }
+                }
+                Key::Left | Key::Backspace | Key::Char('h') => self.go_up(),
+                Key::Char(' ') => {
+                    if let Some(child) = children.get(self.cursor) {
+                        self.toggle_mark(child);
+                        self.cursor = (self.cursor + 1).min(children.len().saturating_sub(1));
+                    }
+                }
+                Key::Char('a') => {
+                    for child in &children {
+                        if !self.is_marked(child) {
+                            self.toggle_mark(child);
+                        }
+                    }
+                }
+                Key::Char('u') => self.marked.clear(),
+                Key::Char('x') => self.extract(&children)?,
+                Key::Char('q') | Key::Char(CTRL_C) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    /// The files and directories directly inside of the current directory.
+    fn children(&self) -> Vec<Child> {
+        let mut dirs = BTreeSet::new();
+        let mut files = BTreeSet::new();
+
+        for entry in &self.reader.entries {
+            if let Some(rest) = entry.name.strip_prefix(&self.prefix) {
+                match rest.split_once(path::MAIN_SEPARATOR) {
+                    Some((dir, _)) => dirs.insert(format!("{}{dir}", self.prefix)),
+                    None => files.insert(entry.name.to_string()),
+                };
+            }
+        }
+
+        dirs.into_iter()
+            .map(|name| Child { name, is_dir: true })
+            .chain(files.into_iter().map(|name| Child {
+                name,
+                is_dir: false,
+            }))
+            .collect()
+    }
+
+    fn go_up(&mut self) {
+        if self.prefix.is_empty() {
+            return;
+        }
+
+        let current = self
+            .prefix
+            .trim_end_matches(path::MAIN_SEPARATOR)
+            .to_string();
+        self.prefix = match current.rsplit_once(path::MAIN_SEPARATOR) {
+            Some((parent, _)) => format!("{parent}{}", path::MAIN_SEPARATOR),
+            None => String::new(),
+        };
+
+        // put the cursor back onto the directory we came from
+        self.cursor = self
+            .children()
+            .iter()
+            .position(|child| child.name == current)
+            .unwrap_or(0);
+        self.scroll = 0;
+    }
+
+    fn entries_of(&self, child: &Child) -> Vec<String> {
+        if child.is_dir {
+            let prefix = format!("{}{}", child.name, path::MAIN_SEPARATOR);
+            self.reader
+                .entries
+                .iter()
+                .filter(|entry| entry.name.starts_with(&prefix))
+                .map(|entry| entry.name.to_string())
+                .collect()
+        } else {
+            vec![child.name.to_string()]
+        }
+    }
+
+    fn is_marked(&self, child: &Child) -> bool {
+        self.entries_of(child)
+            .iter()
+            .all(|name| self.marked.contains(name))
+    }
+
+    fn toggle_mark(&mut self, child: &Child) {
+        let names = self.entries_of(child);
+        if self.is_marked(child) {
+            for name in names {
+                self.marked.remove(&name);
+            }
+        } else {
+            self.marked.extend(names);
+        }
+    }
+
+    fn open_preview(&mut self, name: &str) -> io::Result<()> {
+        let entry = match self.reader.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Ok(()),
+        };
+
+        let mut content = Vec::new();
+        self.reader
+            .stream(&entry)?
+            .take(PREVIEW_LIMIT)
+            .read_to_end(&mut content)?;
+
+        let lines = if content.iter().take(8000).any(|byte| *byte == 0) {
+            vec![format!("binary file, {} bytes", entry.unpacked_length)]
+        } else {
+            String::from_utf8_lossy(&content)
+                .lines()
+                .map(|line| line.replace('\t', "    "))
+                .collect()
+        };
+
+        self.preview = Some((name.to_string(), lines));
+        self.preview_scroll = 0;
+        Ok(())
+    }
+
+    fn extract(&mut self, children: &[Child]) -> io::Result<()> {
+        let names: Vec<String> = if self.marked.is_empty() {
+            match children.get(self.cursor) {
+                Some(child) => self.entries_of(child),
+                None => return Ok(()),
+            }
+        } else {
+            self.marked.iter().cloned().collect()
+        };
+
+        let output = match prompt("Extract to directory: ")? {
+            Some(output) if !output.is_empty() => output,
+            _ => {
+                self.status = "Extraction cancelled".to_string();
+                return Ok(());
+            }
+        };
+
+        let mut extracted = 0;
+        for name in &names {
+            // names that lead out of the output are left out, like kzip -x does
+            if !vfs::is_inside(name) {
+                continue;
+            }
+            if let Some(entry) = self.reader.get(name).cloned() {
+                let content = self.reader.read(&entry)?;
+                write_file(&output, &entry.os_name(), &content, false)?;
+                extracted += 1;
+            }
+        }
+
+        self.status = format!("Extracted {extracted} file(s) to {output}");
+        self.marked.clear();
+        Ok(())
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let (rows, columns) = terminal_size();
+        let height = rows.saturating_sub(3).max(1);
+        let mut screen = String::from("\x1b[H\x1b[2J");
+
+        match &self.preview {
+            Some((name, lines)) => {
+                self.preview_scroll = self.preview_scroll.min(lines.len().saturating_sub(height));
+                screen.push_str(&header(&format!("{} : {name}", self.input), columns));
+                for line in lines.iter().skip(self.preview_scroll).take(height) {
+                    screen.push_str(&truncate(line, columns));
+                    screen.push_str("\r\n");
+                }
+                screen.push_str(&format!(
+                    "\x1b[{rows};1H{}",
+                    truncate(
+                        "j/k scroll  space/PgDn page  any other key closes  q quit",
+                        columns
+                    )
+                ));
+            }
+            None => {
+                let children = self.children();
+                self.cursor = self.cursor.min(children.len().saturating_sub(1));
+                if self.cursor < self.scroll {
+                    self.scroll = self.cursor;
+                } else if self.cursor >= self.scroll + height {
+                    self.scroll = self.cursor + 1 - height;
+                }
+
+                let shown = self.prefix.replace(path::MAIN_SEPARATOR, "/");
+                screen.push_str(&header(&format!("{} : /{shown}", self.input), columns));
+                for (i, child) in children.iter().enumerate().skip(self.scroll).take(height) {
+                    let mark = if self.is_marked(child) { "*" } else { " " };
+                    let name = child.name.strip_prefix(&self.prefix).unwrap_or(&child.name);
+                    let line = if child.is_dir {
+                        format!("{mark} {name}/")
+                    } else {
+                        let size = self
+                            .reader
+                            .get(&child.name)
+                            .map(|entry| format_byte(entry.unpacked_length as f64))
+                            .unwrap_or_default();
+                        format!("{mark} {name}  ({size})")
+                    };
+
+                    if i == self.cursor {
+                        screen.push_str(&format!("\x1b[7m{}\x1b[0m\r\n", truncate(&line, columns)));
+                    } else {
+                        screen.push_str(&format!("{}\r\n", truncate(&line, columns)));
+                    }
+                }
+
+                let footer = if self.status.is_empty() {
+                    format!(
+                        "enter open  h back  space mark  a mark all  u unmark  x extract  q quit  [{} marked]",
+                        self.marked.len()
+                    )
+                } else {
+                    self.status.to_string()
+                };
+                screen.push_str(&format!("\x1b[{rows};1H{}", truncate(&footer, columns)));
+            }
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+fn header(text: &str, columns: usize) -> String {
+    format!("\x1b[1m{}\x1b[0m\r\n\r\n", truncate(text, columns))
+}
+
+/// Cuts `text` to the width of the terminal. Names and content come from the
+/// archive, so control characters are replaced, escape sequences in them
+/// would otherwise be run by the terminal. Tabs in content are expanded
+/// before.
+fn truncate(text: &str, columns: usize) -> String {
+    text.chars()
+        .take(columns)
+        .map(|c| if c.is_control() { '\u{fffd}' } else { c })
+        .collect()
+}
+
+fn page_size() -> usize {
+    terminal_size().0.saturating_sub(3).max(1)
+}
+
+fn read_key() -> io::Result<Key> {
+    let mut stdin = io::stdin();
+    let mut byte = [0; 1];
+    stdin.read_exact(&mut byte)?;
+
+    Ok(match byte[0] {
+        b'\r' | b'\n' => Key::Enter,
+        127 | 8 => Key::Backspace,
+        0x1b => {
+            let mut sequence = [0; 2];
+            stdin.read_exact(&mut sequence)?;
+            match sequence {
+                [b'[', b'A'] => Key::Up,
+                [b'[', b'B'] => Key::Down,
+                [b'[', b'C'] => Key::Right,
+                [b'[', b'D'] => Key::Left,
+                [b'[', b'5'] | [b'[', b'6'] => {
+                    // page keys end with a ~
+                    stdin.read_exact(&mut byte)?;
+                    if sequence[1] == b'5' {
+                        Key::PageUp
+                    } else {
+                        Key::PageDown
+                    }
+                }
+                _ => Key::Other,
+            }
+        }
+        byte if byte.is_ascii() => Key::Char(byte as char),
+        _ => Key::Other,
+    })
+}
+
+/// Reads a line with the terminal temporarily back in its normal mode.
+fn prompt(question: &str) -> io::Result<Option<String>> {
+    let (rows, _) = terminal_size();
+    print!("\x1b[{rows};1H\x1b[2K{question}\x1b[?25h");
+    io::stdout().flush()?;
+
+    set_raw_mode(false);
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+    set_raw_mode(true);
+    print!("\x1b[?25l");
+
+    Ok(match read? {
+        0 => None,
+        _ => Some(line.trim().to_string()),
+    })
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
+
+fn set_raw_mode(raw: bool) -> bool {
+    if raw {
+        stty(&["-icanon", "-echo", "-isig", "min", "1"]).is_some()
+    } else {
+        stty(&["icanon", "echo", "isig"]).is_some()
+    }
+}
+
+fn terminal_size() -> (usize, usize) {
+    stty(&["size"])
+        .and_then(|size| {
+            let mut parts = size.split_whitespace().map(|part| part.parse().ok());
+            Some((parts.next()??, parts.next()??))
+        })
+        .filter(|(rows, columns)| *rows > 0 && *columns > 0)
+        .unwrap_or((24, 80))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_leaves_out_control_characters() {
+        assert_eq!(truncate("name.txt", 4), "name");
+        assert_eq!(
+            truncate("\x1b]0;title\x07ok", 20),
+            "\u{fffd}]0;title\u{fffd}ok"
+        );
+        assert_eq!(
+            truncate("\x1b[2J\r\n\t\u{9b}x", 20),
+            "\u{fffd}[2J\u{fffd}\u{fffd}\u{fffd}\u{fffd}x"
+        );
+        assert_eq!(truncate("ünïcode", 3), "ünï");
+    }
+}
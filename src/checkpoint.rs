@@ -0,0 +1,201 @@
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use crate::{archive::ArchiveWriter, signals};
+
+/*
+    While an archive is being created, how far it got is written next to it
+    in name.kzip.checkpoint every few seconds:
+
+    entries 1200
+    offset 52345678
+
+    The archive is synced before, so the first `entries` entries are whole and
+    on disk within the first `offset` bytes. --resume keeps those and goes on
+    with the files that aren't in the archive yet. The checkpoint is removed
+    once the archive is finished, short runs never write one.
+*/
+
+const INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct Checkpoints {
+    path: String,
+    last: Instant,
+    written: bool,
+}
+
+impl Checkpoints {
+    pub fn new(output: &str) -> Checkpoints {
+        Checkpoints {
+            path: path(output),
+            last: Instant::now(),
+            written: false,
+        }
+    }
+
+    /// Writes a checkpoint if the last one was a while ago.
+    pub fn update(&mut self, writer: &mut ArchiveWriter) -> Result<(), String> {
+        if self.last.elapsed() < INTERVAL {
+            return Ok(());
+        }
+        self.last = Instant::now();
+
+        writer
+            .sync()
+            .map_err(|err| format!("could not sync the archive: {err}"))?;
+        // written next to it and renamed, so there is always a whole one
+        let part = format!("{}.kzip-part", self.path);
+        let checkpoint = format!("entries {}\noffset {}\n", writer.count(), writer.position());
+        fs::write(&part, checkpoint)
+            .and_then(|_| fs::rename(&part, &self.path))
+            .map_err(|err| format!("could not write {}: {err}", self.path))?;
+
+        if !self.written {
+            // the archive can be resumed now, it is better kept when interrupted
+            signals::keep();
+            self.written = true;
+        }
+        Ok(())
+    }
+
+    /// Removes the checkpoint of a finished archive.
+    pub fn remove(self) {
+        if self.written {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Reads the checkpoint of an archive, the amount of entries and where they end.
+pub fn read(output: &str) -> Result<(u32, u64), String> {
+    let path = path(output);
+    let checkpoint =
+        fs::read_to_string(&path).map_err(|err| format!("could not read {path}: {err}"))?;
+
+    let value = |key: &str| {
+        checkpoint
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .ok_or_else(|| format!("{path} has no {key} in it"))
+    };
+    let entries = u32::try_from(value("entries")?)
+        .map_err(|_| format!("{path} has more entries than an archive can have"))?;
+    let offset = value("offset")?;
+
+    Ok((entries, offset))
+}
+
+pub fn path(output: &str) -> String {
+    format!("{output}.checkpoint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kzip::{
+        archive::{ArchiveReader, Header},
+        codec::Codec,
+    };
+    use std::{env, ffi::OsStr};
+
+    fn header(name: &str) -> Header<'_> {
+        Header {
+            name: OsStr::new(name),
+            created_at: None,
+            modified: 1_700_000_000,
+            meta: &[],
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_last_checkpoint() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("resume.kzip").to_string_lossy().to_string();
+
+        let mut writer = ArchiveWriter::create(&output, 4).unwrap();
+        writer.add(&header("a"), b"a", Codec::Store).unwrap();
+        writer.add(&header("b"), b"b", Codec::Store).unwrap();
+        let checkpoint = (writer.count(), writer.position());
+        let mut checkpoints = Checkpoints {
+            last: Instant::now() - INTERVAL,
+            ..Checkpoints::new(&output)
+        };
+        checkpoints.update(&mut writer).unwrap();
+        // too soon for another one
+        writer.add(&header("c"), b"c", Codec::Store).unwrap();
+        checkpoints.update(&mut writer).unwrap();
+        assert_eq!(read(&output).unwrap(), checkpoint);
+        // interrupted halfway through an entry
+        writer.add(&header("d"), b"d", Codec::Store).unwrap();
+        drop(writer);
+        let length = fs::metadata(&output).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&output)
+            .unwrap()
+            .set_len(length - 1)
+            .unwrap();
+
+        let (count, offset) = checkpoint;
+        let (mut writer, entries) = ArchiveWriter::resume(&output, count, offset).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(writer.count(), 2);
+        assert_eq!(writer.position(), offset);
+        // what was kept is still found to be the same
+        let added = writer.add(&header("c"), b"a", Codec::Store).unwrap();
+        assert_eq!(added.duplicate_of, Some(0));
+        writer.add(&header("d"), b"d", Codec::Store).unwrap();
+        writer.finish().unwrap();
+        checkpoints.remove();
+        assert!(fs::metadata(path(&output)).is_err());
+
+        let mut reader = ArchiveReader::open_strict(&output).unwrap();
+        let entries = reader.entries.clone();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        let contents: Vec<_> = entries
+            .iter()
+            .map(|entry| reader.read(entry).unwrap())
+            .collect();
+        assert_eq!(contents, [b"a", b"b", b"a", b"d"]);
+
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn refuses_checkpoints_it_cannot_use() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir
+            .join("bad-checkpoint.kzip")
+            .to_string_lossy()
+            .to_string();
+
+        assert!(read(&output).is_err());
+        for checkpoint in [
+            "",
+            "entries 1\n",
+            "entries x\noffset 10\n",
+            "entries 5000000000\noffset 10\n",
+        ] {
+            fs::write(path(&output), checkpoint).unwrap();
+            assert!(read(&output).is_err(), "{checkpoint:?}");
+        }
+        fs::write(path(&output), "offset 10\nentries 1\n").unwrap();
+        assert_eq!(read(&output).unwrap(), (1, 10));
+
+        // an archive that got shorter than its checkpoint was changed since
+        let mut writer = ArchiveWriter::create(&output, 1).unwrap();
+        writer.add(&header("a"), b"a", Codec::Store).unwrap();
+        let length = writer.position();
+        writer.finish().unwrap();
+        assert!(ArchiveWriter::resume(&output, 1, length + 1).is_err());
+
+        fs::remove_file(path(&output)).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    sync::RwLock,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::sniff::{sniff, Kind};
+
+// how many bytes --algo auto looks at to decide
+const AUTO_SAMPLE: usize = 64 * 1024;
+// deflate can't do better than about 1032 to 1, this leaves some room for the
+// zlib header and checksum of tiny files
+const MAX_ZLIB_RATIO: u64 = 1100;
+// the most that is allocated for content before it is decompressed, the
+// length in the header could be made up
+const MAX_PREALLOCATE: u64 = 64 * 1024 * 1024;
+// ids below this are kept for the codecs built into kzip
+pub const FIRST_CUSTOM_ID: u8 = 16;
+
+static REGISTRY: RwLock<Vec<&'static dyn CustomCodec>> = RwLock::new(Vec::new());
+
+/// A compressor or filter kzip doesn't have built in. Once registered it can
+/// be picked with `--algo` by its name, and entries written with it store its
+/// id, so it has to be registered again before reading them.
+pub trait CustomCodec: Send + Sync {
+    /// What entries store to say they use this codec, from `FIRST_CUSTOM_ID`
+    /// up.
+    fn id(&self) -> u8;
+
+    /// What `--algo` calls it.
+    fn name(&self) -> &str;
+
+    /// Compresses all of `input` into `output`, at a level from `--level`,
+    /// which is 0 when none was given.
+    fn encode(&self, level: u8, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()>;
+
+    /// Decompresses the content while it is read.
+    fn decoder<'a>(&self, level: u8, input: Box<dyn Read + 'a>) -> Box<dyn Read + 'a>;
+}
+
+/// Makes a codec known to kzip, for as long as the process runs.
+pub fn register(codec: Box<dyn CustomCodec>) -> Result<(), String> {
+    if codec.id() < FIRST_CUSTOM_ID {
+        return Err(format!(
+            "{} can't use id {}, ids below {FIRST_CUSTOM_ID} are kzip's own",
+            codec.name(),
+            codec.id()
+        ));
+    }
+
+    let mut registry = REGISTRY.write().unwrap();
+    if let Some(other) = registry
+        .iter()
+        .find(|other| other.id() == codec.id() || other.name() == codec.name())
+    {
+        return Err(format!(
+            "{} ({}) is already registered as {} ({})",
+            codec.name(),
+            codec.id(),
+            other.name(),
+            other.id()
+        ));
+    }
+    // codecs are registered once and kept until kzip exits
+    registry.push(Box::leak(codec));
+
+    Ok(())
+}
+
+fn registered(find: impl Fn(&dyn CustomCodec) -> bool) -> Option<&'static dyn CustomCodec> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|codec| find(**codec))
+        .copied()
+}
+
+/// How the content of a file is compressed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    /// Stored as it is.
+    Store,
+    /// zlib at a level from 0 to 9, kzip has always used 9.
+    Zlib(u32),
+    /// A registered `CustomCodec`, by its id, and the level.
+    Custom(u8, u8),
+}
+
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::Zlib(9)
+    }
+}
+
+impl Codec {
+    /// Reads `--algo` and `--level`, i.e. `zlib` and `6`.
+    pub fn parse(algo: &str, level: Option<&str>) -> Result<Codec, String> {
+        let level = match level {
+            Some(level) => Some(
+                level
+                    .parse::<u32>()
+                    .map_err(|_| format!("{level} is not a compression level"))?,
+            ),
+            None => None,
+        };
+
+        match algo.to_ascii_lowercase().as_str() {
+            "store" | "none" => Ok(Codec::Store),
+            "zlib" | "gzip" | "deflate" => match level.unwrap_or(9) {
+                level @ 0..=9 => Ok(Codec::Zlib(level)),
+                level => Err(format!("zlib levels go from 0 to 9, not {level}")),
+            },
+            "zstd" | "lz4" | "xz" | "brotli" => Err(format!(
+                "{algo} is not built into this version of kzip, use zlib or store"
+            )),
+            name => match registered(|codec| codec.name().eq_ignore_ascii_case(name)) {
+                Some(codec) => Ok(Codec::Custom(codec.id(), level.unwrap_or(0) as u8)),
+                None => Err(format!("unknown algorithm {algo}, use zlib or store")),
+            },
+        }
+    }
+
+    /// Every codec there is, store, each zlib level and the registered ones,
+    /// which are listed at level 0.
+    pub fn all() -> Vec<Codec> {
+        let mut codecs = vec![Codec::Store];
+        codecs.extend((0..=9).map(Codec::Zlib));
+        codecs.extend(
+            REGISTRY
+                .read()
+                .unwrap()
+                .iter()
+                .map(|codec| Codec::Custom(codec.id(), 0)),
+        );
+        codecs
+    }
+
+    /// Picks a codec for a file from what it starts with and how well its
+    /// start compresses. Content that is already compressed (images, video,
+    /// archives) is stored, content that barely compresses gets the fastest
+    /// zlib level and everything else the best one.
+    pub fn auto(content: &[u8]) -> Codec {
+        if content.is_empty() {
+            return Codec::default();
+        }
+        // no need to try compressing a png or a zip
+        if let Kind::Compressed(_) = sniff(content) {
+            return Codec::Store;
+        }
+
+        let sample = &content[..content.len().min(AUTO_SAMPLE)];
+        // zlib writing into memory doesn't fail
+        let compressed = Codec::Zlib(1)
+            .compress(sample)
+            .map_or(sample.len(), |compressed| compressed.len());
+        let ratio = compressed as f64 / sample.len() as f64;
+        if ratio > 0.9 {
+            Codec::Store
+        } else if ratio > 0.7 {
+            Codec::Zlib(1)
+        } else {
+            Codec::default()
+        }
+    }
+
+    /// How the codec is stored in an entry, the kind and the level.
+    pub fn id(&self) -> (u8, u8) {
+        match self {
+            Codec::Zlib(level) => (0, *level as u8),
+            Codec::Store => (1, 0),
+            Codec::Custom(id, level) => (*id, *level),
+        }
+    }
+
+    /// The codec of an id, None if it isn't built in or registered.
+    pub fn from_id(id: u8, level: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::Zlib(level as u32)),
+            1 => Some(Codec::Store),
+            id => registered(|codec| codec.id() == id).map(|_| Codec::Custom(id, level)),
+        }
+    }
+
+    /// Fails if a registered codec can't encode the content.
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Store => Ok(bytes.to_vec()),
+            Codec::Zlib(level) => {
+                let mut e = ZlibEncoder::new(Vec::new(), Compression::new(*level));
+                e.write_all(bytes)?;
+                e.finish()
+            }
+            Codec::Custom(id, level) => {
+                let mut compressed = Vec::new();
+                custom(*id).encode(*level, &mut &bytes[..], &mut compressed)?;
+                Ok(compressed)
+            }
+        }
+    }
+
+    /// Fails if the content is broken or doesn't end up `file_size` long.
+    /// Memory grows with what is actually decompressed, never past one byte
+    /// more than `file_size`, so a header can't make kzip allocate more.
+    pub fn decompress(&self, bytes: &[u8], file_size: u64) -> io::Result<Vec<u8>> {
+        let content = match self {
+            Codec::Store => bytes.to_vec(),
+            Codec::Custom(..) => {
+                let mut buf = Vec::with_capacity(file_size.min(MAX_PREALLOCATE) as usize);
+                self.decoder(bytes)
+                    .take(file_size.saturating_add(1))
+                    .read_to_end(&mut buf)?;
+                buf
+            }
+            Codec::Zlib(_) => {
+                let mut decompressor = flate2::Decompress::new(true);
+                let mut buf = Vec::with_capacity(file_size.min(MAX_PREALLOCATE) as usize);
+                loop {
+                    let input = &bytes[decompressor.total_in() as usize..];
+                    let status = decompressor
+                        .decompress_vec(input, &mut buf, flate2::FlushDecompress::None)
+                        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                    if status != flate2::Status::Ok || buf.len() as u64 > file_size {
+                        break;
+                    }
+                    if buf.len() == buf.capacity() {
+                        // doubling, up to one byte more than there should be
+                        let wanted = (buf.capacity() as u64 * 2)
+                            .max(64 * 1024)
+                            .min(file_size.saturating_add(1));
+                        buf.reserve_exact((wanted as usize).saturating_sub(buf.len()));
+                    }
+                }
+                buf
+            }
+        };
+
+        if content.len() as u64 != file_size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the content is {} bytes instead of {file_size}",
+                    content.len()
+                ),
+            ));
+        }
+        Ok(content)
+    }
+
+    /// Whether `length` compressed bytes can hold `unpacked_length` bytes,
+    /// for codecs where that is known.
+    pub fn can_unpack(&self, length: u64, unpacked_length: u64) -> bool {
+        match self {
+            // encrypted entries are a little longer than what they hold
+            Codec::Store => unpacked_length <= length,
+            Codec::Zlib(_) => unpacked_length <= length.saturating_mul(MAX_ZLIB_RATIO),
+            Codec::Custom(..) => true,
+        }
+    }
+
+    /// Decompresses the content while it is read.
+    pub fn decoder<'a>(&self, reader: impl Read + 'a) -> Box<dyn Read + 'a> {
+        match self {
+            Codec::Store => Box::new(reader),
+            Codec::Zlib(_) => Box::new(ZlibDecoder::new(reader)),
+            Codec::Custom(id, level) => custom(*id).decoder(*level, Box::new(reader)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Codec::Store => "store".to_string(),
+            Codec::Zlib(level) => format!("zlib level {level}"),
+            Codec::Custom(id, level) => format!("{} level {level}", custom(*id).name()),
+        }
+    }
+}
+
+// a Custom codec is only made for registered ids, and nothing is unregistered
+fn custom(id: u8) -> &'static dyn CustomCodec {
+    registered(|codec| codec.id() == id).unwrap()
+}
@@ -0,0 +1,124 @@
+use std::{io, path::Path, process::exit, time::Instant};
+
+use crate::{
+    codec::Codec,
+    estimate::sample,
+    filter::{self, Filter},
+    list_files,
+    utils::{format_byte, format_ratio, option_value, positionals},
+};
+
+// how much bigger than the smallest archive the recommended setting may be
+const TOLERANCE: f64 = 1.02;
+
+/// `kzip compare-codecs -i dir`
+///
+/// Compresses the same samples `kzip estimate` picks with every codec and
+/// level, and shows how big the archive would get and how long it would
+/// take with each. The fastest one within 2% of the smallest is recommended.
+pub fn run(args: &[String]) {
+    let mut value_options = vec!["--input", "-i"];
+    value_options.extend(filter::OPTIONS);
+    let input = match option_value(args, &["--input", "-i"])
+        .or_else(|| positionals(&args[2..], &value_options).first().cloned())
+    {
+        Some(input) => input,
+        None => {
+            println!("kzip: compare-codecs needs an input, i.e. kzip compare-codecs -i dir");
+            exit(1);
+        }
+    };
+
+    let filter = match Filter::from_args(args, Path::new(&input)) {
+        Ok(filter) => filter,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let mut files = Vec::new();
+    list_files(Path::new(&input), &filter, &mut files);
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    let blocks = match sample(&files) {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            println!("kzip: could not read {input}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+    let sampled: u64 = blocks.iter().map(|block| block.len() as u64).sum();
+    if sampled == 0 {
+        println!("kzip: {input} has nothing to compress");
+        exit(1);
+    }
+
+    println!("Files: {}", files.len());
+    println!("Input: {}", format_byte(total as f64));
+    println!("Sample: {}", format_byte(sampled as f64));
+    println!();
+    println!(
+        "{:<16}  {:>10}  {:>7}  {:>10}  {:>10}",
+        "Codec", "Archive", "Ratio", "Speed", "Time"
+    );
+
+    // (codec, estimated archive size, estimated seconds)
+    let mut results = Vec::new();
+    for codec in Codec::all() {
+        let started = Instant::now();
+        let packed = blocks
+            .iter()
+            .map(|block| Ok(codec.compress(block)?.len() as u64))
+            .sum::<io::Result<u64>>();
+        let packed = match packed {
+            Ok(packed) => packed,
+            Err(err) => {
+                println!("kzip: could not compress with {}", codec.name());
+                println!("{:#?}", err);
+                exit(1);
+            }
+        };
+        // timers can't tell apart what is too fast to measure
+        let seconds = started.elapsed().as_secs_f64().max(1e-6);
+
+        let archive = (total as f64 * packed as f64 / sampled as f64) as u64;
+        let speed = sampled as f64 / seconds;
+        let time = total as f64 / speed;
+        println!(
+            "{:<16}  {:>10}  {:>7}  {:>8}/s  {:>9.1}s",
+            codec.name(),
+            format_byte(archive as f64),
+            format_ratio(packed, sampled),
+            format_byte(speed),
+            time
+        );
+        results.push((codec, archive, time));
+    }
+
+    let smallest = results
+        .iter()
+        .map(|(_, archive, _)| *archive)
+        .min()
+        .unwrap();
+    let (codec, _, _) = results
+        .iter()
+        .filter(|(_, archive, _)| *archive as f64 <= smallest as f64 * TOLERANCE)
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+        .unwrap();
+    println!();
+    println!(
+        "Recommended: {} (the fastest within 2% of the smallest archive)",
+        options(codec)
+    );
+    exit(0);
+}
+
+/// The options that pick `codec`, i.e. `--algo zlib --level 6`.
+fn options(codec: &Codec) -> String {
+    match codec.name().split_once(" level ") {
+        Some((algo, level)) => format!("--algo {algo} --level {level}"),
+        None => format!("--algo {}", codec.name()),
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+};
+
+use sha2::{Digest, Sha256};
+
+/*
+    Encrypted entries are compressed first, then encrypted with ChaCha20 and
+    authenticated with HMAC-SHA256 over everything stored:
+
+    salt (16 bytes) | nonce (12 bytes) | encrypted content | tag (32 bytes)
+
+    The tag also covers the header of the entry the content is for, as the
+    caller gives it, so content can't be moved to another entry, or the
+    entry's header changed, without the password.
+
+    The keys come from the password and the salt with PBKDF2-HMAC-SHA256. One
+    salt is used for every entry written with the same `Password`, so the slow
+    part only happens once per archive.
+*/
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 32;
+/// How much longer content gets by being encrypted.
+pub const OVERHEAD: usize = SALT_LENGTH + NONCE_LENGTH + TAG_LENGTH;
+const ITERATIONS: u32 = 100_000;
+/// The most that can be encrypted at once, the block counter is 32 bits and
+/// starts at 1. Past it the same key stream would be used again.
+pub const MAX_LENGTH: u64 = (u32::MAX as u64) * 64;
+
+/// The password for encrypted entries, with the keys derived from it so far.
+pub struct Password {
+    password: Vec<u8>,
+    // salt -> (cipher key, mac key)
+    keys: HashMap<[u8; SALT_LENGTH], ([u8; 32], [u8; 32])>,
+    // what new entries are encrypted with
+    salt: Option<[u8; SALT_LENGTH]>,
+}
+
+impl Password {
+    pub fn new(password: &str) -> Password {
+        Password {
+            password: password.as_bytes().to_vec(),
+            keys: HashMap::new(),
+            salt: None,
+        }
+    }
+
+    /// Encrypts `content` for the entry `header` stands for.
+    pub fn encrypt(&mut self, content: &[u8], header: &[u8]) -> io::Result<Vec<u8>> {
+        check_length(content.len() as u64, ErrorKind::InvalidInput)?;
+        let salt = match self.salt {
+            Some(salt) => salt,
+            None => {
+                let mut salt = [0; SALT_LENGTH];
+                random_bytes(&mut salt)?;
+                self.salt = Some(salt);
+                salt
+            }
+        };
+        let mut nonce = [0; NONCE_LENGTH];
+        random_bytes(&mut nonce)?;
+        let (cipher_key, mac_key) = self.keys(&salt);
+
+        let mut encrypted = Vec::with_capacity(content.len() + OVERHEAD);
+        encrypted.extend_from_slice(&salt);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(content);
+        chacha20(
+            &cipher_key,
+            &nonce,
+            &mut encrypted[SALT_LENGTH + NONCE_LENGTH..],
+        );
+        let tag = tag(&mac_key, header, &encrypted);
+        encrypted.extend_from_slice(&tag);
+
+        Ok(encrypted)
+    }
+
+    /// Fails if the password is wrong, or the content or `header` isn't
+    /// what it was encrypted with.
+    pub fn decrypt(&mut self, encrypted: &[u8], header: &[u8]) -> io::Result<Vec<u8>> {
+        if encrypted.len() < OVERHEAD {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the encrypted content is cut off",
+            ));
+        }
+        check_length((encrypted.len() - OVERHEAD) as u64, ErrorKind::InvalidData)?;
+        let (stored, stored_tag) = encrypted.split_at(encrypted.len() - TAG_LENGTH);
+        let salt: [u8; SALT_LENGTH] = stored[..SALT_LENGTH].try_into().unwrap();
+        let nonce: [u8; NONCE_LENGTH] = stored[SALT_LENGTH..SALT_LENGTH + NONCE_LENGTH]
+            .try_into()
+            .unwrap();
+        let (cipher_key, mac_key) = self.keys(&salt);
+
+        // every byte is compared, so the time taken doesn't tell how much matched
+        let difference = tag(&mac_key, header, stored)
+            .iter()
+            .zip(stored_tag)
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        if difference != 0 {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "the password is wrong or the content or its header was changed",
+            ));
+        }
+
+        let mut content = stored[SALT_LENGTH + NONCE_LENGTH..].to_vec();
+        chacha20(&cipher_key, &nonce, &mut content);
+        Ok(content)
+    }
+
+    fn keys(&mut self, salt: &[u8; SALT_LENGTH]) -> ([u8; 32], [u8; 32]) {
+        let password = &self.password;
+        *self.keys.entry(*salt).or_insert_with(|| {
+            let master = pbkdf2(password, salt, ITERATIONS);
+            (
+                hmac(&master, b"kzip cipher key"),
+                hmac(&master, b"kzip mac key"),
+            )
+        })
+    }
+}
+
+fn check_length(length: u64, kind: ErrorKind) -> io::Result<()> {
+    if length > MAX_LENGTH {
+        return Err(io::Error::new(
+            kind,
+            format!("only up to {MAX_LENGTH} bytes can be encrypted at once"),
+        ));
+    }
+    Ok(())
+}
+
+// the header goes first with its length, so where it ends can't be moved
+fn tag(mac_key: &[u8; 32], header: &[u8], stored: &[u8]) -> [u8; 32] {
+    hmac_parts(
+        mac_key,
+        &[&(header.len() as u64).to_be_bytes(), header, stored],
+    )
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    hmac_parts(key, &[message])
+}
+
+// the HMAC of the parts one after another
+fn hmac_parts(key: &[u8], message: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    for part in message {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+
+    outer.finalize().into()
+}
+
+// only the first block, 32 bytes are all kzip needs
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut message = salt.to_vec();
+    message.extend_from_slice(&1u32.to_be_bytes());
+    let mut block = hmac(password, &message);
+    let mut key = block;
+    for _ in 1..iterations {
+        block = hmac(password, &block);
+        for (key, byte) in key.iter_mut().zip(block) {
+            *key ^= byte;
+        }
+    }
+
+    key
+}
+
+/// Encrypts or decrypts `data` in place, as described in RFC 8439 with the
+/// block counter starting at 1. `data` can't be longer than `MAX_LENGTH`.
+fn chacha20(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH], data: &mut [u8]) {
+    assert!(data.len() as u64 <= MAX_LENGTH);
+    let word = |bytes: &[u8], index: usize| {
+        u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap())
+    };
+    let mut state = [0u32; 16];
+    // "expand 32-byte k"
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        state[4 + i] = word(key, i);
+    }
+    for i in 0..3 {
+        state[13 + i] = word(nonce, i);
+    }
+
+    for (counter, chunk) in data.chunks_mut(64).enumerate() {
+        state[12] = counter as u32 + 1;
+        let mut block = state;
+        for _ in 0..10 {
+            quarter_round(&mut block, 0, 4, 8, 12);
+            quarter_round(&mut block, 1, 5, 9, 13);
+            quarter_round(&mut block, 2, 6, 10, 14);
+            quarter_round(&mut block, 3, 7, 11, 15);
+            quarter_round(&mut block, 0, 5, 10, 15);
+            quarter_round(&mut block, 1, 6, 11, 12);
+            quarter_round(&mut block, 2, 7, 8, 13);
+            quarter_round(&mut block, 3, 4, 9, 14);
+        }
+
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            let key_word = block[i / 4].wrapping_add(state[i / 4]);
+            *byte ^= key_word.to_le_bytes()[i % 4];
+        }
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+#[cfg(unix)]
+fn random_bytes(bytes: &mut [u8]) -> io::Result<()> {
+    use std::{fs::File, io::Read};
+
+    File::open("/dev/urandom")?.read_exact(bytes)
+}
+
+#[cfg(windows)]
+fn random_bytes(bytes: &mut [u8]) -> io::Result<()> {
+    #[link(name = "advapi32")]
+    extern "system" {
+        // RtlGenRandom
+        fn SystemFunction036(buffer: *mut u8, length: u32) -> u8;
+    }
+
+    if unsafe { SystemFunction036(bytes.as_mut_ptr(), bytes.len() as u32) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn chacha20_matches_rfc_8439() {
+        // 2.4.2, which starts at block 1 as well
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        chacha20(&key, &nonce, &mut data);
+        assert_eq!(
+            hex(&data),
+            "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+             f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+             07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+             5af90bbf74a35be6b40b8eedf2785e42874d"
+        );
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // a key longer than a block is hashed first
+        assert_eq!(
+            hex(&hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hmac_parts(b"key", &[b"split ", b"message"]),
+            hmac(b"key", b"split message")
+        );
+    }
+
+    #[test]
+    fn pbkdf2_matches_known_answers() {
+        // the SHA-256 versions of the RFC 6070 tests
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 1)),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 2)),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 4096)),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let mut password = Password::new("secret");
+        let encrypted = password.encrypt(b"content", b"header").unwrap();
+        assert_eq!(encrypted.len(), b"content".len() + OVERHEAD);
+        assert_eq!(OVERHEAD as u64, crate::archive::ENCRYPTION_OVERHEAD);
+        assert_eq!(password.decrypt(&encrypted, b"header").unwrap(), b"content");
+        assert_eq!(
+            Password::new("secret")
+                .decrypt(&encrypted, b"header")
+                .unwrap(),
+            b"content"
+        );
+
+        // a new nonce every time
+        assert_ne!(password.encrypt(b"content", b"header").unwrap(), encrypted);
+        let empty = password.encrypt(b"", b"").unwrap();
+        assert_eq!(password.decrypt(&empty, b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn fails_for_the_wrong_password_or_header() {
+        let mut password = Password::new("secret");
+        let encrypted = password.encrypt(b"content", b"header").unwrap();
+
+        let err = Password::new("wrong")
+            .decrypt(&encrypted, b"header")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let err = password.decrypt(&encrypted, b"other").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        // where the header ends is part of what is checked
+        let encrypted = password.encrypt(b"content", b"").unwrap();
+        assert!(password.decrypt(&encrypted, b"c").is_err());
+    }
+
+    #[test]
+    fn fails_for_changed_bytes() {
+        let mut password = Password::new("secret");
+        let encrypted = password.encrypt(b"some content", b"header").unwrap();
+        // the salt, nonce, content and tag
+        for i in [
+            0,
+            SALT_LENGTH,
+            SALT_LENGTH + NONCE_LENGTH,
+            encrypted.len() - 1,
+        ] {
+            let mut changed = encrypted.clone();
+            changed[i] ^= 1;
+            assert!(password.decrypt(&changed, b"header").is_err(), "byte {i}");
+        }
+
+        let err = password
+            .decrypt(&encrypted[..OVERHEAD - 1], b"header")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(password
+            .decrypt(&encrypted[..encrypted.len() - 1], b"header")
+            .is_err());
+    }
+
+    #[test]
+    fn stops_before_the_counter_wraps() {
+        // the last block is numbered u32::MAX
+        assert_eq!(MAX_LENGTH / 64, u32::MAX as u64);
+        assert!(check_length(MAX_LENGTH, ErrorKind::InvalidInput).is_ok());
+        let err = check_length(MAX_LENGTH + 1, ErrorKind::InvalidInput).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fs, process::exit};
+
+use crate::{
+    archive::ArchiveReader,
+    history,
+    pattern::glob_match,
+    rewrite::{self, Change},
+    utils::{format_byte, positionals},
+};
+
+// above this share of the archive being removed, writing a fresh copy costs
+// about as much as moving what is left, and can't leave a broken archive
+const VACUUM_LIMIT: f64 = 0.5;
+
+/// `kzip delete archive.kzip 'glob'... [--rewrite]`
+///
+/// Removes every file matching one of the globs. When little is removed the
+/// rest is moved down inside of the archive, otherwise or with `--rewrite`
+/// the archive is written again next to itself.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &[]);
+    let [input, globs @ ..] = &positionals[..] else {
+        println!(
+            "kzip: delete needs an archive and globs, i.e. kzip delete archive.kzip 'logs/**'"
+        );
+        exit(1);
+    };
+    if globs.is_empty() {
+        println!(
+            "kzip: delete needs an archive and globs, i.e. kzip delete archive.kzip 'logs/**'"
+        );
+        exit(1);
+    }
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+    let force_rewrite = args.iter().any(|arg| arg == "--rewrite");
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    let size_before = fs::metadata(input)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    let deleted: Vec<bool> = reader
+        .entries
+        .iter()
+        .map(|entry| globs.iter().any(|glob| glob_match(glob, &entry.name)))
+        .collect();
+    let count = deleted.iter().filter(|deleted| **deleted).count();
+    if count == 0 {
+        println!("kzip: nothing in {input} matches {}", globs.join(" "));
+        exit(1);
+    }
+
+    let mut removed_bytes = 0;
+    let mut needs_rewrite = force_rewrite;
+    for (index, entry) in reader.entries.iter().enumerate() {
+        if deleted[index] {
+            if is_verbose {
+                println!("kzip: deleting {}", entry.name);
+            }
+            if !entry.is_duplicate() {
+                removed_bytes += entry.length;
+            }
+        } else if entry.duplicate_of.is_some_and(|original| deleted[original]) {
+            // the content has to move to the duplicate, which only a rewrite can do
+            needs_rewrite = true;
+        }
+    }
+    needs_rewrite |= removed_bytes as f64 > size_before as f64 * VACUUM_LIMIT;
+
+    history::record(&mut reader.meta, "delete", count);
+    // the history makes the header bigger, which only fits if enough is
+    // removed right at the start
+    needs_rewrite |= !rewrite::can_vacuum(&reader, &deleted);
+
+    let result = if needs_rewrite {
+        let changes = reader
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !deleted[*index])
+            .map(|(_, entry)| Change::keep(entry))
+            .collect();
+        rewrite::rewrite(input, reader, changes)
+    } else {
+        rewrite::vacuum(reader, &deleted)
+    };
+    if let Err(err) = result {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    let size_after = fs::metadata(input)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    println!(
+        "kzip: Deleted {count} file(s) from {input}, {} reclaimed ({})",
+        format_byte(size_before.saturating_sub(size_after) as f64),
+        if needs_rewrite {
+            "written again"
+        } else {
+            "compacted in place"
+        }
+    );
+    exit(0);
+}
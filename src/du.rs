@@ -0,0 +1,89 @@
+use std::{collections::HashMap, path, process::exit};
+
+use crate::{
+    archive::ArchiveReader,
+    utils::{format_byte, option_value, positionals},
+};
+
+/// `kzip du archive.kzip [--depth N]`
+///
+/// Like `du -h` for the files in an archive: the unpacked and packed size of
+/// every directory with everything in it, subdirectories before the
+/// directory they are in and `.` for the whole archive last. `--depth` only
+/// shows directories up to that many levels in, 0 only shows the total.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--depth"]);
+    let Some(input) = positionals.first() else {
+        println!("kzip: du needs an archive, i.e. kzip du archive.kzip --depth 2");
+        exit(1);
+    };
+    let depth = match option_value(args, &["--depth"]).map(|depth| depth.parse::<usize>()) {
+        None => usize::MAX,
+        Some(Ok(depth)) => depth,
+        Some(Err(_)) => {
+            println!("kzip: --depth has to be a number");
+            exit(1);
+        }
+    };
+
+    let reader = match ArchiveReader::open(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    // directory -> (unpacked, packed), every directory an entry is in
+    // counts it, up to the whole archive
+    let mut sizes: HashMap<Vec<&str>, (u64, u64)> = HashMap::new();
+    for entry in &reader.entries {
+        // duplicates take up no space of their own
+        let packed = if entry.is_duplicate() {
+            0
+        } else {
+            entry.length
+        };
+        let mut parts: Vec<&str> = entry.name.split(path::MAIN_SEPARATOR).collect();
+        parts.pop();
+        for end in 0..=parts.len() {
+            let size = sizes.entry(parts[..end].to_vec()).or_default();
+            size.0 += entry.unpacked_length;
+            size.1 += packed;
+        }
+    }
+
+    let mut dirs: Vec<_> = sizes.keys().filter(|dir| dir.len() <= depth).collect();
+    dirs.sort();
+
+    println!("{:>10}  {:>10}  Directory", "Unpacked", "Packed");
+    let print = |dir: &Vec<&str>| {
+        let (unpacked, packed) = sizes[dir];
+        let name = if dir.is_empty() {
+            ".".to_string()
+        } else {
+            dir.join(path::MAIN_SEPARATOR_STR)
+        };
+        println!(
+            "{:>10}  {:>10}  {name}",
+            format_byte(unpacked as f64),
+            format_byte(packed as f64)
+        );
+    };
+    // sorted, a directory comes right before what is in it, it is printed
+    // once everything in it is
+    let mut open: Vec<&Vec<&str>> = Vec::new();
+    for dir in dirs {
+        while let Some(parent) = open.last() {
+            if dir.starts_with(parent) {
+                break;
+            }
+            print(open.pop().unwrap());
+        }
+        open.push(dir);
+    }
+    while let Some(dir) = open.pop() {
+        print(dir);
+    }
+    exit(0);
+}
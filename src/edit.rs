@@ -0,0 +1,159 @@
+use std::{
+    fs,
+    io::Write,
+    process::{exit, Command},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader},
+    history,
+    rewrite::{self, Change, Content},
+    signals,
+    utils::{option_value, positionals, TempFile},
+};
+
+/// `kzip edit archive.kzip path --from file` or `--with editor`
+///
+/// Replaces the content of one entry, either with a file or with whatever is
+/// saved in an editor that is opened on the current content.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--from", "--with"]);
+    let [input, name] = &positionals[..] else {
+        println!(
+            "kzip: edit needs an archive and a path, i.e. kzip edit archive.kzip path --from file"
+        );
+        exit(1);
+    };
+    let name = parse_file_path(name.to_string());
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    let entry = match reader.get(&name) {
+        Some(entry) if entry.special => {
+            println!("kzip: {name} is a special file, it has no content to edit");
+            exit(1);
+        }
+        Some(entry) => entry.clone(),
+        None => {
+            println!("kzip: {name} does not exist in {input}");
+            exit(1);
+        }
+    };
+
+    let content = match (
+        option_value(args, &["--from"]),
+        option_value(args, &["--with"]),
+    ) {
+        (Some(from), _) => match fs::read(&from) {
+            Ok(content) => content,
+            Err(err) => {
+                println!("kzip: could not read {from}");
+                println!("{:#?}", err);
+                exit(1);
+            }
+        },
+        (None, Some(editor)) => {
+            let current = match reader.read(&entry) {
+                Ok(content) => content,
+                Err(err) => {
+                    println!("kzip: could not read {name} from {input}");
+                    println!("{:#?}", err);
+                    exit(1);
+                }
+            };
+            let content = edit_with(&editor, &entry.name, &current);
+            if content == current {
+                println!("kzip: {name} did not change");
+                exit(0);
+            }
+            content
+        }
+        (None, None) => {
+            println!("kzip: edit needs --from file or --with editor, i.e. --with \"$EDITOR\"");
+            exit(1);
+        }
+    };
+
+    let modified = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(entry.modified);
+    let changes = reader
+        .entries
+        .iter()
+        .map(|other| {
+            let mut change = Change::keep(other);
+            if other.name == entry.name {
+                change.modified = modified;
+                change.content = Content::New(content.clone(), entry.codec);
+            }
+            change
+        })
+        .collect();
+
+    history::record(&mut reader.meta, "edit", 1);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    println!("kzip: Replaced {name} in {input}");
+    exit(0);
+}
+
+/// Opens `content` in an editor and returns what was saved.
+fn edit_with(editor: &str, name: &str, content: &[u8]) -> Vec<u8> {
+    let temp_file = TempFile::create(name).and_then(|(temp_file, mut file)| {
+        signals::remove_on_interrupt(&temp_file.path);
+        file.write_all(content)?;
+        Ok(temp_file)
+    });
+    let temp_file = match temp_file {
+        Ok(temp_file) => temp_file,
+        Err(err) => {
+            println!("kzip: could not write a temporary file for {name}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+    let temp_path = temp_file.path.clone();
+
+    // i.e. "code --wait"
+    let mut editor_args = editor.split_whitespace();
+    let status = Command::new(editor_args.next().unwrap_or_default())
+        .args(editor_args)
+        .arg(&temp_path)
+        .status();
+    let edited = fs::read(&temp_path);
+    drop(temp_file);
+    signals::keep();
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            println!("kzip: {editor} exited with {status}, leaving the archive as it is");
+            exit(1);
+        }
+        Err(err) => {
+            println!("kzip: could not run {editor}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+
+    match edited {
+        Ok(edited) => edited,
+        Err(err) => {
+            println!("kzip: could not read back {}", temp_path.display());
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::borrow::Cow;
+
+// how much of a file is looked at to tell whether it is text, the same as git
+const SNIFF_LENGTH: usize = 8000;
+
+/// The line endings text files get when extracting with `--eol`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+impl Eol {
+    /// Reads `lf`, `crlf` or `native`, which is crlf on windows.
+    pub fn parse(eol: &str) -> Option<Eol> {
+        match eol {
+            "lf" => Some(Eol::Lf),
+            "crlf" => Some(Eol::Crlf),
+            "native" if cfg!(windows) => Some(Eol::Crlf),
+            "native" => Some(Eol::Lf),
+            _ => None,
+        }
+    }
+
+    /// Changes the line endings of `content` if it looks like text, binary
+    /// files are returned as they are.
+    pub fn apply<'a>(&self, content: &'a [u8]) -> Cow<'a, [u8]> {
+        if !is_text(content) {
+            return Cow::Borrowed(content);
+        }
+
+        let has_cr = content.contains(&b'\r');
+        match self {
+            Eol::Lf if !has_cr => Cow::Borrowed(content),
+            Eol::Lf => Cow::Owned(to_lf(content)),
+            Eol::Crlf => {
+                let content = if has_cr {
+                    Cow::Owned(to_lf(content))
+                } else {
+                    Cow::Borrowed(content)
+                };
+                let mut converted = Vec::with_capacity(content.len() + content.len() / 32);
+                for byte in content.iter() {
+                    if *byte == b'\n' {
+                        converted.push(b'\r');
+                    }
+                    converted.push(*byte);
+                }
+                Cow::Owned(converted)
+            }
+        }
+    }
+}
+
+/// Whether a file is text, which like for git means there is no NUL byte
+/// near its start.
+pub fn is_text(content: &[u8]) -> bool {
+    !content[..content.len().min(SNIFF_LENGTH)].contains(&0)
+}
+
+fn to_lf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, byte) in content.iter().enumerate() {
+        if *byte == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(*byte);
+    }
+    converted
+}
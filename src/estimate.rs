@@ -0,0 +1,166 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    process::exit,
+};
+
+use crate::{
+    codec::Codec,
+    filter::{self, Filter},
+    list_files,
+    utils::{format_byte, format_ratio, option_value, positionals},
+};
+
+// how many blocks are compressed, and how big they are
+const SAMPLES: u64 = 64;
+const BLOCK: u64 = 64 * 1024;
+// what every entry adds besides its data: flags, name length, dates and lengths
+const ENTRY_OVERHEAD: u64 = 1 + 4 + 8 + 8 + 8 + 8;
+
+/// `kzip estimate -i dir [--algo zlib] [--level 9]`
+///
+/// Guesses how big an archive of `dir` would be, by only compressing samples.
+pub fn run(args: &[String]) {
+    let mut value_options = vec!["--input", "-i", "--algo", "--level"];
+    value_options.extend(filter::OPTIONS);
+    let input = match option_value(args, &["--input", "-i"])
+        .or_else(|| positionals(&args[2..], &value_options).first().cloned())
+    {
+        Some(input) => input,
+        None => {
+            println!("kzip: estimate needs an input, i.e. kzip estimate -i dir --algo zlib");
+            exit(1);
+        }
+    };
+
+    let algo = option_value(args, &["--algo"]).unwrap_or("zlib".to_string());
+    let level = option_value(args, &["--level"]);
+    let codec = match Codec::parse(&algo, level.as_deref()) {
+        Ok(codec) => codec,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let filter = match Filter::from_args(args, Path::new(&input)) {
+        Ok(filter) => filter,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let mut files = Vec::new();
+    list_files(Path::new(&input), &filter, &mut files);
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    let estimate = match estimate(&files, codec) {
+        Ok(estimate) => estimate,
+        Err(err) => {
+            println!("kzip: could not read {input}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    println!("Files: {}", files.len());
+    println!("Input: {}", format_byte(total as f64));
+    println!("Algorithm: {}", codec.name());
+    println!(
+        "Estimated archive: {} (± {}, {} of the input)",
+        format_byte(estimate.packed as f64),
+        format_byte(estimate.margin as f64),
+        format_ratio(estimate.packed, total)
+    );
+    exit(0);
+}
+
+/// A guess of how big an archive is going to be.
+pub struct Estimate {
+    pub packed: u64,
+    /// How far off `packed` may be, 95% of the time.
+    pub margin: u64,
+}
+
+/*
+    Instead of compressing everything, blocks are picked at even distances
+    over all of the files, as if they were one long file. The ratio of those
+    blocks is used for everything, and how much the blocks differ from each
+    other gives the margin.
+
+    Duplicate files are counted as if they weren't, so the real archive can
+    only be smaller because of them.
+*/
+pub fn estimate(files: &[(OsString, u64)], codec: Codec) -> io::Result<Estimate> {
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+    let overhead: u64 = files
+        .iter()
+        .map(|(name, _)| ENTRY_OVERHEAD + name.len() as u64)
+        .sum();
+    if total == 0 {
+        return Ok(Estimate {
+            packed: overhead,
+            margin: 0,
+        });
+    }
+
+    let ratios: Vec<f64> = sample(files)?
+        .iter()
+        .map(|block| Ok(codec.compress(block)?.len() as f64 / block.len() as f64))
+        .collect::<io::Result<_>>()?;
+
+    if ratios.is_empty() {
+        return Ok(Estimate {
+            packed: total + overhead,
+            margin: 0,
+        });
+    }
+
+    let count = ratios.len() as f64;
+    let mean = ratios.iter().sum::<f64>() / count;
+    let variance = ratios.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / count;
+    let margin = 1.96 * variance.sqrt() / count.sqrt();
+
+    Ok(Estimate {
+        packed: (total as f64 * mean) as u64 + overhead,
+        margin: (total as f64 * margin) as u64,
+    })
+}
+
+/// The blocks `estimate` compresses, none of them empty.
+pub fn sample(files: &[(OsString, u64)]) -> io::Result<Vec<Vec<u8>>> {
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+    let samples = SAMPLES.min(total.div_ceil(BLOCK));
+    let mut blocks = Vec::with_capacity(samples as usize);
+    let mut file_index = 0;
+    let mut file_start = 0;
+
+    for i in 0..samples {
+        let position = total / samples * i + total / samples / 2;
+        while file_start + files[file_index].1 <= position {
+            file_start += files[file_index].1;
+            file_index += 1;
+        }
+
+        let (name, size) = &files[file_index];
+        // small files are compressed as a whole, like they will be
+        let offset = if *size <= BLOCK {
+            0
+        } else {
+            (position - file_start).min(size - BLOCK)
+        };
+
+        let mut block = Vec::new();
+        let mut file = File::open(name)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(BLOCK).read_to_end(&mut block)?;
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+    }
+
+    Ok(blocks)
+}
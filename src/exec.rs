@@ -0,0 +1,122 @@
+use std::{
+    io::{self, ErrorKind},
+    process::{exit, Command, Stdio},
+};
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader, Entry},
+    signals,
+    utils::{positionals, TempFile},
+};
+
+/// `kzip exec archive.kzip path [--temp-file] -- cmd args...`
+///
+/// Pipes one entry into the stdin of a command. With `--temp-file` the entry
+/// is written to a temporary file instead, and `{}` in the arguments is
+/// replaced with its path (or the path is added as the last argument).
+pub fn run(args: &[String]) {
+    let separator = args.iter().position(|arg| arg == "--");
+    let (kzip_args, command) = match separator {
+        Some(separator) if separator + 1 < args.len() => {
+            (&args[2..separator], &args[separator + 1..])
+        }
+        _ => {
+            println!("kzip: exec needs a command, i.e. kzip exec archive.kzip path -- less");
+            exit(1);
+        }
+    };
+
+    let positionals = positionals(kzip_args, &[]);
+    if positionals.len() < 2 {
+        println!(
+            "kzip: exec needs an archive and a path, i.e. kzip exec archive.kzip path -- less"
+        );
+        exit(1);
+    }
+
+    let input = &positionals[0];
+    let name = parse_file_path(positionals[1].to_string());
+    let use_temp_file = kzip_args.iter().any(|arg| arg == "--temp-file");
+
+    let mut reader = match ArchiveReader::open(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let entry = match reader.get(&name) {
+        Some(entry) => entry.clone(),
+        None => {
+            println!("kzip: {name} does not exist in {input}");
+            exit(1);
+        }
+    };
+
+    let result = if use_temp_file {
+        run_with_temp_file(&mut reader, &entry, command)
+    } else {
+        run_with_stdin(&mut reader, &entry, command)
+    };
+
+    match result {
+        Ok(code) => exit(code),
+        Err(err) => {
+            println!("kzip: could not run {}", command[0]);
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+}
+
+fn run_with_stdin(
+    reader: &mut ArchiveReader,
+    entry: &Entry,
+    command: &[String],
+) -> io::Result<i32> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let mut content = reader.stream(entry)?;
+    if let Err(err) = io::copy(&mut content, &mut stdin) {
+        // the command is allowed to stop reading early, like a pager does
+        if err.kind() != ErrorKind::BrokenPipe {
+            return Err(err);
+        }
+    }
+    drop(stdin);
+
+    Ok(child.wait()?.code().unwrap_or(1))
+}
+
+fn run_with_temp_file(
+    reader: &mut ArchiveReader,
+    entry: &Entry,
+    command: &[String],
+) -> io::Result<i32> {
+    let (temp_file, mut file) = TempFile::create(&entry.name)?;
+    let temp_path = &temp_file.path;
+
+    signals::remove_on_interrupt(temp_path);
+    io::copy(&mut reader.stream(entry)?, &mut file)?;
+    drop(file);
+
+    let temp_path_str = temp_path.to_string_lossy().to_string();
+    let mut command_args: Vec<String> = command[1..]
+        .iter()
+        .map(|arg| arg.replace("{}", &temp_path_str))
+        .collect();
+    if !command[1..].iter().any(|arg| arg.contains("{}")) {
+        command_args.push(temp_path_str);
+    }
+
+    let status = Command::new(&command[0]).args(command_args).status();
+    drop(temp_file);
+    signals::keep();
+
+    Ok(status?.code().unwrap_or(1))
+}
@@ -0,0 +1,109 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader, Entry},
+    crypto::Password,
+    signals,
+    utils::{option_value, password, positionals},
+};
+
+/// `kzip extract archive.kzip path --to destination`
+///
+/// Writes one file to exactly where `--to` says, without the directories it
+/// is in inside the archive. When the destination is a directory the file
+/// keeps its name, like `cp` does.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--to", "--password-file"]);
+    let Some(to) = option_value(args, &["--to"]) else {
+        println!(
+            "kzip: extract needs a destination, i.e. kzip extract a.kzip logs/app.log --to app.log"
+        );
+        exit(1);
+    };
+    if positionals.len() < 2 {
+        println!("kzip: extract needs an archive and a path, i.e. kzip extract a.kzip logs/app.log --to app.log");
+        exit(1);
+    }
+
+    let input = &positionals[0];
+    let name = parse_file_path(positionals[1].to_string());
+
+    let mut reader = match ArchiveReader::open_indexed(input, true) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let entry = match reader.get(&name) {
+        Some(entry) => entry.clone(),
+        None => {
+            println!("kzip: {name} does not exist in {input}");
+            exit(1);
+        }
+    };
+    if entry.special {
+        println!("kzip: {name} is not a regular file, extract it with kzip -x");
+        exit(1);
+    }
+    if entry.encrypted {
+        reader.password = match password(args) {
+            Ok(Some(password)) => Some(Password::new(&password)),
+            Ok(None) => {
+                println!("kzip: {name} is encrypted, set KZIP_PASSWORD or use --password-file");
+                exit(1);
+            }
+            Err(err) => {
+                println!("kzip: {err}");
+                exit(1);
+            }
+        };
+    }
+
+    let destination = destination(&to, &entry);
+    if let Err(err) = write(&mut reader, &entry, &destination) {
+        println!(
+            "kzip: could not extract {name} to {}",
+            destination.display()
+        );
+        println!("{:#?}", err);
+        exit(1);
+    }
+    exit(0);
+}
+
+fn destination(to: &str, entry: &Entry) -> PathBuf {
+    let to = Path::new(to);
+    if !to.is_dir() {
+        return to.to_path_buf();
+    }
+
+    match Path::new(&entry.name).file_name() {
+        Some(file_name) => to.join(file_name),
+        None => to.join(&entry.name),
+    }
+}
+
+fn write(reader: &mut ArchiveReader, entry: &Entry, destination: &Path) -> io::Result<()> {
+    // written next to the file first, so an interrupted extraction doesn't
+    // leave a file behind that looks complete
+    let mut part = destination.as_os_str().to_os_string();
+    part.push(".kzip-part");
+    signals::remove_on_interrupt(&part);
+
+    let result = File::create(&part)
+        .and_then(|mut file| io::copy(&mut reader.stream(entry)?, &mut file))
+        .and_then(|_| fs::rename(&part, destination));
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    signals::keep();
+
+    result.map(|_| ())
+}
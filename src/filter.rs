@@ -0,0 +1,368 @@
+use std::{
+    fs::{File, Metadata},
+    io::Read,
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+use crate::{
+    pattern::glob_match,
+    utils::{option_value, option_values},
+};
+
+/// Every option that decides which files end up in a new archive.
+pub const OPTIONS: [&str; 7] = [
+    "--newer-than",
+    "--older-than",
+    "--newer-mtime-than",
+    "--min-size",
+    "--max-size",
+    "--include-hidden",
+    "--max-depth",
+];
+
+/// Options without a value.
+pub const FLAGS: [&str; 4] = [
+    "--exclude-vcs",
+    "--exclude-caches",
+    "--one-file-system",
+    "--exclude-hidden",
+];
+
+// what a CACHEDIR.TAG has to start with, see https://bford.info/cachedir/
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+// what version control systems keep next to the files, the same list tar uses
+// (.git is a file in worktrees and submodules)
+const VCS_NAMES: [&str; 21] = [
+    ".git",
+    ".gitignore",
+    ".gitattributes",
+    ".gitmodules",
+    ".hg",
+    ".hgignore",
+    ".hgtags",
+    ".svn",
+    "CVS",
+    ".cvsignore",
+    "RCS",
+    "SCCS",
+    ".bzr",
+    ".bzrignore",
+    ".bzrtags",
+    "_darcs",
+    ".arch-ids",
+    "{arch}",
+    "=RELEASE-ID",
+    "=meta-update",
+    "=update",
+];
+
+/// Decides which files and directories are zipped while walking the input.
+#[derive(Default)]
+pub struct Filter {
+    // unix timestamps the modified time is compared to
+    newer_than: Option<u64>,
+    older_than: Option<u64>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    // how many levels of directories are read, 1 only zips the files in the input
+    max_depth: Option<usize>,
+    exclude_vcs: bool,
+    exclude_caches: bool,
+    exclude_hidden: bool,
+    // globs for hidden names that are zipped anyway, i.e. .config
+    include_hidden: Vec<String>,
+    // the file system of the input, set by --one-file-system
+    device: Option<u64>,
+}
+
+impl Filter {
+    pub fn from_args(args: &[String], input: &Path) -> Result<Filter, String> {
+        let mut filter = Filter {
+            exclude_vcs: args.iter().any(|arg| arg == "--exclude-vcs"),
+            exclude_caches: args.iter().any(|arg| arg == "--exclude-caches"),
+            exclude_hidden: args.iter().any(|arg| arg == "--exclude-hidden"),
+            include_hidden: option_values(args, &["--include-hidden"]),
+            ..Filter::default()
+        };
+
+        if args.iter().any(|arg| arg == "--one-file-system") {
+            filter.device = input.metadata().ok().and_then(|metadata| device(&metadata));
+        }
+
+        if let Some(date) = option_value(args, &["--newer-than"]) {
+            filter.newer_than = Some(parse_date(&date)?);
+        }
+        if let Some(date) = option_value(args, &["--older-than"]) {
+            filter.older_than = Some(parse_date(&date)?);
+        }
+        if let Some(file) = option_value(args, &["--newer-mtime-than"]) {
+            let modified = Path::new(&file)
+                .metadata()
+                .ok()
+                .and_then(|metadata| modified(&metadata))
+                .ok_or_else(|| format!("could not read when {file} was modified"))?;
+            filter.newer_than = Some(modified);
+        }
+        if let Some(size) = option_value(args, &["--min-size"]) {
+            filter.min_size = Some(parse_size(&size)?);
+        }
+        if let Some(size) = option_value(args, &["--max-size"]) {
+            filter.max_size = Some(parse_size(&size)?);
+        }
+        if let Some(depth) = option_value(args, &["--max-depth"]) {
+            match depth.parse() {
+                Ok(depth) if depth > 0 => filter.max_depth = Some(depth),
+                _ => return Err(format!("{depth} is not a depth, use 1 or more")),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Whether a directory is read, `depth` being 1 for the directories
+    /// directly in the input.
+    pub fn include_dir(&self, path: &Path, metadata: &Metadata, depth: usize) -> bool {
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return false;
+        }
+        if self.device.is_some() && device(metadata) != self.device {
+            return false;
+        }
+        if self.exclude_vcs && is_vcs(path) {
+            return false;
+        }
+        if self.exclude_caches && is_cache(path) {
+            return false;
+        }
+        if self.is_excluded_hidden(path) {
+            return false;
+        }
+
+        true
+    }
+
+    pub fn include_file(&self, path: &Path, metadata: &Metadata) -> bool {
+        if self.exclude_vcs && is_vcs(path) {
+            return false;
+        }
+        if self.is_excluded_hidden(path) {
+            return false;
+        }
+        if self
+            .min_size
+            .is_some_and(|min_size| metadata.len() < min_size)
+            || self
+                .max_size
+                .is_some_and(|max_size| metadata.len() > max_size)
+        {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let modified = modified(metadata).unwrap_or_default();
+            if self
+                .newer_than
+                .is_some_and(|newer_than| modified <= newer_than)
+            {
+                return false;
+            }
+            if self
+                .older_than
+                .is_some_and(|older_than| modified >= older_than)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn is_excluded_hidden(&self, path: &Path) -> bool {
+        if !self.exclude_hidden {
+            return false;
+        }
+
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        name.starts_with('.')
+            && !self
+                .include_hidden
+                .iter()
+                .any(|pattern| glob_match(pattern, &name))
+    }
+}
+
+fn is_vcs(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| VCS_NAMES.contains(&name))
+}
+
+/// Whether a directory is marked as a cache, like cargo's target directory.
+fn is_cache(path: &Path) -> bool {
+    let mut signature = [0; CACHEDIR_SIGNATURE.len()];
+    File::open(path.join("CACHEDIR.TAG"))
+        .and_then(|mut tag| tag.read_exact(&mut signature))
+        .is_ok_and(|_| signature == CACHEDIR_SIGNATURE)
+}
+
+/// The device a file is on, directories on another one are mount points.
+#[cfg(unix)]
+fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+fn modified(metadata: &Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Reads a size like `500`, `10k`, `1.5M` or `1G`, where k is 1024 bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let invalid = || format!("{size} is not a size, use i.e. 500, 10k, 1.5M or 1G");
+
+    let lower = size.trim().to_ascii_lowercase();
+    let number = lower.trim_end_matches(['b', 'i']);
+    let (number, unit) = match number.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&number[..index], unit),
+        _ => (number, ' '),
+    };
+    let multiplier: u64 = match unit {
+        ' ' => 1,
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        't' => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if number < 0.0 {
+        return Err(invalid());
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Reads `2024-01-01`, `2024-01-01T10:30:00` (both UTC) or a unix timestamp
+/// like `@1704067200`.
+pub fn parse_date(date: &str) -> Result<u64, String> {
+    let invalid = || format!("{date} is not a date, use i.e. 2024-01-01 or 2024-01-01T10:30:00");
+
+    if let Some(timestamp) = date.strip_prefix('@') {
+        return timestamp.parse().map_err(|_| invalid());
+    }
+
+    let (day, time) = match date.split_once(['T', ' ']) {
+        Some((day, time)) => (day, Some(time)),
+        None => (date, None),
+    };
+
+    let day: Vec<&str> = day.split('-').collect();
+    let [year, month, day] = day[..] else {
+        return Err(invalid());
+    };
+    let month = month
+        .parse::<u8>()
+        .ok()
+        .and_then(|month| Month::try_from(month).ok())
+        .ok_or_else(invalid)?;
+    let day = Date::from_calendar_date(
+        year.parse().map_err(|_| invalid())?,
+        month,
+        day.parse().map_err(|_| invalid())?,
+    )
+    .map_err(|_| invalid())?;
+
+    let time = match time {
+        Some(time) => {
+            let parts: Vec<u8> = time
+                .trim_end_matches('Z')
+                .split(':')
+                .map(|part| part.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            match parts[..] {
+                [hour, minute] => Time::from_hms(hour, minute, 0),
+                [hour, minute, second] => Time::from_hms(hour, minute, second),
+                _ => return Err(invalid()),
+            }
+            .map_err(|_| invalid())?
+        }
+        None => Time::MIDNIGHT,
+    };
+
+    let timestamp = PrimitiveDateTime::new(day, time)
+        .assume_utc()
+        .unix_timestamp();
+    u64::try_from(timestamp).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("10KiB"), Ok(10 * 1024));
+        assert_eq!(parse_size("10kb"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5M"), Ok(1536 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert_eq!(parse_size("2t"), Ok(2 << 40));
+        assert_eq!(parse_size(" 7b "), Ok(7));
+
+        for size in [
+            "", "-1", "-1k", "x", "k", "10x", "10 k", "1.2.3", "nan", "inf",
+        ] {
+            assert!(parse_size(size).is_err(), "{size:?}");
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("2024-01-01"), Ok(1_704_067_200));
+        assert_eq!(parse_date("2024-01-01T10:30:00"), Ok(1_704_105_000));
+        assert_eq!(parse_date("2024-01-01T10:30:00Z"), Ok(1_704_105_000));
+        assert_eq!(parse_date("2024-01-01 10:30"), Ok(1_704_105_000));
+        assert_eq!(parse_date("2024-02-29"), Ok(1_709_164_800));
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("@1704067200"), Ok(1_704_067_200));
+
+        for date in [
+            "",
+            "yesterday",
+            "2024-01",
+            "2024-01-01-01",
+            "2024-13-01",
+            "2024-00-10",
+            "2023-02-29",
+            "2024-01-32",
+            "2024-01-01T10",
+            "2024-01-01T24:00",
+            "2024-01-01T10:60",
+            "2024-01-01T10:30:00:00",
+            "1969-12-31",
+            "@",
+            "@-1",
+            "@x",
+        ] {
+            assert!(parse_date(date).is_err(), "{date:?}");
+        }
+    }
+}
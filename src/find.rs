@@ -0,0 +1,129 @@
+use std::{collections::HashMap, process::exit};
+
+use crate::{
+    archive::{ArchiveReader, Entry},
+    crypto::Password,
+    grep,
+    pattern::{glob_match, Regex},
+    utils::{option_value, password, positionals},
+};
+
+/// `kzip find-in archives... [--name 'invoice*.pdf'] [--content 'regex'] [-i]`
+///
+/// Looks for files across many archives, i.e. months of backups. Archives
+/// are opened through their index when they have a fresh one, which also
+/// knows the hash of every file, so content that was already searched in
+/// another archive isn't decompressed again.
+pub fn run(args: &[String]) {
+    let archives = positionals(&args[2..], &["--name", "--content", "--password-file"]);
+    let name = option_value(args, &["--name"]);
+    let content = option_value(args, &["--content"]);
+    if archives.is_empty() || (name.is_none() && content.is_none()) {
+        println!("kzip: find-in needs archives and --name or --content, i.e. kzip find-in backups/*.kzip --name 'invoice*.pdf'");
+        exit(1);
+    }
+
+    let ignore_case = args.iter().any(|arg| arg == "-i" || arg == "--ignore-case");
+    let regex = content.map(|content| match Regex::with_options(&content, ignore_case) {
+        Ok(regex) => regex,
+        Err(err) => {
+            println!("kzip: invalid pattern: {err}");
+            exit(1);
+        }
+    });
+    let password = match password(args) {
+        Ok(password) => password,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    // whether content with a hash matched, from the indexes
+    let mut searched: HashMap<String, bool> = HashMap::new();
+    let mut matches = 0;
+    let mut archives_with_matches = 0;
+    let mut unreadable = 0;
+    for archive in &archives {
+        let mut reader = match ArchiveReader::open_indexed(archive, false) {
+            Ok(reader) => reader,
+            Err(err) => {
+                println!("kzip: skipping {archive}: {err}");
+                unreadable += 1;
+                continue;
+            }
+        };
+        reader.password = password.as_deref().map(Password::new);
+
+        let mut found = false;
+        for entry in reader.entries.clone() {
+            if entry.special {
+                continue;
+            }
+            if let Some(name) = &name {
+                if !name_matches(name, &entry) {
+                    continue;
+                }
+            }
+            if let Some(regex) = &regex {
+                // encrypted files are only searched with the password
+                if entry.encrypted && reader.password.is_none() {
+                    continue;
+                }
+                let hash = reader.hash(&entry);
+                let known = hash.as_ref().and_then(|hash| searched.get(hash));
+                let is_match = match known {
+                    Some(is_match) => *is_match,
+                    None => {
+                        let is_match = reader
+                            .stream(&entry)
+                            .and_then(|stream| grep::any_line_matches(stream, regex));
+                        let is_match = match is_match {
+                            Ok(is_match) => is_match,
+                            Err(err) => {
+                                println!(
+                                    "kzip: could not read {} from {archive}: {err}",
+                                    entry.name
+                                );
+                                continue;
+                            }
+                        };
+                        if let Some(hash) = hash {
+                            searched.insert(hash, is_match);
+                        }
+                        is_match
+                    }
+                };
+                if !is_match {
+                    continue;
+                }
+            }
+
+            println!("{archive}: {}", entry.name);
+            matches += 1;
+            found = true;
+        }
+        if found {
+            archives_with_matches += 1;
+        }
+    }
+
+    println!(
+        "kzip: {matches} match(es) in {archives_with_matches} of {} archive(s)",
+        archives.len()
+    );
+    if unreadable > 0 {
+        println!("kzip: {unreadable} archive(s) could not be read");
+    }
+    exit(if matches > 0 { 0 } else { 1 });
+}
+
+/// Like find, `--name` matches the file name, or the whole path when it has
+/// a `/` in it.
+fn name_matches(pattern: &str, entry: &Entry) -> bool {
+    if pattern.contains('/') {
+        return glob_match(pattern, &entry.name);
+    }
+    let file_name = entry.name.rsplit('/').next().unwrap_or(&entry.name);
+    glob_match(pattern, file_name)
+}
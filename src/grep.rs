@@ -0,0 +1,363 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::exit,
+};
+
+use crate::{
+    archive::ArchiveReader,
+    crypto::Password,
+    eol,
+    pattern::{glob_match, Regex},
+    utils::{option_value, password, positionals},
+};
+
+// how much of an entry is looked at to tell if it is binary
+const SNIFF_LENGTH: u64 = 8000;
+// lines longer than this are searched in parts with the same line number,
+// so a file without new lines doesn't have to fit into memory
+const MAX_LINE_LENGTH: u64 = 1024 * 1024;
+
+/// `kzip grep archive.kzip 'regex' [glob] [-n] [-i] [-A 2] [-B 2] [-C 2]`
+///
+/// Entries are decompressed while they are searched, a line at a time, so
+/// searching multi-GB logs takes as much memory as small files. Entries
+/// with a NUL byte near their start are binary, like grep only that they
+/// match is shown for them.
+pub fn run(args: &[String]) {
+    let positionals = positionals(
+        &args[2..],
+        &[
+            "--password-file",
+            "-A",
+            "--after-context",
+            "-B",
+            "--before-context",
+            "-C",
+            "--context",
+        ],
+    );
+    if positionals.len() < 2 {
+        println!(
+            "kzip: grep needs an archive and a pattern, i.e. kzip grep archive.kzip 'regex' [glob]"
+        );
+        exit(1);
+    }
+
+    let input = &positionals[0];
+    let glob = positionals.get(2);
+    let line_numbers = args.iter().any(|arg| arg == "-n" || arg == "--line-number");
+    let ignore_case = args.iter().any(|arg| arg == "-i" || arg == "--ignore-case");
+    let context = context_lines(args, &["-C", "--context"]).unwrap_or(0);
+    let after = context_lines(args, &["-A", "--after-context"]).unwrap_or(context);
+    let before = context_lines(args, &["-B", "--before-context"]).unwrap_or(context);
+
+    let regex = match Regex::with_options(&positionals[1], ignore_case) {
+        Ok(regex) => regex,
+        Err(err) => {
+            println!("kzip: invalid pattern: {err}");
+            exit(1);
+        }
+    };
+
+    let mut reader = match ArchiveReader::open(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    reader.password = match password(args) {
+        Ok(password) => password.as_deref().map(Password::new),
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let options = Options {
+        line_numbers,
+        before,
+        after,
+    };
+    let mut found = false;
+    // groups of lines are separated by -- when there is context, like grep,
+    // also between entries
+    let mut printed = false;
+    let mut out = io::stdout().lock();
+    for entry in reader.entries.clone() {
+        // encrypted files are only searched with the password
+        if entry.special || (entry.encrypted && reader.password.is_none()) {
+            continue;
+        }
+        if let Some(glob) = glob {
+            if !glob_match(glob, &entry.name) {
+                continue;
+            }
+        }
+
+        let stream = match reader.stream(&entry) {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("kzip: could not read {} from {input}", entry.name);
+                println!("{:#?}", err);
+                exit(1);
+            }
+        };
+        match search(
+            &entry.name,
+            stream,
+            &regex,
+            &options,
+            &mut printed,
+            &mut out,
+        ) {
+            Ok(matched) => found |= matched,
+            Err(err) => {
+                println!("kzip: could not read {} from {input}", entry.name);
+                println!("{:#?}", err);
+            }
+        }
+    }
+
+    exit(if found { 0 } else { 1 });
+}
+
+/// How matches are shown.
+struct Options {
+    line_numbers: bool,
+    // lines of context before and after matches
+    before: usize,
+    after: usize,
+}
+
+/// Writes the lines of `content` that match to `out`, with the context
+/// `options` asks for, and returns whether any did. `printed` is whether
+/// anything was written before, to know when groups need a `--` between
+/// them.
+fn search(
+    name: &str,
+    mut content: impl Read,
+    regex: &Regex,
+    options: &Options,
+    printed: &mut bool,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let Options {
+        line_numbers,
+        before,
+        after,
+    } = *options;
+
+    let mut start = Vec::new();
+    (&mut content).take(SNIFF_LENGTH).read_to_end(&mut start)?;
+    let is_binary = !eol::is_text(&start);
+    let mut content = BufReader::new(start.as_slice().chain(content));
+
+    let mut found = false;
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    // the part that was read ended before the end of its line
+    let mut is_partial = false;
+    // the last lines that weren't printed, for -B
+    let mut previous: VecDeque<(usize, String)> = VecDeque::with_capacity(before);
+    // how many lines after the last match are still printed, for -A
+    let mut remaining_after = 0;
+    // the last line that was printed, to know where a group starts
+    let mut last_printed: Option<usize> = None;
+    loop {
+        line.clear();
+        match (&mut content)
+            .take(MAX_LINE_LENGTH)
+            .read_until(b'\n', &mut line)?
+        {
+            0 => break,
+            _ if is_partial => {}
+            _ => line_number += 1,
+        }
+
+        is_partial = !line.ends_with(b"\n");
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        if regex.is_match(text) {
+            found = true;
+            if is_binary {
+                writeln!(out, "Binary file {name} matches")?;
+                break;
+            }
+
+            let first = previous.front().map_or(line_number, |(number, _)| *number);
+            let starts_group = last_printed.is_none_or(|last| last + 1 < first);
+            if *printed && starts_group && (before > 0 || after > 0) {
+                writeln!(out, "--")?;
+            }
+            for (number, text) in previous.drain(..) {
+                write_line(out, name, number, &text, '-', line_numbers)?;
+            }
+            write_line(out, name, line_number, text, ':', line_numbers)?;
+            *printed = true;
+            last_printed = Some(line_number);
+            remaining_after = after;
+        } else if remaining_after > 0 {
+            write_line(out, name, line_number, text, '-', line_numbers)?;
+            last_printed = Some(line_number);
+            remaining_after -= 1;
+        } else if before > 0 {
+            if previous.len() == before {
+                previous.pop_front();
+            }
+            previous.push_back((line_number, text.to_string()));
+        }
+    }
+
+    Ok(found)
+}
+
+/// A line as grep shows it, with `separator` being `:` for matches and `-`
+/// for context.
+fn write_line(
+    out: &mut impl Write,
+    name: &str,
+    line_number: usize,
+    text: &str,
+    separator: char,
+    line_numbers: bool,
+) -> io::Result<()> {
+    if line_numbers {
+        writeln!(out, "{name}{separator}{line_number}{separator}{text}")
+    } else {
+        writeln!(out, "{name}{separator}{text}")
+    }
+}
+
+/// The number of lines given to one of `names`, i.e. 3 for `-C 3`.
+fn context_lines(args: &[String], names: &[&str]) -> Option<usize> {
+    let lines = option_value(args, names)?;
+    match lines.parse::<usize>() {
+        Ok(lines) => Some(lines),
+        Err(_) => {
+            println!("kzip: {} has to be a number of lines", names[0]);
+            exit(1);
+        }
+    }
+}
+
+/// Whether any line of `content` matches, for searching without showing
+/// the lines.
+pub fn any_line_matches(content: impl Read, regex: &Regex) -> io::Result<bool> {
+    let mut content = BufReader::new(content);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if (&mut content)
+            .take(MAX_LINE_LENGTH)
+            .read_until(b'\n', &mut line)?
+            == 0
+        {
+            return Ok(false);
+        }
+        let text = String::from_utf8_lossy(&line);
+        if regex.is_match(text.trim_end_matches(['\n', '\r'])) {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep(content: &[u8], pattern: &str, before: usize, after: usize) -> (bool, String) {
+        let options = Options {
+            line_numbers: true,
+            before,
+            after,
+        };
+        let regex = Regex::with_options(pattern, false).unwrap();
+        let mut out = Vec::new();
+        let found = search("f", content, &regex, &options, &mut false, &mut out).unwrap();
+        (found, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn shows_matching_lines() {
+        let (found, out) = grep(b"one\ntwo\r\nthree", "t", 0, 0);
+        assert!(found);
+        assert_eq!(out, "f:2:two\nf:3:three\n");
+        assert_eq!(grep(b"one\n", "x", 0, 0), (false, String::new()));
+    }
+
+    #[test]
+    fn separates_groups_of_context() {
+        let content = b"a\nmatch\nb\nc\nd\ne\nmatch\nf\n";
+        let (_, out) = grep(content, "match", 1, 1);
+        assert_eq!(
+            out,
+            "f-1-a\nf:2:match\nf-3-b\n--\nf-6-e\nf:7:match\nf-8-f\n"
+        );
+
+        // groups that touch or overlap are one group, without lines twice
+        let (_, out) = grep(content, "match", 2, 2);
+        assert_eq!(
+            out,
+            "f-1-a\nf:2:match\nf-3-b\nf-4-c\nf-5-d\nf-6-e\nf:7:match\nf-8-f\n"
+        );
+        let (_, out) = grep(b"match\nmatch\nx\n", "match", 1, 1);
+        assert_eq!(out, "f:1:match\nf:2:match\nf-3-x\n");
+
+        // no separators without context
+        let (_, out) = grep(content, "match", 0, 0);
+        assert_eq!(out, "f:2:match\nf:7:match\n");
+    }
+
+    #[test]
+    fn separates_entries_with_context() {
+        let options = Options {
+            line_numbers: false,
+            before: 0,
+            after: 1,
+        };
+        let regex = Regex::with_options("x", false).unwrap();
+        let mut printed = false;
+        let mut out = Vec::new();
+        search("a", &b"x\n"[..], &regex, &options, &mut printed, &mut out).unwrap();
+        search("b", &b"x\n"[..], &regex, &options, &mut printed, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a:x\n--\nb:x\n");
+    }
+
+    #[test]
+    fn searches_long_lines_in_parts() {
+        let mut content = vec![b'a'; 3 * MAX_LINE_LENGTH as usize];
+        content.extend(b"b\nnext\n");
+        // the last part is what is after the last whole MiB, still line 1
+        let (found, out) = grep(&content, "^b$", 0, 0);
+        assert!(found);
+        assert_eq!(out, "f:1:b\n");
+        let (_, out) = grep(&content, "next", 0, 0);
+        assert_eq!(out, "f:2:next\n");
+    }
+
+    #[test]
+    fn only_says_binary_files_match() {
+        let mut content = b"match\0\nmatch\n".to_vec();
+        let (found, out) = grep(&content, "match", 0, 0);
+        assert!(found);
+        assert_eq!(out, "Binary file f matches\n");
+
+        // a NUL after what is looked at doesn't make it binary
+        content = vec![b'x'; SNIFF_LENGTH as usize];
+        content.extend(b"\nmatch\0\n");
+        let (_, out) = grep(&content, "match", 0, 0);
+        assert_eq!(out, "f:2:match\0\n");
+
+        assert_eq!(grep(b"\0\0", "match", 0, 0), (false, String::new()));
+    }
+
+    #[test]
+    fn any_line_matches_stops_at_the_first() {
+        let regex = Regex::with_options("^b", false).unwrap();
+        assert!(any_line_matches(&b"a\nb\n"[..], &regex).unwrap());
+        assert!(!any_line_matches(&b"ab\n"[..], &regex).unwrap());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    env,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use time::OffsetDateTime;
+
+use crate::{archive::ArchiveReader, utils::positionals, VERSION};
+
+/*
+    Every change to an archive adds a line to its meta, under keys kzip
+    reserves:
+
+        kzip.history.1=1760572800	rename	3	0.0.8
+
+    that is when, what, how many entries it touched and the version of kzip
+    that did it, separated by tabs. Older versions of kzip keep these like
+    any other key when they change an archive, they just don't add to them.
+*/
+
+/// The start of every key kzip keeps to itself.
+pub const RESERVED: &str = "kzip.";
+
+const PREFIX: &str = "kzip.history.";
+
+/// `kzip history archive.kzip`
+///
+/// Lists every change made to an archive since it was created, the oldest
+/// first.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &[]);
+    let Some(input) = positionals.first() else {
+        println!("kzip: history needs an archive, i.e. kzip history archive.kzip");
+        exit(1);
+    };
+
+    let reader = match ArchiveReader::open(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let history = history(&reader.meta);
+    if history.is_empty() {
+        println!("kzip: {input} hasn't been changed since it was created");
+        exit(0);
+    }
+
+    println!(
+        "{:<20}  {:<12}  {:>7}  Version",
+        "Date", "Operation", "Entries"
+    );
+    for [time, operation, entries, version] in history {
+        let date = time
+            .parse::<i64>()
+            .ok()
+            .and_then(|time| OffsetDateTime::from_unix_timestamp(time).ok())
+            .map(|date| {
+                let (hour, minute, second) = date.to_hms();
+                format!("{} {hour:02}:{minute:02}:{second:02}", date.date())
+            })
+            .unwrap_or(time.to_string());
+        println!("{date:<20}  {operation:<12}  {entries:>7}  {version}");
+    }
+    exit(0);
+}
+
+/// Adds `operation` on `entries` entries to the history in `meta`.
+pub fn record(meta: &mut Vec<(String, String)>, operation: &str, entries: usize) {
+    // a reproducible build changing an archive shouldn't make it differ
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let time = match env::var("SOURCE_DATE_EPOCH").map(|epoch| epoch.parse::<u64>()) {
+        Ok(Ok(epoch)) => now.min(epoch),
+        _ => now,
+    };
+
+    let number = history(meta).len() + 1;
+    meta.push((
+        format!("{PREFIX}{number}"),
+        format!("{time}\t{operation}\t{entries}\t{VERSION}"),
+    ));
+}
+
+/// Drops the history from `meta`, for archives made of other archives.
+pub fn forget(meta: &mut Vec<(String, String)>) {
+    meta.retain(|(key, _)| !key.starts_with(PREFIX));
+}
+
+/// Every recorded change, the oldest first.
+fn history(meta: &[(String, String)]) -> Vec<[&str; 4]> {
+    let mut history: Vec<(u64, [&str; 4])> = meta
+        .iter()
+        .filter_map(|(key, value)| {
+            let number = key.strip_prefix(PREFIX)?.parse().ok()?;
+            let mut fields = value.splitn(4, '\t');
+            let line = [(); 4].map(|_| fields.next().unwrap_or_default());
+            Some((number, line))
+        })
+        .collect();
+    history.sort_by_key(|(number, _)| *number);
+    history.into_iter().map(|(_, line)| line).collect()
+}
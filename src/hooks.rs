@@ -0,0 +1,54 @@
+use std::process::{exit, Command};
+
+/*
+    --pre-cmd runs before kzip reads anything and --post-cmd once the archive
+    is finished or extracted, both through the shell. What kzip did is passed
+    in environment variables:
+
+    KZIP_HOOK          pre or post
+    KZIP_MODE          create or extract
+    KZIP_INPUT         the input directory, file or archive
+    KZIP_OUTPUT        the archive or directory being written
+    KZIP_STATUS        complete, or partial when files were left out (post)
+    KZIP_FILES         how many files were zipped (post, create)
+    KZIP_SKIPPED       how many files could not be read (post, create)
+    KZIP_INPUT_SIZE    the size of the files in bytes (post, create)
+    KZIP_ARCHIVE_SIZE  the size of the archive in bytes (post, create)
+    KZIP_SHA256        the checksum of the archive (post, create)
+
+    A pre command that fails stops kzip before anything is written, a post
+    command that fails makes kzip exit with 1.
+*/
+
+/// Runs `command` with the `KZIP_` variables, exiting when it fails.
+pub fn run(hook: &str, command: &str, env: &[(&str, String)]) {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command).env("KZIP_HOOK", hook);
+    for (name, value) in env {
+        shell.env(format!("KZIP_{name}"), value);
+    }
+
+    match shell.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            match status.code() {
+                Some(code) => println!("kzip: --{hook}-cmd failed with exit code {code}"),
+                None => println!("kzip: --{hook}-cmd was stopped by a signal"),
+            }
+            exit(1);
+        }
+        Err(err) => {
+            println!("kzip: could not run --{hook}-cmd {command}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+}
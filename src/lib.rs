@@ -0,0 +1,34 @@
+//! The kzip archive format, for reading and writing .kzip files from other
+//! programs. The `kzip` command is built on top of this.
+//!
+//! Compressors kzip doesn't have can be added with `codec::register`, i.e.
+//! before opening archives that were written with them.
+//!
+//! Only flate2 is needed for reading, the other parts are features:
+//!
+//! - `reader`: `ArchiveReader`
+//! - `writer`: `ArchiveWriter`
+//! - `encryption`: encrypted entries, see `crypto`
+//! - `index`: indexes kept next to archives for reading them faster, see
+//!   `sidecar`
+//! - `serde`: `Serialize` and `Deserialize` for entries, codecs, special
+//!   files and timings
+//! - `cli`: the kzip command itself, the default
+//!
+//! i.e. `kzip = { version = "0.0.8", default-features = false, features = ["reader"] }`
+
+pub mod archive;
+pub mod codec;
+#[cfg(feature = "encryption")]
+pub mod crypto;
+pub mod normalize;
+pub mod options;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "index")]
+pub mod sidecar;
+pub mod sniff;
+pub mod special;
+pub mod vfs;
+
+pub const VERSION: &str = "0.0.8";
@@ -0,0 +1,60 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::utils::iso_date;
+
+/*
+    --log-file writes one line for everything kzip does, whatever the
+    verbosity is, i.e.
+
+    2024-05-01T10:00:00Z added name="src/main.rs" size=4120 packed=1380
+    2024-05-01T10:00:01Z skipped name=src/broken error="No such file or directory (os error 2)"
+
+    Values with spaces, quotes or = in them are quoted.
+*/
+
+static LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Starts appending every event to `path`.
+pub fn open(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG.lock().unwrap() = Some(file);
+
+    Ok(())
+}
+
+pub fn event(action: &str, fields: &[(&str, String)]) {
+    let mut log = LOG.lock().unwrap();
+    let Some(file) = log.as_mut() else {
+        return;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    let mut line = format!("{} {action}", iso_date(now));
+    for (key, value) in fields {
+        line.push_str(&format!(" {key}={}", quote(value)));
+    }
+    line.push('\n');
+
+    // the log is only there to help, so a full disk doesn't stop kzip
+    let _ = file.write_all(line.as_bytes());
+}
+
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains([' ', '"', '=', '\n', '\\']) {
+        return value.to_string();
+    }
+
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
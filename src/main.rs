@@ -1,23 +1,132 @@
+mod ads;
+mod analyze;
+mod batch;
+mod browse;
+mod checkpoint;
+mod compare;
+mod delete;
+mod du;
+mod edit;
+mod eol;
+mod estimate;
+mod exec;
+mod extract;
+mod filter;
+mod find;
+mod grep;
+mod history;
+mod hooks;
+mod log;
+mod merge;
+mod meta;
+mod mode;
+mod optimize;
+mod owner;
+mod pattern;
+mod progress;
+mod recompress;
+mod rename;
+mod rewrite;
+mod rotate;
+mod serve;
+mod shard;
+mod signals;
+mod stats;
+mod times;
+mod touch;
+mod transform;
+mod utils;
+mod xattr;
+
 use std::{
-    cmp::{self},
-    collections::HashMap,
-    env,
-    fs::{self, File, Metadata, OpenOptions},
-    io::{BufReader, ErrorKind, Read, Write},
+    borrow::Cow,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs::{self, File, Metadata},
+    io::{self, ErrorKind, Read, Write},
     path::{self, Path},
     process::exit,
-    time::UNIX_EPOCH,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    vec,
 };
 
-use bytebuffer::ByteBuffer;
-use flate2::{write::ZlibEncoder, Compression};
-use sha256::digest;
+use checkpoint::Checkpoints;
+use eol::Eol;
+use filter::Filter;
+use kzip::{
+    archive::{self, ArchiveReader, ArchiveWriter, Header, Timings},
+    codec::{self, Codec},
+    crypto::{self, Password},
+    normalize::Normalization,
+    options::{self, CreateOptions},
+    sidecar,
+    sniff::sniff,
+    special::{self, Special},
+    vfs, VERSION,
+};
+use pattern::glob_match;
+use progress::Progress;
 use time::OffsetDateTime;
+use transform::Transform;
+use utils::{create_dir_if_not_exists, format_byte, format_ratio, long_path, windows_safe_name};
 
-const VERSION: &str = "0.0.8";
+// exit code for when an archive was made, but some files had to be left out
+const EXIT_PARTIAL: i32 = 2;
+// a file compressed to more than this share of its size was hardly worth compressing
+const INCOMPRESSIBLE: f64 = 0.95;
 
 fn help() {
     println!("Command usage: kzip [OPTIONS]...");
+    println!("               kzip <COMMAND> <ARCHIVE> [OPTIONS]...");
+    println!("Commands:");
+    println!(
+        "  serve          Serves an archive over HTTP and read-only WebDAV (--port 8080, --host)"
+    );
+    println!("  browse         Opens an interactive browser for the files in an archive");
+    println!(
+        "  exec           Pipes one file into a command, i.e. kzip exec a.kzip log.txt -- less"
+    );
+    println!("  extract        Writes one file to a path of its own, i.e.");
+    println!("                 kzip extract a.kzip logs/app.log --to /tmp/app.log");
+    println!("  grep           Searches file contents with a regex, i.e. kzip grep a.kzip 'fn \\w+' '**/*.rs'");
+    println!("                 -n shows line numbers, -i ignores case, -A/-B/-C 2 shows lines");
+    println!("                 around matches");
+    println!("  find-in        Finds files by --name or --content across many archives, i.e.");
+    println!("                 kzip find-in backups/*.kzip --name 'invoice*.pdf'");
+    println!("  merge          Merges archives into one, i.e. kzip merge a.kzip b.kzip -o c.kzip");
+    println!("                 --conflict fail|keep-first|keep-last|rename decides on equal names");
+    println!("  estimate       Guesses the archive size by compressing samples of the input,");
+    println!("                 i.e. kzip estimate -i dir --algo zlib --level 6");
+    println!("  compare-codecs Compresses samples of the input with every codec and level to");
+    println!("                 compare sizes and times, i.e. kzip compare-codecs -i dir");
+    println!("  analyze        Finds the files that would only be stored once and how much");
+    println!("                 that saves, without compressing, i.e. kzip analyze -i dir");
+    println!("  rename         Renames a file or directory inside of an archive,");
+    println!("                 i.e. kzip rename archive.kzip old/path new/path");
+    println!("  touch          Sets the stored times of files matching a glob, i.e.");
+    println!("                 kzip touch archive.kzip 'src/**' --mtime 2024-01-01T00:00:00Z");
+    println!("  optimize       Sorts an archive by directory and stores repeated content only");
+    println!("                 once, i.e. kzip optimize archive.kzip [--order dir|name]");
+    println!("  delete         Removes files matching globs from an archive, moving the rest");
+    println!("                 down in place, i.e. kzip delete archive.kzip 'logs/**'");
+    println!("  recompress     Compresses files matching a glob again with another codec,");
+    println!("                 i.e. kzip recompress archive.kzip 'assets/**' --algo store");
+    println!("  meta           Stores key/value pairs with a file inside of an archive, i.e.");
+    println!("                 kzip meta set archive.kzip path commit=abc123, also get,");
+    println!("                 unset and list");
+    println!("  batch          Runs add, delete, rename and meta set lines from a file or");
+    println!("                 stdin with one rewrite, i.e. kzip batch archive.kzip ops.txt");
+    println!("  stats          Shows what takes up the space in an archive, by extension and");
+    println!("                 by top-level directory, i.e. kzip stats a.kzip");
+    println!("  du             Shows the size of every directory in an archive, like du -h,");
+    println!("                 i.e. kzip du a.kzip --depth 2");
+    println!("  history        Lists every change made to an archive since it was created,");
+    println!("                 i.e. kzip history a.kzip");
+    println!("  rotate         Removes old backups, keeping the newest of every day, week or");
+    println!("                 month, i.e. kzip rotate --dir /backups --keep-daily 7");
+    println!("                 --keep-weekly 4 [--keep-monthly 12] [--pattern '*.kzip']");
+    println!("  edit           Replaces the content of a file inside of an archive, i.e.");
+    println!("                 kzip edit archive.kzip path --from file or --with \"$EDITOR\"");
     println!("Options:");
     println!("  --version      Displays the version");
     println!("  --help         Displays this");
@@ -25,7 +134,110 @@ fn help() {
     println!("  --ls      -l   Displays zipped files inside a .kzip file");
     println!("  --input   -i   Tells kzip what the input directory or file is");
     println!("  --output  -o   Tells kzip what the output directory or file is");
-    println!("  --verbose -v   Shows some possibly useful debug information");
+    println!("  --verbose -v   Shows every file, -vv adds sizes and timings, -vvv adds");
+    println!("                 where files are stored and which ones are duplicates");
+    println!("  --ignore-errors  Leaves out files that can't be read instead of stopping,");
+    println!("                 exiting with {EXIT_PARTIAL} when the archive is incomplete");
+    println!("  --algo         How files are compressed: zlib (the default), store, or auto");
+    println!("                 which picks one for every file from how well it compresses");
+    println!("  --level        The zlib level, from 0 to 9 (the default)");
+    println!("  --newer-than / --older-than  Only zips files modified after or before a date,");
+    println!("                 i.e. 2024-01-01 or 2024-01-01T10:30:00 (UTC)");
+    println!("  --newer-mtime-than  Only zips files modified after the given file was");
+    println!("  --min-size / --max-size  Only zips files of at least or at most a size,");
+    println!("                 i.e. --max-size 1G, k, M and G being 1024 based");
+    println!("  --max-depth    How many levels of directories are zipped, 1 only zips the");
+    println!("                 files directly in the input");
+    println!("  --exclude-vcs  Leaves out .git, .hg, .svn and other version control files");
+    println!("  --exclude-caches  Leaves out directories with a CACHEDIR.TAG, like build caches");
+    println!("  --exclude-hidden  Leaves out files and directories starting with a dot,");
+    println!("                 except names matching --include-hidden, which can be given");
+    println!("                 more than once, i.e. --include-hidden .config");
+    println!("  --one-file-system  Stays on the file system of the input, skipping mount points");
+    println!("  --special=skip Leaves out fifos, sockets and device nodes (the default),");
+    println!("                 =store zips them to be created again when extracting as");
+    println!("                 root, and =fail stops on the first one");
+    println!("  --preserve-times=mtime  With -x, sets when files were last modified to the");
+    println!("                 stored time, =all also sets when they were created on");
+    println!("                 windows and macos");
+    println!("  --devices      Zips fifos and device nodes like --special=store, with -x");
+    println!("                 creates device nodes again, which needs root");
+    println!("  --eol          Line endings for text files when extracting: lf, crlf or");
+    println!("                 native, files with a NUL byte are left as they are");
+    println!("  --order dir    Zips the files of a directory before its subdirectories, so");
+    println!("                 extracting writes one directory at a time, none is the default");
+    println!("                 size zips the smallest files first, so progress shows right");
+    println!("                 away and a cancelled run leaves many files complete");
+    println!("  --deterministic  Sorts files by name and stores no times or kzip version,");
+    println!("                 so the same files always make a byte identical archive");
+    println!("                 When SOURCE_DATE_EPOCH is set, no stored time is later than it");
+    println!("  --transform    Renames files with a sed style rule while zipping or");
+    println!("                 extracting, i.e. --transform 's#^build/#dist/#', can be");
+    println!("                 given more than once");
+    println!("  --dry-run      Shows how many files would be zipped and how big the archive");
+    println!("                 would be, or which files extracting would create and");
+    println!("                 overwrite, without writing anything");
+    println!("  --backup=numbered  Renames files that would be overwritten when extracting");
+    println!("                 to name.~1~, name.~2~ and so on");
+    println!("  --checksum-file  Also writes the SHA-256 of a new archive to a file, in the");
+    println!("                 format sha256sum -c reads, i.e. --checksum-file a.kzip.sha256");
+    println!("  --pre-cmd / --post-cmd  Runs a shell command before reading the input and");
+    println!("                 after the archive is written or extracted, with KZIP_INPUT,");
+    println!("                 KZIP_OUTPUT, KZIP_STATUS, KZIP_FILES, KZIP_ARCHIVE_SIZE and");
+    println!("                 more set, i.e. --post-cmd 'notify-send \"$KZIP_OUTPUT\"'");
+    println!("  --meta         Stores a key/value pair with the archive, shown by --ls, i.e.");
+    println!("                 --meta build=1234, can be given more than once");
+    println!("  --encrypt-glob Encrypts files matching a glob, i.e. --encrypt-glob 'secrets/**',");
+    println!("                 names and sizes can still be listed without the password");
+    println!("  --password-file  Reads the password for encrypted files from the first line");
+    println!("                 of a file, KZIP_PASSWORD is used without it");
+    println!("  --log-file     Writes everything that happens to a file, i.e. --log-file kzip.log");
+    println!("  --no-progress  Hides the progress bar, which is also hidden with --verbose");
+    println!("  --strict       Stops on any warning, i.e. a left out file, a missing creation");
+    println!("                 time or a renamed file, even with --ignore-errors");
+    println!("  --ratio        With --ls, shows how well every file compressed and which ones");
+    println!("                 got bigger");
+    println!("  --profile      Shows how long reading, hashing, compressing and writing took");
+    println!("  --report-memory  Shows the most memory kzip used at once");
+    println!("  --ads          Stores and extracts the alternate data streams of files on");
+    println!("                 Windows, i.e. the Zone.Identifier of downloads");
+    println!("  --selinux      With -x, sets the SELinux labels files were stored with, which");
+    println!("                 needs the right to relabel them");
+    println!("  --acls         With -x, sets the POSIX ACLs files were stored with");
+    println!("  --owner        Stores who owns every file, extracting as root gives them back");
+    println!("  --numeric-owner  Only stores and restores user and group ids, not names");
+    println!("  --preserve-permissions  With -x, sets permissions exactly as stored, setuid");
+    println!("                 bits included, instead of through the umask");
+    println!("  --check-space  With -x, stops before extracting when the files don't fit on");
+    println!("                 the destination's file system");
+    println!("  --max-unpacked-size / --max-ratio / --max-entries  With -x, stops before");
+    println!("                 extracting archives that unpack to more than a size in total,");
+    println!("                 a file to more than so many times its stored size, or that");
+    println!("                 have more files, i.e. --max-unpacked-size 10G --max-ratio 100");
+    println!("  --stdin-name   Stores what is piped into kzip as one file with this name,");
+    println!("                 i.e. pg_dump db | kzip --stdin-name db.sql -o backup.kzip");
+    println!("  --shard-by-dir Makes an archive for every directory in the input, -o being the");
+    println!("                 directory they go in, i.e. out/src.kzip and out/docs.kzip");
+    println!("  --keep-path    Stores a single file with the directories it was given with,");
+    println!("                 i.e. var/log/syslog for -i /var/log/syslog instead of syslog");
+    println!("  --force / -f   Overwrites the archive if it already exists, which is an error");
+    println!("                 otherwise (--no-clobber)");
+    println!("  --auto-number  Writes to name.1.kzip, name.2.kzip and so on when the archive");
+    println!("                 already exists");
+    println!("  --fsync        Waits until the archive is on disk before saying it is done,");
+    println!("                 so a crash or power cut right after can't lose it");
+    println!("  --resume       Carries on with an archive whose creation was interrupted,");
+    println!("                 from the checkpoint kzip writes every 10 seconds");
+    println!("  --hashes       Only extracts files whose SHA-256 is in a list, as sha256sum");
+    println!("                 writes it, i.e. --hashes wanted.sha256");
+    println!("  --write-index  Writes archive.kzip.idx next to the archive, with every name,");
+    println!("                 offset and hash, which --ls, -x and kzip extract then read");
+    println!("                 instead of the whole archive until it changes");
+    println!("  --trusted      Extracts without the limits on name lengths, entry counts and");
+    println!("                 unknown flags that archives from elsewhere are checked with,");
+    println!("                 names that lead out of the output are still left out");
+    println!("  --normalize    Stores and extracts names as nfc, nfd or none (the default),");
+    println!("                 nfc makes names from macOS match on Linux and Windows");
     println!("Information:");
     println!("  KZIP is developed with Rust.");
     println!("  When zipping files, KZIP uses GZIP's best compression.");
@@ -40,17 +252,79 @@ fn version() {
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args = utils::args();
+    signals::install();
+
+    if let Some(command) = args.get(1) {
+        match command.as_str() {
+            "serve" => serve::run(&args),
+            "browse" => browse::run(&args),
+            "exec" => exec::run(&args),
+            "extract" => extract::run(&args),
+            "grep" => grep::run(&args),
+            "find-in" => find::run(&args),
+            "merge" => merge::run(&args),
+            "estimate" => estimate::run(&args),
+            "compare-codecs" => compare::run(&args),
+            "analyze" => analyze::run(&args),
+            "rename" => rename::run(&args),
+            "touch" => touch::run(&args),
+            "optimize" => optimize::run(&args),
+            "edit" => edit::run(&args),
+            "delete" => delete::run(&args),
+            "recompress" => recompress::run(&args),
+            "meta" => meta::run(&args),
+            "batch" => batch::run(&args),
+            "rotate" => rotate::run(&args),
+            "stats" => stats::run(&args),
+            "du" => du::run(&args),
+            "history" => history::run(&args),
+            _ => {}
+        }
+    }
+
     let options: Vec<&String> = args.iter().filter(|f| f.starts_with("-")).collect();
 
     let mut input = "".to_string();
     let mut output = "".to_string();
     let mut is_extracting = false;
-    let mut is_verbose = false;
+    // 1 shows every file, 2 adds sizes and timings, 3 adds offsets and duplicates
+    let mut verbosity: u8 = 0;
     let mut show_files = false;
-
-    if options.len() > 0 {
-        for (_i, option) in options.iter().enumerate() {
+    let mut ignore_errors = false;
+    let mut is_strict = false;
+    let mut show_progress = true;
+    let mut checksum_file: Option<String> = None;
+    let mut backup = false;
+    let mut is_dry_run = false;
+    let mut normalize = Normalization::None;
+    let mut special = special::Policy::Skip;
+    let mut eol: Option<Eol> = None;
+    let mut order = Order::None;
+    let mut deterministic = false;
+    let mut is_trusted = false;
+    let mut resume = false;
+    let mut fsync = false;
+    let mut if_exists = IfExists::Fail;
+    let mut keep_path = false;
+    let mut shard_by_dir = false;
+    let mut stdin_name: Option<String> = None;
+    let mut show_ratio = false;
+    let mut check_space = false;
+    let mut profile = false;
+    let mut report_memory = false;
+    let mut ads = false;
+    let mut devices = false;
+    let mut preserve_times = times::Preserve::None;
+    let mut selinux = false;
+    let mut acls = false;
+    let mut owner = false;
+    let mut numeric_owner = false;
+    let mut preserve_permissions = false;
+    let mut write_index = false;
+
+    if !options.is_empty() {
+        for option in options.iter() {
             match option.as_str() {
                 "--version" => version(),
                 "--help" => help(),
@@ -73,8 +347,108 @@ fn main() {
                         args[index + 1].to_string()
                     }
                 }
-                "--verbose" | "-v" => is_verbose = true,
+                "--verbose" | "-v" => verbosity += 1,
+                "-vv" => verbosity += 2,
+                "-vvv" => verbosity += 3,
                 "--ls" | "-l" => show_files = true,
+                "--ignore-errors" => ignore_errors = true,
+                "--strict" => is_strict = true,
+                "--no-progress" => show_progress = false,
+                "--dry-run" => is_dry_run = true,
+                "--deterministic" => deterministic = true,
+                "--trusted" => is_trusted = true,
+                "--resume" => resume = true,
+                "--fsync" => fsync = true,
+                "--write-index" => write_index = true,
+                "--force" | "-f" => if_exists = IfExists::Overwrite,
+                "--no-clobber" => if_exists = IfExists::Fail,
+                "--auto-number" => if_exists = IfExists::Number,
+                "--keep-path" => keep_path = true,
+                "--shard-by-dir" => shard_by_dir = true,
+                "--ratio" => show_ratio = true,
+                "--check-space" => check_space = true,
+                "--profile" => profile = true,
+                "--report-memory" => report_memory = true,
+                "--ads" => ads = true,
+                "--devices" => devices = true,
+                "--selinux" => selinux = true,
+                "--acls" => acls = true,
+                "--owner" => owner = true,
+                "--numeric-owner" => numeric_owner = true,
+                "--preserve-permissions" => preserve_permissions = true,
+                "--stdin-name" => stdin_name = utils::option_value(&args, &["--stdin-name"]),
+                // read after the loop, as they belong together
+                "--algo" | "--level" => {}
+                // read after the loop, as they can be given more than once
+                "--transform" | "--meta" | "--encrypt-glob" => {}
+                "--password-file" | "--hashes" => {}
+                "--max-unpacked-size" | "--max-ratio" | "--max-entries" => {}
+                // read after the loop, when it is known what kzip is doing
+                "--pre-cmd" | "--post-cmd" => {}
+                option if filter::OPTIONS.contains(&option) || filter::FLAGS.contains(&option) => {}
+                "--backup=numbered" => backup = true,
+                "--backup=none" | "--backup=off" => backup = false,
+                option if option.starts_with("--preserve-times=") => {
+                    preserve_times =
+                        match times::Preserve::parse(&option["--preserve-times=".len()..]) {
+                            Some(preserve) => preserve,
+                            None => {
+                                println!("kzip: --preserve-times must be none, mtime or all");
+                                exit(1);
+                            }
+                        }
+                }
+                option if option.starts_with("--special=") => {
+                    special = match special::Policy::parse(&option["--special=".len()..]) {
+                        Some(special) => special,
+                        None => {
+                            println!("kzip: --special must be skip, store or fail");
+                            exit(1);
+                        }
+                    }
+                }
+                "--checksum-file" => {
+                    checksum_file = utils::option_value(&args, &["--checksum-file"]);
+                }
+                "--log-file" => {
+                    let log_file = utils::option_value(&args, &["--log-file"]).unwrap_or_default();
+                    if let Err(err) = log::open(&log_file) {
+                        println!("kzip: could not open log file {log_file}");
+                        println!("{:#?}", err);
+                        exit(1);
+                    }
+                }
+                "--normalize" => {
+                    let value = utils::option_value(&args, &["--normalize"]).unwrap_or_default();
+                    normalize = match Normalization::parse(&value) {
+                        Some(normalize) => normalize,
+                        None => {
+                            println!("kzip: --normalize must be nfc, nfd or none");
+                            exit(1);
+                        }
+                    }
+                }
+                "--order" => {
+                    order = match utils::option_value(&args, &["--order"]).as_deref() {
+                        Some("dir") => Order::Dir,
+                        Some("size") => Order::Size,
+                        Some("none") => Order::None,
+                        _ => {
+                            println!("kzip: --order must be dir, size or none");
+                            exit(1);
+                        }
+                    }
+                }
+                "--eol" => {
+                    let value = utils::option_value(&args, &["--eol"]).unwrap_or_default();
+                    eol = match Eol::parse(&value) {
+                        Some(eol) => Some(eol),
+                        None => {
+                            println!("kzip: --eol must be lf, crlf or native");
+                            exit(1);
+                        }
+                    }
+                }
                 _ => help(),
             }
         }
@@ -82,450 +456,2064 @@ fn main() {
         help()
     }
 
+    // --devices zips special files unless --special says otherwise
+    if devices && special == special::Policy::Skip {
+        special = special::Policy::Store;
+    }
+
+    if stdin_name.is_some() {
+        if output.is_empty() {
+            println!("kzip: --stdin-name needs -o with the archive to write");
+            exit(1);
+        }
+        input = "-".to_string();
+    }
+    if output.is_empty() && shard_by_dir {
+        println!("kzip: --shard-by-dir needs -o with the directory to put the archives in");
+        exit(1);
+    }
     if output.is_empty() {
         output = input.clone();
     }
 
-    if is_verbose {
+    let filter = match Filter::from_args(&args, Path::new(&input)) {
+        Ok(filter) => filter,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let transforms: Vec<Transform> = match utils::option_values(&args, &["--transform"])
+        .iter()
+        .map(|expression| Transform::parse(expression))
+        .collect()
+    {
+        Ok(transforms) => transforms,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let password = match utils::password(&args) {
+        Ok(password) => password,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+    let hashes = match utils::option_value(&args, &["--hashes"]) {
+        Some(path) => match utils::hash_list(&path) {
+            Ok(hashes) => Some(hashes),
+            Err(err) => {
+                println!("kzip: {err}");
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    let max_unpacked_size = match utils::option_value(&args, &["--max-unpacked-size"])
+        .map(|size| filter::parse_size(&size))
+    {
+        None => None,
+        Some(Ok(size)) => Some(size),
+        Some(Err(err)) => {
+            println!("kzip: --max-unpacked-size: {err}");
+            exit(1);
+        }
+    };
+    let max_ratio =
+        match utils::option_value(&args, &["--max-ratio"]).map(|ratio| ratio.parse::<f64>()) {
+            None => None,
+            Some(Ok(ratio)) if ratio > 0.0 => Some(ratio),
+            Some(_) => {
+                println!("kzip: --max-ratio has to be a number above 0, i.e. 100");
+                exit(1);
+            }
+        };
+    let max_entries = match utils::option_value(&args, &["--max-entries"])
+        .map(|entries| entries.parse::<u64>())
+    {
+        None => None,
+        Some(Ok(entries)) => Some(entries),
+        Some(Err(_)) => {
+            println!("kzip: --max-entries has to be a number");
+            exit(1);
+        }
+    };
+    let encrypt_globs = utils::option_values(&args, &["--encrypt-glob"]);
+    if !encrypt_globs.is_empty() && password.is_none() {
+        println!("kzip: --encrypt-glob needs a password, set KZIP_PASSWORD or use --password-file");
+        exit(1);
+    }
+
+    let mut archive_meta = Vec::new();
+    if let Err(err) = meta::set(&mut archive_meta, &utils::option_values(&args, &["--meta"])) {
+        println!("kzip: {err}");
+        exit(1);
+    }
+    if let Some((key, _)) = archive_meta
+        .iter()
+        .find(|(key, _)| key.starts_with(history::RESERVED))
+    {
+        println!(
+            "kzip: {key} can't be set, keys starting with {} are kept by kzip",
+            history::RESERVED
+        );
+        exit(1);
+    }
+
+    // None picks a codec for every file
+    let codec = match utils::option_value(&args, &["--algo"]).as_deref() {
+        Some("auto") => None,
+        algo => {
+            let level = utils::option_value(&args, &["--level"]);
+            match Codec::parse(algo.unwrap_or("zlib"), level.as_deref()) {
+                Ok(codec) => Some(codec),
+                Err(err) => {
+                    println!("kzip: {err}");
+                    exit(1);
+                }
+            }
+        }
+    };
+
+    if verbosity > 0 {
         println!("input: {input}\noutput: {output}");
+        // the bar would end up in between the file names
+        show_progress = false;
+    }
+
+    let read_options = ReadOptions {
+        verbosity,
+        normalize,
+        is_strict,
+        show_progress: show_progress && !show_files,
+        backup,
+        is_dry_run,
+        transforms: &transforms,
+        eol,
+        password: password.as_deref(),
+        is_trusted,
+        hashes: hashes.as_ref(),
+        show_ratio,
+        check_space,
+        max_unpacked_size,
+        max_ratio,
+        max_entries,
+        profile,
+        report_memory,
+        ads,
+        selinux,
+        acls,
+        numeric_owner,
+        preserve_permissions,
+        devices,
+        preserve_times,
+        write_index,
+    };
+
+    if show_files || is_extracting {
+        log::event(
+            "start",
+            &[
+                (
+                    "mode",
+                    if show_files { "list" } else { "extract" }.to_string(),
+                ),
+                ("input", input.to_string()),
+                ("output", output.to_string()),
+            ],
+        );
     }
 
     if show_files {
-        read_kzip_file(&input, &output, is_verbose, false);
+        read_kzip_file(&input, &output, false, &read_options);
+        exit(0);
+    }
+
+    if !is_extracting && is_dry_run {
+        dry_run(&input, &filter);
+        exit(0);
+    }
+
+    if !is_extracting && shard_by_dir {
+        shard::run(&args, &input, &output, &filter);
     }
 
+    let pre_cmd = utils::option_value(&args, &["--pre-cmd"]);
+    let post_cmd = utils::option_value(&args, &["--post-cmd"]);
+
     if !is_extracting {
         let output_with_kzip = output.to_owned() + ".kzip";
         if !output.ends_with(".kzip") {
             output = output_with_kzip;
         }
 
-        let nof = get_number_of_files(&input);
-        let mut hashes: HashMap<String, usize> = HashMap::new();
-        let mut buffer = ByteBuffer::new();
-
-        if let Ok(_meta) = fs::metadata(&output) {
-            output = output.clone().replace(".kzip", "")
-                + "."
-                + &get_number_of_files(&output).to_string()
-                + ".kzip";
-        }
-
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(output)
-            .unwrap();
-
-        buffer.write_u8(12);
-        buffer.write_u8(10);
-        buffer.write_u8(116);
-        // magic number = cat
-        buffer.write_string(VERSION); // version
-        buffer.write_u32(nof); // amount of files
-
-        file.write(&buffer.clone().into_vec()).unwrap();
-        buffer.clear();
-        buffer.flush().unwrap();
-
-        if let Ok(metadata) = fs::metadata(&input.to_string()) {
-            if metadata.is_dir() {
-                read_dir(
-                    &mut file,
-                    &mut buffer,
-                    &input.to_string(),
-                    is_verbose,
-                    &mut hashes,
+        // an interrupted archive is carried on with, not made again next to it
+        if !resume && fs::symlink_metadata(&output).is_ok() {
+            match if_exists {
+                // a link to the archive stays a link to the new one
+                IfExists::Overwrite => {
+                    let is_link = fs::symlink_metadata(&output).is_ok_and(|m| m.is_symlink());
+                    if let Some(target) = fs::canonicalize(&output).ok().filter(|_| is_link) {
+                        output = target.to_string_lossy().to_string();
+                    }
+                }
+                IfExists::Fail => {
+                    println!("kzip: {output} already exists, use --force to overwrite it or --auto-number to pick another name");
+                    exit(1);
+                }
+                IfExists::Number => {
+                    let stem = output.strip_suffix(".kzip").unwrap_or(&output).to_string();
+                    output = (1..)
+                        .map(|number| format!("{stem}.{number}.kzip"))
+                        .find(|name| fs::symlink_metadata(name).is_err())
+                        .unwrap();
+                }
+            }
+        }
+
+        let paths = [
+            ("MODE", "create".to_string()),
+            ("INPUT", input.to_string()),
+            ("OUTPUT", output.to_string()),
+        ];
+        if let Some(pre_cmd) = &pre_cmd {
+            hooks::run("pre", pre_cmd, &paths);
+        }
+
+        let nof = match stdin_name {
+            Some(_) => 1,
+            None => get_number_of_files(Path::new(&input), &filter),
+        };
+
+        // written next to the output and renamed once it is finished, so
+        // nothing ever sees half an archive under its name
+        let part = format!("{output}.kzip-part");
+        let version = if deterministic { "" } else { VERSION };
+        let created = if resume {
+            resume_archive(&output, &part)
+        } else {
+            let options = CreateOptions::new().version(version).meta(&archive_meta);
+            ArchiveWriter::create_with(&part, nof, &options).map(|writer| (writer, HashSet::new()))
+        };
+        let (writer, resumed) = match created {
+            Ok(created) => created,
+            Err(err) => {
+                log::event(
+                    "error",
+                    &[("output", output.to_string()), ("error", err.to_string())],
                 );
-            } else {
-                let file_name = Path::new(&input).file_name();
-                if let Ok(mut content) = fs::read(file_name.unwrap().to_str().unwrap()) {
-                    generate_buffer(
-                        &mut file,
-                        &mut buffer,
-                        file_name.unwrap().to_str().unwrap().to_string(),
-                        &mut content,
-                        &metadata,
-                        &mut hashes,
-                    );
+                println!("kzip: There was an error writing to {output}");
+                println!("{:#?}", err);
+                exit(1);
+            }
+        };
+
+        signals::remove_on_interrupt(&part);
+        log::event(
+            "start",
+            &[
+                ("mode", "create".to_string()),
+                ("input", input.to_string()),
+                ("output", output.to_string()),
+            ],
+        );
+
+        let mut zip = Zip {
+            writer,
+            output: output.to_string(),
+            part: part.to_string(),
+            verbosity,
+            ignore_errors,
+            is_strict,
+            normalize,
+            filter,
+            codec,
+            progress: Progress::new(nof as u64, show_progress),
+            stats: Stats {
+                started: Instant::now(),
+                input_bytes: 0,
+                duplicates: 0,
+                duplicate_bytes: 0,
+                incompressible: 0,
+                incompressible_bytes: 0,
+            },
+            skipped: Vec::new(),
+            transforms,
+            order,
+            by_size: Vec::new(),
+            deterministic,
+            encrypt_globs,
+            password: password.as_deref().map(Password::new),
+            special,
+            left_out: Vec::new(),
+            checkpoints: Checkpoints::new(&output),
+            resumed,
+            ads,
+            owner,
+            numeric_owner,
+        };
+
+        if let Some(stdin_name) = &stdin_name {
+            let mut content = Vec::new();
+            match io::stdin().read_to_end(&mut content) {
+                Ok(_) => add_file(&mut zip, OsStr::new(stdin_name), &content, None, &[]),
+                Err(err) => skip(&mut zip, "stdin".to_string(), err),
+            }
+        } else {
+            match fs::metadata(&input) {
+                Ok(metadata) if metadata.is_dir() => {
+                    read_dir(&mut zip, Path::new(&input));
+                    add_by_size(&mut zip);
+                }
+                Ok(metadata) if !metadata.is_file() => {
+                    add_special(&mut zip, &file_entry_name(&input, keep_path), &metadata)
+                }
+                Ok(metadata) => {
+                    let file_name = file_entry_name(&input, keep_path);
+                    match read_file(&mut zip, Path::new(&input)) {
+                        Ok(content) => {
+                            let meta = attributes(&mut zip, Path::new(&input), &metadata);
+                            add_file(&mut zip, &file_name, &content, Some(&metadata), &meta);
+                            add_streams(&mut zip, &file_name, Path::new(&input), &metadata);
+                        }
+                        Err(err) => skip(&mut zip, input.to_string(), err),
+                    }
+                }
+                Err(err) => skip(&mut zip, input.to_string(), err),
+            }
+        }
+
+        let added = zip.writer.count();
+        let timings = zip.writer.timings;
+        let finished = if fsync {
+            let dir = Path::new(&output)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty());
+            zip.writer
+                .finish_synced()
+                .and_then(|_| replace(&part, &output))
+                .and_then(|_| utils::sync_dir(dir.unwrap_or(Path::new("."))))
+        } else {
+            zip.writer.finish().and_then(|_| replace(&part, &output))
+        };
+        if let Err(err) = finished {
+            log::event(
+                "error",
+                &[("output", output.to_string()), ("error", err.to_string())],
+            );
+            println!("kzip: There was an error writing to {output}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+        signals::keep();
+        zip.checkpoints.remove();
+        zip.progress.clear();
+
+        let checksum = match sha256::try_digest(Path::new(&output)) {
+            Ok(checksum) => checksum,
+            Err(err) => {
+                println!("kzip: could not read {output} back to calculate its checksum");
+                println!("{:#?}", err);
+                exit(1);
+            }
+        };
+        if let Some(checksum_file) = &checksum_file {
+            write_checksum_file(checksum_file, &output, &checksum);
+        }
+        if write_index {
+            match ArchiveReader::open(&output) {
+                Ok(mut reader) => write_sidecar(&mut reader, &output),
+                Err(err) => {
+                    println!("kzip: could not read {output} back to index it");
+                    println!("{:#?}", err);
+                    exit(1);
                 }
             }
         }
+        log::event(
+            "done",
+            &[
+                ("files", added.to_string()),
+                ("skipped", zip.skipped.len().to_string()),
+                ("sha256", checksum.to_string()),
+            ],
+        );
+        if let Some(post_cmd) = &post_cmd {
+            let archive_size = fs::metadata(&output)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            let status = if zip.skipped.is_empty() {
+                "complete"
+            } else {
+                "partial"
+            };
+            let mut env = paths.to_vec();
+            env.extend([
+                ("STATUS", status.to_string()),
+                ("FILES", added.to_string()),
+                ("SKIPPED", zip.skipped.len().to_string()),
+                ("INPUT_SIZE", zip.stats.input_bytes.to_string()),
+                ("ARCHIVE_SIZE", archive_size.to_string()),
+                ("SHA256", checksum.to_string()),
+            ]);
+            hooks::run("post", post_cmd, &env);
+        }
+
+        if !zip.left_out.is_empty() {
+            println!(
+                "kzip: {} special file(s) were left out, use --special=store to keep fifos and devices:",
+                zip.left_out.len()
+            );
+            for (name, kind) in &zip.left_out {
+                println!("  {name} ({kind})");
+            }
+        }
+        if !zip.skipped.is_empty() {
+            println!(
+                "kzip: {} file(s) could not be read and were left out:",
+                zip.skipped.len()
+            );
+            for (name, err) in &zip.skipped {
+                println!("  {name}: {err}");
+            }
+            println!("kzip: Done zipping, the archive is incomplete");
+            print_summary(&zip.stats, &output, added, &checksum);
+            if profile {
+                print_profile(&timings, zip.stats.started.elapsed(), false);
+            }
+            if report_memory {
+                print_peak_memory();
+            }
+            exit(EXIT_PARTIAL);
+        }
 
         println!("kzip: Done zipping");
+        print_summary(&zip.stats, &output, added, &checksum);
+        if profile {
+            print_profile(&timings, zip.stats.started.elapsed(), false);
+        }
+        if report_memory {
+            print_peak_memory();
+        }
     } else {
-        read_kzip_file(&input, &output, is_verbose, true);
-        println!("kzip: Done unzipping");
+        let paths = [
+            ("MODE", "extract".to_string()),
+            ("INPUT", input.to_string()),
+            ("OUTPUT", output.to_string()),
+        ];
+        if let Some(pre_cmd) = pre_cmd.as_ref().filter(|_| !is_dry_run) {
+            hooks::run("pre", pre_cmd, &paths);
+        }
+
+        read_kzip_file(&input, &output, true, &read_options);
+        if !is_dry_run {
+            println!("kzip: Done unzipping");
+        }
+
+        if let Some(post_cmd) = post_cmd.as_ref().filter(|_| !is_dry_run) {
+            let mut env = paths.to_vec();
+            env.push(("STATUS", "complete".to_string()));
+            hooks::run("post", post_cmd, &env);
+        }
     }
 
     exit(0);
 }
 
-fn generate_buffer(
-    file: &mut File,
-    buffer: &mut ByteBuffer,
-    file_name: String,
-    content: &mut Vec<u8>,
-    metadata: &Metadata,
-    hashes: &mut HashMap<String, usize>,
-) {
-    let modified = metadata
-        .modified()
-        .unwrap()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let created_at = metadata
-        .created()
-        .unwrap()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    let file_hash = digest(content.clone());
-    let is_duplicate = if !metadata.is_dir() {
-        hashes.contains_key(&file_hash)
-    } else {
-        false
-    };
-
-    buffer.write_u8(if is_duplicate { 1 } else { 0 }); // tell kzip if file is duplicate
+/// The name a single file given as the input is stored under, its file name
+/// or with --keep-path the path as it was given, without a leading `/`,
+/// drive or `..`.
+fn file_entry_name(input: &str, keep_path: bool) -> OsString {
+    let path = Path::new(input);
+    if !keep_path {
+        if let Some(file_name) = path.file_name() {
+            return file_name.to_os_string();
+        }
+    }
 
-    // buffer.write_u8(file_name.len() as u8);
-    buffer.write_string(&file_name);
-    buffer.write_u64(created_at);
-    buffer.write_u64(modified);
-    if is_duplicate {
-        // there is a duplicate file found
-        // going to tell kzip this to save some space
-        buffer.write_u32((*hashes.get(&file_hash).unwrap()).try_into().unwrap());
-    } else {
-        buffer.write_u64(content.len() as u64);
-        let encoded_content = &mut encode(content);
-        buffer.write_u64(encoded_content.len() as u64);
-        buffer.write(&encoded_content).unwrap();
-        if !metadata.is_dir() {
-            hashes.insert(file_hash, hashes.len());
+    let mut name = OsString::new();
+    for component in path.components() {
+        if let path::Component::Normal(part) = component {
+            if !name.is_empty() {
+                name.push(path::MAIN_SEPARATOR_STR);
+            }
+            name.push(part);
         }
     }
+    name
+}
 
-    file.write(&buffer.clone().into_vec()).unwrap();
-    buffer.clear();
-    buffer.flush().unwrap();
+/// What to do when the archive being created already exists.
+enum IfExists {
+    Fail,
+    Overwrite,
+    // name.1.kzip, name.2.kzip and so on, the first that doesn't exist
+    Number,
 }
 
-fn read_dir(
-    mut file: &mut File,
-    mut buffer: &mut ByteBuffer,
-    dir_name: &String,
-    verbose: bool,
-    hashes: &mut HashMap<String, usize>,
-) {
-    match fs::read_dir(dir_name) {
-        Ok(dir_result) => {
-            for result in dir_result {
-                let entry = result.unwrap();
-                let file_name = entry.file_name();
-                if let Ok(mut content) = fs::read(format!(
-                    "{}{}{}",
-                    dir_name,
-                    path::MAIN_SEPARATOR,
-                    file_name.to_str().unwrap()
-                )) {
-                    if verbose {
-                        println!("kzip: reading file: {}", file_name.to_str().unwrap());
-                    }
+/// What is zipped first.
+#[derive(Clone, Copy, PartialEq)]
+enum Order {
+    // as the file system lists them
+    None,
+    // the files of a directory before going into its subdirectories
+    Dir,
+    // the smallest files of the whole input first
+    Size,
+}
 
-                    generate_buffer(
-                        &mut file,
-                        &mut buffer,
-                        format!(
-                            "{}{}{}",
-                            dir_name,
-                            path::MAIN_SEPARATOR,
-                            file_name.to_str().unwrap()
-                        ),
-                        &mut content,
-                        &entry.metadata().unwrap(),
-                        hashes,
-                    );
+/// Everything needed while walking the input of a new archive.
+struct Zip {
+    writer: ArchiveWriter,
+    output: String,
+    // what is being written until the archive is finished
+    part: String,
+    verbosity: u8,
+    ignore_errors: bool,
+    is_strict: bool,
+    normalize: Normalization,
+    filter: Filter,
+    codec: Option<Codec>,
+    progress: Progress,
+    stats: Stats,
+    skipped: Vec<(String, io::Error)>,
+    // renames files before they are stored
+    transforms: Vec<Transform>,
+    // the order files are zipped in
+    order: Order,
+    // files left for after reading every directory with --order size
+    by_size: Vec<(OsString, Metadata)>,
+    // sorted names and no times, so the same files always make the same archive
+    deterministic: bool,
+    // files matching these are encrypted with the password
+    encrypt_globs: Vec<String>,
+    password: Option<Password>,
+    special: special::Policy,
+    // special files left out because of --special=skip, with what they are
+    left_out: Vec<(String, &'static str)>,
+    checkpoints: Checkpoints,
+    // names already in an archive that is being resumed
+    resumed: HashSet<String>,
+    // stores the alternate data streams of files
+    ads: bool,
+    // stores who owns every file, only by ids with numeric_owner
+    owner: bool,
+    numeric_owner: bool,
+}
+
+/// Numbers for the summary after zipping.
+struct Stats {
+    started: Instant,
+    input_bytes: u64,
+    duplicates: u32,
+    duplicate_bytes: u64,
+    // files compression hardly made smaller, and their size
+    incompressible: u32,
+    incompressible_bytes: u64,
+}
+
+/// Adds a file, `metadata` being None for content that isn't a file, which
+/// is stored with the current time.
+fn add_file(
+    zip: &mut Zip,
+    os_name: &OsStr,
+    content: &[u8],
+    metadata: Option<&Metadata>,
+    meta: &[(String, String)],
+) {
+    // names that aren't UTF-8 are stored as they are, there is nothing to normalize
+    let os_name = match os_name.to_str() {
+        Some(name) => {
+            let normalized = zip.normalize.apply(name);
+            if normalized != name {
+                warn(zip, format!("{name} is stored as {normalized}"));
+            }
+            let transformed = transform::apply_all(&zip.transforms, &normalized);
+            if transformed.is_empty() {
+                if zip.verbosity > 0 {
+                    println!("kzip: leaving out {name}, --transform removed its name");
+                }
+                return;
+            }
+            if !vfs::is_inside(&transformed) {
+                let message = if transformed == normalized {
+                    format!("leaving out {name}, it leads outside of the archive")
                 } else {
-                    if let Ok(meta) = fs::metadata(format!(
-                        "{}{}{}",
-                        dir_name,
-                        path::MAIN_SEPARATOR,
-                        file_name.to_str().unwrap()
-                    )) {
-                        if meta.is_dir() {
-                            if verbose {
-                                println!("kzip: reading directory: {}", dir_name);
-                            }
+                    format!("leaving out {name}, --transform names it {transformed}, which leads outside of the archive")
+                };
+                log::event("warning", &[("message", message.clone())]);
+                zip.progress.clear();
+                println!("kzip: {message}");
+                stop_if_strict(zip);
+                return;
+            }
+            if zip.verbosity > 0 && transformed != normalized {
+                println!("kzip: storing {normalized} as {transformed}");
+            }
+            OsString::from(transformed)
+        }
+        None => os_name.to_os_string(),
+    };
+    let file_name = os_name.to_string_lossy();
+    if zip
+        .resumed
+        .remove(&archive::parse_file_path(file_name.to_string()))
+    {
+        if zip.verbosity > 0 {
+            println!("kzip: {file_name} is already in the archive");
+        }
+        zip.progress.add(content.len() as u64);
+        zip.stats.input_bytes += content.len() as u64;
+        return;
+    }
+    let (created_at, modified) = if zip.deterministic {
+        (None, 0)
+    } else if let Some(metadata) = metadata {
+        // file systems without times, and times before 1970
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+        let created_at = metadata
+            .created()
+            .ok()
+            .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+            .map(|created| created.as_secs());
+        (created_at, modified)
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        (Some(now), now)
+    };
+    if created_at.is_none() && !zip.deterministic {
+        warn(
+            zip,
+            format!("{file_name} has no creation time, only storing when it was modified"),
+        );
+    }
 
-                            read_dir(
-                                file,
-                                buffer,
-                                &format!(
-                                    "{}{}{}",
-                                    dir_name,
-                                    path::MAIN_SEPARATOR,
-                                    file_name.to_str().unwrap(),
-                                ),
-                                verbose,
-                                hashes,
-                            );
-                        }
-                    } else {
-                        println!("kzip: could not read file {}", file_name.to_str().unwrap());
-                    }
+    let started = Instant::now();
+    let special = metadata.and_then(Special::from_metadata);
+    let codec = match special {
+        Some(_) => Codec::Store,
+        None => zip.codec.unwrap_or_else(|| Codec::auto(content)),
+    };
+    let header = Header {
+        name: &os_name,
+        created_at,
+        modified,
+        meta,
+    };
+    let is_encrypted = zip
+        .encrypt_globs
+        .iter()
+        .any(|glob| glob_match(glob, &file_name));
+    let result = match (special, zip.password.as_mut()) {
+        (Some(special), _) => zip.writer.add_special(&header, special),
+        (None, Some(password)) if is_encrypted => {
+            zip.writer.add_encrypted(&header, content, codec, password)
+        }
+        (None, _) => zip.writer.add(&header, content, codec),
+    };
+    match result {
+        Ok(added) => {
+            zip.progress.add(content.len() as u64);
+            zip.stats.input_bytes += content.len() as u64;
+            if added.duplicate_of.is_some() {
+                zip.stats.duplicates += 1;
+                zip.stats.duplicate_bytes += content.len() as u64;
+            } else if codec != Codec::Store
+                && !content.is_empty()
+                && added.length as f64 > content.len() as f64 * INCOMPRESSIBLE
+            {
+                zip.stats.incompressible += 1;
+                zip.stats.incompressible_bytes += content.len() as u64;
+            }
+            let mut fields = vec![
+                ("name", file_name.to_string()),
+                ("size", content.len().to_string()),
+                ("packed", added.length.to_string()),
+            ];
+            if let Some(original) = added.duplicate_of {
+                fields.push(("duplicate_of", original.to_string()));
+            }
+            let kind = sniff(content).name();
+            if let Some(kind) = kind {
+                fields.push(("type", kind.to_string()));
+            }
+            log::event("added", &fields);
+            if let Err(err) = zip.checkpoints.update(&mut zip.writer) {
+                zip.progress.clear();
+                println!("kzip: {err}");
+                exit(1);
+            }
+            if zip.verbosity >= 2 {
+                println!(
+                    "  {} -> {} ({}) with {}{} in {} ms",
+                    format_byte(content.len() as f64),
+                    format_byte(added.length as f64),
+                    format_ratio(added.length, content.len() as u64),
+                    codec.name(),
+                    kind.map(|kind| format!(" ({kind})")).unwrap_or_default(),
+                    started.elapsed().as_millis()
+                );
+            }
+            if zip.verbosity >= 3 {
+                match added.duplicate_of {
+                    Some(original) => println!(
+                        "  at offset {}, same content as file {original}, only storing a pointer",
+                        added.offset
+                    ),
+                    None => println!("  at offset {}, new content", added.offset),
                 }
             }
         }
+        // the file itself can't be stored, i.e. its name is too long
+        Err(err) if err.kind() == ErrorKind::InvalidInput => skip(zip, file_name.to_string(), err),
         Err(err) => {
-            println!("kzip: there was an error");
-            println!("{:#?}", err)
+            log::event(
+                "error",
+                &[
+                    ("output", zip.output.to_string()),
+                    ("error", err.to_string()),
+                ],
+            );
+            println!("kzip: There was an error writing to {}", zip.output);
+            println!("{:#?}", err);
+            exit(1);
         }
     }
 }
 
-fn get_number_of_files(dir_name: &String) -> u32 {
-    let mut i = 0;
+/// Moves a finished archive into place. An archive that is overwritten keeps
+/// who can read and write it, like it was written over where it is.
+fn replace(part: &str, output: &str) -> io::Result<()> {
+    if let Ok(metadata) = fs::metadata(output) {
+        fs::set_permissions(part, metadata.permissions())?;
+    }
+    fs::rename(part, output)
+}
 
-    match fs::read_dir(dir_name) {
-        Ok(dir) => {
-            for entry in dir {
-                if let Ok(metadata) = fs::metadata(dir_name) {
-                    if metadata.is_dir() {
-                        i += get_number_of_files(&format!(
-                            "{dir_name}/{}",
-                            entry.unwrap().file_name().into_string().unwrap()
-                        ));
-                    } else {
-                        i += 1;
-                    }
-                }
-            }
+/// Opens an archive that --resume carries on with, along with the names of
+/// the files that are already in it.
+fn resume_archive(output: &str, part: &str) -> io::Result<(ArchiveWriter, HashSet<String>)> {
+    let (count, length) = match checkpoint::read(output) {
+        Ok(checkpoint) => checkpoint,
+        Err(err) => {
+            println!("kzip: {output} can't be resumed, {err}");
+            exit(1);
         }
-        Err(_err) => {
-            if let Ok(metadata) = fs::metadata(dir_name) {
-                if metadata.is_file() {
-                    i += 1;
-                }
+    };
+    let (writer, entries) = ArchiveWriter::resume(part, count, length)?;
+    println!("kzip: resuming {output} after {count} file(s)");
+
+    Ok((
+        writer,
+        entries.into_iter().map(|entry| entry.name).collect(),
+    ))
+}
+
+/// Handles a fifo, socket or device node the way --special says. Reading
+/// one would hang or never end, so its content is never read.
+fn add_special(zip: &mut Zip, os_name: &OsStr, metadata: &Metadata) {
+    let file_name = os_name.to_string_lossy().to_string();
+    let special = match Special::from_metadata(metadata) {
+        Some(special) => special,
+        None => {
+            let err = io::Error::new(ErrorKind::Unsupported, "not a regular file");
+            return skip(zip, file_name, err);
+        }
+    };
+    let kind = special.kind.name();
+
+    match zip.special {
+        // a socket only exists while a program listens on it, there is
+        // nothing to create again
+        special::Policy::Store if special.kind != special::Kind::Socket => {
+            if zip.verbosity > 0 {
+                println!("kzip: storing {kind}: {file_name}");
             }
+            let meta = if zip.owner {
+                owner::meta(metadata, zip.numeric_owner)
+            } else {
+                Vec::new()
+            };
+            add_file(zip, os_name, &[], Some(metadata), &meta);
+        }
+        special::Policy::Store | special::Policy::Skip => {
+            warn(zip, format!("leaving out {kind} {file_name}"));
+            zip.left_out.push((file_name, kind));
+        }
+        special::Policy::Fail => {
+            zip.progress.clear();
+            log::event(
+                "error",
+                &[("name", file_name.to_string()), ("kind", kind.to_string())],
+            );
+            println!("kzip: {file_name} is a {kind}, use --special=skip or --special=store");
+            let _ = fs::remove_file(&zip.part);
+            exit(1);
         }
     }
+}
 
-    return i;
+/// Looks at what would be zipped and guesses the size of the archive.
+fn dry_run(input: &str, filter: &Filter) {
+    let mut files = Vec::new();
+    list_files(Path::new(input), filter, &mut files);
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    let estimate = match estimate::estimate(&files, Codec::default()) {
+        Ok(estimate) => estimate,
+        Err(err) => {
+            println!("kzip: could not read {input}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    println!("kzip: Dry run, nothing was written");
+    println!("  Files: {}", files.len());
+    println!("  Input: {}", format_byte(total as f64));
+    println!(
+        "  Estimated archive: {} (± {}, {} of the input)",
+        format_byte(estimate.packed as f64),
+        format_byte(estimate.margin as f64),
+        format_ratio(estimate.packed, total)
+    );
 }
 
-fn encode(bytes: &mut [u8]) -> Vec<u8> {
-    let mut e = ZlibEncoder::new(Vec::new(), Compression::best());
-    let _ = e.write_all(bytes);
-    let compressed_bytes = e.finish().unwrap();
+fn print_summary(stats: &Stats, output: &str, files: u32, checksum: &str) {
+    let elapsed = stats.started.elapsed().as_secs_f64();
+    let archive_size = fs::metadata(output)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    println!("  Files: {files} ({} duplicate)", stats.duplicates);
+    println!("  Input: {}", format_byte(stats.input_bytes as f64));
+    println!(
+        "  Archive: {} ({} of the input)",
+        format_byte(archive_size as f64),
+        format_ratio(archive_size, stats.input_bytes)
+    );
+    println!(
+        "  Saved by duplicates: {}",
+        format_byte(stats.duplicate_bytes as f64)
+    );
+    if stats.incompressible > 0 {
+        println!(
+            "  Incompressible: {} file(s) ({}) were stored nearly uncompressed, --algo auto or store saves compressing them",
+            stats.incompressible,
+            format_byte(stats.incompressible_bytes as f64)
+        );
+    }
+    println!(
+        "  Time: {elapsed:.2} s ({}/s)",
+        format_byte(if elapsed > 0.0 {
+            stats.input_bytes as f64 / elapsed
+        } else {
+            0.0
+        })
+    );
+    println!("  SHA-256: {checksum}");
+}
 
-    return compressed_bytes.to_vec();
+/// Shows where the time of a run went, for --profile. Whatever isn't
+/// reading, hashing, compressing or writing is listed as other, i.e. walking
+/// directories.
+fn print_profile(timings: &Timings, elapsed: Duration, is_extract: bool) {
+    let Timings {
+        read,
+        hash,
+        compress,
+        write,
+    } = *timings;
+    let other = elapsed.saturating_sub(read + hash + compress + write);
+    let phases = [
+        ("Read", read),
+        ("Hash", hash),
+        (if is_extract { "Decompress" } else { "Compress" }, compress),
+        ("Write", write),
+        ("Other", other),
+    ];
+
+    println!("  Profile:");
+    for (name, time) in phases {
+        println!(
+            "    {:<11} {:>8.2} s  {:>6}",
+            format!("{name}:"),
+            time.as_secs_f64(),
+            format_ratio(time.as_micros() as u64, elapsed.as_micros() as u64)
+        );
+    }
 }
 
-fn decode(bytes: &[u8], file_size: u64) -> Vec<u8> {
-    let input = bytes;
-    let mut decompressor = flate2::Decompress::new(true);
-    let mut buf = Vec::with_capacity(file_size as usize);
-    decompressor
-        .decompress_vec(&input, &mut buf, flate2::FlushDecompress::None)
-        .unwrap();
+/// Sets the attributes `names` an entry was stored with on the extracted
+/// file, setting labels takes the right to relabel files.
+fn restore_attributes(
+    output: &str,
+    os_name: &OsStr,
+    entry: &archive::Entry,
+    names: &[&str],
+) -> io::Result<()> {
+    let path = output_path(output, os_name);
+    for (name, key) in xattr::KEPT {
+        if !names.contains(&name) {
+            continue;
+        }
+        let Some((_, value)) = entry.meta.iter().find(|(other, _)| other == key) else {
+            continue;
+        };
+        let value = xattr::from_meta(name, value)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("{key} is not valid")))?;
+        xattr::set(Path::new(&path), name, &value)?;
+    }
+    Ok(())
+}
+
+/// Sets the permissions an entry was stored with on the extracted file.
+fn restore_mode(
+    output: &str,
+    os_name: &OsStr,
+    entry: &archive::Entry,
+    preserve: bool,
+) -> io::Result<()> {
+    match mode::stored(&entry.meta) {
+        Some(stored) => mode::apply(Path::new(&output_path(output, os_name)), stored, preserve),
+        None => Ok(()),
+    }
+}
+
+/// Gives an extracted file to the user and group its entry was stored
+/// with, `numeric` only going by their ids.
+fn restore_owner(
+    output: &str,
+    os_name: &OsStr,
+    entry: &archive::Entry,
+    numeric: bool,
+) -> io::Result<()> {
+    match owner::ids(&entry.meta, numeric) {
+        Some(ids) => owner::chown(Path::new(&output_path(output, os_name)), ids),
+        None => Ok(()),
+    }
+}
 
-    return buf;
+fn print_peak_memory() {
+    match utils::peak_memory() {
+        Ok(peak) => println!("  Peak memory: {}", format_byte(peak as f64)),
+        Err(err) => println!("  Peak memory: unknown, {err}"),
+    }
 }
 
-/*
-    Stolen from: https://github.com/banyan/rust-pretty-bytes/blob/master/src/converter.rs
-*/
-fn format_byte(num: f64) -> String {
-    let negative = if num.is_sign_positive() { "" } else { "-" };
-    let num = num.abs();
-    let units = ["B", "kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
-    if num < 1_f64 {
-        return format!("{}{} {}", negative, num, "B");
+/// What the system keeps about a file besides its content, as meta of its
+/// entry: its permissions, SELinux label, POSIX ACLs and with --owner who
+/// owns it.
+fn attributes(zip: &mut Zip, path: &Path, metadata: &Metadata) -> Vec<(String, String)> {
+    let mut meta: Vec<_> = mode::meta(metadata).into_iter().collect();
+    if zip.owner {
+        meta.extend(owner::meta(metadata, zip.numeric_owner));
+    }
+    for (name, key) in xattr::KEPT {
+        match xattr::get(path, name) {
+            Ok(Some(value)) => meta.push((key.to_string(), xattr::to_meta(name, &value))),
+            Ok(None) => {}
+            Err(err) => warn(
+                zip,
+                format!("could not read {name} of {}: {err}", path.display()),
+            ),
+        }
+    }
+    meta
+}
+
+/// Adds the alternate data streams of a file with --ads, each as an entry
+/// named `os_name:stream` right after the file.
+fn add_streams(zip: &mut Zip, os_name: &OsStr, path: &Path, metadata: &Metadata) {
+    if !zip.ads {
+        return;
+    }
+    let streams = match ads::list(path) {
+        Ok(streams) => streams,
+        Err(err) => return skip(zip, format!("the streams of {}", path.display()), err),
+    };
+
+    for stream in streams {
+        let stream_path = ads::path(path.as_os_str(), &stream);
+        match read_file(zip, Path::new(&stream_path)) {
+            Ok(content) => add_file(
+                zip,
+                &ads::path(os_name, &stream),
+                &content,
+                Some(metadata),
+                &[(ads::KEY.to_string(), stream)],
+            ),
+            Err(err) => skip(zip, stream_path.to_string_lossy().to_string(), err),
+        }
     }
-    let delimiter = 1000_f64;
-    let exponent = cmp::min(
-        (num.ln() / delimiter.ln()).floor() as i32,
-        (units.len() - 1) as i32,
+}
+
+/// Reads a file going into the archive, counting the time it takes.
+fn read_file(zip: &mut Zip, path: &Path) -> io::Result<Vec<u8>> {
+    let started = Instant::now();
+    let content = fs::read(path);
+    zip.writer.timings.read += started.elapsed();
+    content
+}
+
+/// Writes a checksum the way sha256sum does, so `sha256sum -c` can check it
+/// next to the archive.
+fn write_checksum_file(checksum_file: &str, output: &str, checksum: &str) {
+    let archive_name = Path::new(output)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| output.to_string());
+
+    if let Err(err) = fs::write(checksum_file, format!("{checksum}  {archive_name}\n")) {
+        println!("kzip: There was an error writing to {checksum_file}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+}
+
+/// Writes archive.kzip.idx for `reader`, which has `archive` open.
+fn write_sidecar(reader: &mut ArchiveReader, archive: &str) {
+    if let Err(err) = sidecar::write(reader, archive) {
+        println!(
+            "kzip: There was an error writing to {}",
+            sidecar::path(archive)
+        );
+        println!("{:#?}", err);
+        exit(1);
+    }
+}
+
+/// Leaves out a file that could not be read. Unless errors are ignored this
+/// stops kzip and removes the unfinished archive.
+fn skip(zip: &mut Zip, file_name: String, err: io::Error) {
+    zip.progress.clear();
+    log::event(
+        "skipped",
+        &[("name", file_name.to_string()), ("error", err.to_string())],
     );
-    let pretty_bytes = format!("{:.2}", num / delimiter.powi(exponent))
-        .parse::<f64>()
-        .unwrap()
-        * 1_f64;
-    let unit = units[exponent as usize];
-    format!("{}{} {}", negative, pretty_bytes, unit)
-}
-
-fn create_dir_if_not_exists(output: &str) {
-    if let Err(err) = fs::metadata(output) {
-        if err.kind() == ErrorKind::NotFound {
-            // directory does not exist, so create it
-            if let Err(err_dir) = fs::create_dir_all(output) {
-                println!("kzip: There was an error creating directory {output}");
-                println!("{:#?}", err_dir);
-                exit(1);
-            }
+    println!("kzip: could not read file {file_name}");
+
+    if !zip.ignore_errors || zip.is_strict {
+        println!("{:#?}", err);
+        if zip.is_strict {
+            log::event(
+                "error",
+                &[("message", "stopped because of --strict".to_string())],
+            );
+            println!("kzip: stopping because of --strict");
         } else {
-            println!("kzip: There was an error writing to {output}");
-            println!("{:#?}", err);
-            exit(1);
+            println!("kzip: use --ignore-errors to leave out files that can't be read");
         }
+        let _ = fs::remove_file(&zip.part);
+        exit(1);
     }
+
+    zip.skipped.push((file_name, err));
 }
 
-fn read_file_into_bytes_until(input: &String, offset: u32, until: u32) -> Vec<u8> {
-    let mut bytes: Vec<u8> = vec![0; until as usize];
-    if let Ok(file) = File::open(input) {
-        let mut reader = BufReader::new(file);
-        reader.seek_relative(offset.into()).unwrap();
-        reader.read(&mut bytes).unwrap();
-    } else {
-        println!("kzip: could not read {input}");
+/// Shows something that may not be what the user expected. Only shown with
+/// --verbose, unless --strict turns it into an error.
+fn warn(zip: &mut Zip, message: String) {
+    log::event("warning", &[("message", message.to_string())]);
+    if zip.verbosity > 0 || zip.is_strict {
+        zip.progress.clear();
+        println!("kzip: {message}");
+    }
+
+    stop_if_strict(zip);
+}
+
+fn stop_if_strict(zip: &mut Zip) {
+    if zip.is_strict {
+        log::event(
+            "error",
+            &[("message", "stopped because of --strict".to_string())],
+        );
+        println!("kzip: stopping because of --strict");
+        let _ = fs::remove_file(&zip.part);
         exit(1);
     }
+}
 
-    return bytes;
+/// A directory that is being read, with the names in it that are still to
+/// come, joined onto it.
+struct OpenDir {
+    id: Option<(u64, u64)>,
+    names: vec::IntoIter<OsString>,
 }
 
-fn read_kzip_file(input: &String, output: &str, is_verbose: bool, is_extract: bool) {
-    let mut index = 0;
-    let mut cached: HashMap<u32, String> = HashMap::new();
-    let mut rpos = 0;
-    let mut bytes = read_file_into_bytes_until(&input, 0, 16);
-    let mut buffer = ByteBuffer::from_bytes(&bytes);
-    let mut mk = 0;
+/// Whether the directory `id` is one of `dirs`, which a symlink in it
+/// leads back to.
+fn loops_back(dirs: &[OpenDir], id: Option<(u64, u64)>) -> bool {
+    id.is_some_and(|id| dirs.iter().any(|dir| dir.id == Some(id)))
+}
 
-    mk += buffer.read_u8().unwrap();
-    mk += buffer.read_u8().unwrap();
-    mk += buffer.read_u8().unwrap();
+/// Reads the directory `root` and everything in it. Directories are read
+/// from a stack rather than by recursing, so trees as deep as the longest
+/// paths allow still fit on the stack of the thread.
+fn read_dir(zip: &mut Zip, root: &Path) {
+    let mut dirs = Vec::new();
+    open_dir(zip, root, &mut dirs);
+    while let Some(dir) = dirs.last_mut() {
+        match dir.names.next() {
+            Some(file_name) => read_dir_entry(zip, &file_name, &mut dirs),
+            None => {
+                dirs.pop();
+            }
+        }
+    }
+}
 
-    if mk != 138 {
-        println!("kzip: {}: Invalid KZip header", input);
-        exit(1);
+fn open_dir(zip: &mut Zip, dir_name: &Path, dirs: &mut Vec<OpenDir>) {
+    let id = fs::metadata(dir_name)
+        .ok()
+        .and_then(|metadata| vfs::dir_id(dir_name, &metadata));
+    if loops_back(dirs, id) {
+        let message = format!(
+            "skipping {}, it links back to a directory that is already being read",
+            dir_name.display()
+        );
+        zip.progress.clear();
+        println!("kzip: {message}");
+        log::event("warning", &[("message", message)]);
+        stop_if_strict(zip);
+        return;
     }
 
-    let _version = buffer.read_string().unwrap();
-    let mut nof = buffer.read_u32().unwrap();
-    let og_nof = nof.clone();
-    let mut total_length: u64 = 0;
-    let mut total_unpacked_length: u64 = 0;
+    let dir_result = match fs::read_dir(dir_name) {
+        Ok(dir_result) => dir_result,
+        Err(err) => return skip(zip, dir_name.display().to_string(), err),
+    };
 
-    rpos += buffer.get_rpos();
-    buffer.clear();
-    buffer.flush().unwrap();
+    let mut names = Vec::new();
+    for result in dir_result {
+        match result {
+            Ok(entry) => names.push(entry.file_name()),
+            Err(err) => skip(zip, dir_name.display().to_string(), err),
+        }
+    }
+    if zip.deterministic {
+        // read_dir returns names in whatever order the file system keeps them
+        names.sort();
+    }
+    if zip.order == Order::Dir {
+        // files before directories, so every directory is written in one go
+        // when extracting
+        names.sort_by_key(|name| fs::metadata(join_name(dir_name, name)).is_ok_and(|m| m.is_dir()));
+    }
 
-    while nof > 0 {
-        bytes = read_file_into_bytes_until(&input, rpos as u32, 1024);
-        buffer = ByteBuffer::from_bytes(&bytes);
+    let names: Vec<_> = names.iter().map(|name| join_name(dir_name, name)).collect();
+    dirs.push(OpenDir {
+        id,
+        names: names.into_iter(),
+    });
+}
 
-        let is_duplicate = buffer.read_u8().unwrap();
-        let file_name = parse_file_path(buffer.read_string().unwrap());
-        let created_at = buffer.read_u64().unwrap();
-        let modified = buffer.read_u64().unwrap();
+fn read_dir_entry(zip: &mut Zip, file_name: &OsStr, dirs: &mut Vec<OpenDir>) {
+    let display_name = file_name.to_string_lossy().to_string();
+    // the input itself is 0 deep
+    let depth = dirs.len();
+
+    match fs::metadata(file_name) {
+        Ok(metadata) if metadata.is_dir() => {
+            if !zip
+                .filter
+                .include_dir(Path::new(file_name), &metadata, depth)
+            {
+                if zip.verbosity > 0 {
+                    println!("kzip: leaving out directory: {display_name}");
+                }
+                return;
+            }
+            if zip.verbosity > 0 {
+                println!("kzip: reading directory: {display_name}");
+            }
 
-        if is_duplicate == 1 {
-            let file_index = buffer.read_u32().unwrap();
-            rpos += buffer.get_rpos();
+            open_dir(zip, Path::new(file_name), dirs);
+        }
+        Ok(metadata) if !zip.filter.include_file(Path::new(file_name), &metadata) => {
+            if zip.verbosity > 0 {
+                println!("kzip: leaving out file: {display_name}");
+            }
+        }
+        Ok(metadata) if !metadata.is_file() => add_special(zip, file_name, &metadata),
+        Ok(metadata) if zip.order == Order::Size => {
+            zip.by_size.push((file_name.to_os_string(), metadata))
+        }
+        Ok(metadata) => add_regular_file(zip, file_name, &metadata),
+        Err(err) => skip(zip, display_name, err),
+    }
+}
 
-            if !is_extract {
-                println!("{file_name} (duplicate)");
-            } else {
-                let cached_name = cached.get(&file_index).unwrap();
-                let content = fs::read(cached_name).unwrap();
-                write_file(output, &file_name, &content);
+fn add_regular_file(zip: &mut Zip, file_name: &OsStr, metadata: &Metadata) {
+    let display_name = file_name.to_string_lossy().to_string();
+    match read_file(zip, Path::new(file_name)) {
+        Ok(content) => {
+            if zip.verbosity > 0 {
+                println!("kzip: reading file: {display_name}");
             }
-        } else {
-            let unpacked_length = buffer.read_u64().unwrap();
-            let length = buffer.read_u64().unwrap();
 
-            if is_extract {
-                rpos += buffer.get_rpos();
+            let meta = attributes(zip, Path::new(file_name), metadata);
+            add_file(zip, file_name, &content, Some(metadata), &meta);
+            add_streams(zip, file_name, Path::new(file_name), metadata);
+        }
+        Err(err) => skip(zip, display_name, err),
+    }
+}
+
+/// Adds the files --order size left for after reading every directory,
+/// smallest first. Files of the same size stay in the order they were found.
+fn add_by_size(zip: &mut Zip) {
+    let mut files = std::mem::take(&mut zip.by_size);
+    files.sort_by_key(|(_, metadata)| metadata.len());
+    for (file_name, metadata) in files {
+        add_regular_file(zip, &file_name, &metadata);
+    }
+}
+
+/// `dir_name/file_name`, without going through a String so names that
+/// aren't UTF-8 stay as they are.
+fn join_name(dir_name: &Path, file_name: &OsStr) -> OsString {
+    let mut name = dir_name.as_os_str().to_os_string();
+    name.push(path::MAIN_SEPARATOR_STR);
+    name.push(file_name);
+    name
+}
 
-                let file_bytes = read_file_into_bytes_until(&input, rpos as u32, length as u32);
+fn get_number_of_files(dir_name: &Path, filter: &Filter) -> u32 {
+    let mut files = Vec::new();
+    list_files(dir_name, filter, &mut files);
+    files.len() as u32
+}
 
-                let mut file_buffer = ByteBuffer::from(file_bytes);
-                let bytes = file_buffer.read_bytes(length as usize).unwrap();
-                let content = decode(&bytes, unpacked_length);
+/// Finds every file that would be zipped, with its size.
+fn list_files(root: &Path, filter: &Filter, files: &mut Vec<(OsString, u64)>) {
+    let mut dirs = Vec::new();
+    list_file(root.as_os_str(), filter, &mut dirs, files);
+    while let Some(dir) = dirs.last_mut() {
+        match dir.names.next() {
+            Some(file_name) => list_file(&file_name, filter, &mut dirs, files),
+            None => {
+                dirs.pop();
+            }
+        }
+    }
+}
 
-                write_file(output, &file_name, &content);
+fn list_file(
+    file_name: &OsStr,
+    filter: &Filter,
+    dirs: &mut Vec<OpenDir>,
+    files: &mut Vec<(OsString, u64)>,
+) {
+    let path = Path::new(file_name);
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            let id = vfs::dir_id(path, &metadata);
+            if loops_back(dirs, id) {
+                return;
+            }
 
-                rpos += length as usize;
-            } else {
-                rpos += buffer.get_rpos() + length as usize;
+            // the input itself is always read
+            let depth = dirs.len();
+            if depth > 0 && !filter.include_dir(path, &metadata, depth) {
+                return;
             }
 
-            total_length += length;
-            total_unpacked_length += unpacked_length;
+            if let Ok(dir) = fs::read_dir(path) {
+                let names: Vec<_> = dir
+                    .flatten()
+                    .map(|entry| join_name(path, &entry.file_name()))
+                    .collect();
+                dirs.push(OpenDir {
+                    id,
+                    names: names.into_iter(),
+                });
+            }
+        }
+        Ok(metadata) if metadata.is_file() && filter.include_file(path, &metadata) => {
+            files.push((file_name.to_os_string(), metadata.len()))
+        }
+        _ => {}
+    }
+}
 
-            cached.insert(
-                index,
-                format!("{output}{}{file_name}", path::MAIN_SEPARATOR),
+/// How an archive is listed or extracted.
+struct ReadOptions<'a> {
+    verbosity: u8,
+    normalize: Normalization,
+    is_strict: bool,
+    show_progress: bool,
+    // rename existing files to name.~1~ instead of overwriting them
+    backup: bool,
+    is_dry_run: bool,
+    // renames entries while extracting
+    transforms: &'a [Transform],
+    // line endings for text files
+    eol: Option<Eol>,
+    // for reading encrypted files
+    password: Option<&'a str>,
+    // skips the stricter checks archives are extracted with
+    is_trusted: bool,
+    // only extracts files with one of these SHA-256 hashes
+    hashes: Option<&'a HashSet<String>>,
+    // lists how well every file compressed
+    show_ratio: bool,
+    // stops when what is extracted doesn't fit on the file system
+    check_space: bool,
+    // stops before extracting archives that would unpack to more than this
+    // in total, to more than this times what is stored of a file, or to
+    // more files
+    max_unpacked_size: Option<u64>,
+    max_ratio: Option<f64>,
+    max_entries: Option<u64>,
+    // shows where the time went
+    profile: bool,
+    // shows the most memory that was used at once
+    report_memory: bool,
+    // restores alternate data streams
+    ads: bool,
+    // restores SELinux labels
+    selinux: bool,
+    // restores POSIX ACLs
+    acls: bool,
+    // restores owners by their ids instead of their names
+    numeric_owner: bool,
+    // applies stored permissions without the umask
+    preserve_permissions: bool,
+    // creates device nodes
+    devices: bool,
+    // the stored times that are set on extracted files
+    preserve_times: times::Preserve,
+    // writes the index next to the archive before reading it
+    write_index: bool,
+}
+
+fn read_kzip_file(input: &str, output: &str, is_extract: bool, options: &ReadOptions) {
+    let ReadOptions {
+        verbosity,
+        normalize,
+        is_strict,
+        show_progress,
+        backup,
+        is_dry_run,
+        transforms,
+        eol,
+        password,
+        is_trusted,
+        hashes,
+        show_ratio,
+        check_space,
+        max_unpacked_size,
+        max_ratio,
+        max_entries,
+        profile,
+        report_memory,
+        ads,
+        selinux,
+        acls,
+        numeric_owner,
+        preserve_permissions,
+        devices,
+        preserve_times,
+        write_index,
+    } = *options;
+    let started = Instant::now();
+
+    let opened = ArchiveReader::open_indexed(input, is_extract && !is_trusted);
+    let mut reader = match opened {
+        Ok(reader) => reader,
+        Err(err) if err.kind() == ErrorKind::InvalidData => {
+            log::event(
+                "error",
+                &[("input", input.to_string()), ("error", err.to_string())],
+            );
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+        Err(err) => {
+            log::event(
+                "error",
+                &[("input", input.to_string()), ("error", err.to_string())],
             );
+            println!("kzip: could not read {input}");
+            exit(1);
+        }
+    };
 
-            index += 1;
-
-            if !is_extract {
-                if is_verbose {
-                    println!(
-                        "{file_name}\n  Created At: {}, Last Modified: {}\n  Packed: {}, Unpacked: {}",
-                        OffsetDateTime::from_unix_timestamp(created_at as i64)
-                            .unwrap()
-                            .date(),
-                        OffsetDateTime::from_unix_timestamp(modified as i64)
-                            .unwrap()
-                            .date(),
-                        format_byte(length as f64),
-                        format_byte(unpacked_length as f64)
+    for warning in &reader.warnings {
+        log::event("warning", &[("message", warning.to_string())]);
+        println!("kzip: {input}: {warning}");
+    }
+    if is_strict && !reader.warnings.is_empty() {
+        log::event(
+            "error",
+            &[("message", "stopped because of --strict".to_string())],
+        );
+        println!("kzip: stopping because of --strict");
+        exit(1);
+    }
+
+    reader.password = password.map(Password::new);
+    if is_extract && password.is_none() && reader.entries.iter().any(|entry| entry.encrypted) {
+        println!("kzip: {input} has encrypted files, set KZIP_PASSWORD or use --password-file");
+        exit(1);
+    }
+
+    if write_index {
+        write_sidecar(&mut reader, input);
+    }
+
+    if is_extract && !is_dry_run && devices && !utils::is_privileged(utils::CAP_MKNOD) {
+        println!("kzip: --devices needs root to create device nodes");
+        exit(1);
+    }
+
+    if is_extract && check_space {
+        check_free_space(&reader, input, output);
+    }
+    if is_extract {
+        check_limits(&reader, input, max_unpacked_size, max_ratio, max_entries);
+    }
+
+    if is_extract && is_dry_run {
+        return dry_run_extract(&mut reader, output, options);
+    }
+
+    let mut total_length: u64 = 0;
+    let mut total_unpacked_length: u64 = 0;
+    // files that take up more space than they would uncompressed
+    let mut expanded: u32 = 0;
+    let mut expanded_bytes: u64 = 0;
+    let mut progress = Progress::new(reader.entries.len() as u64, show_progress);
+    let mut found_hashes = HashSet::new();
+    // alternate data streams that weren't extracted
+    let mut left_out_streams = 0;
+    // device nodes that weren't created without --devices
+    let mut left_out_devices = 0;
+    // files whose names lead out of the output, with --trusted
+    let mut left_out_outside = 0;
+    // the attributes that are restored
+    let mut attributes = Vec::new();
+    if selinux {
+        attributes.push(xattr::SELINUX);
+    }
+    if acls {
+        attributes.extend([xattr::ACL, xattr::DEFAULT_ACL]);
+    }
+    // only root can give files to someone else
+    let can_chown = is_extract && owner::can_chown();
+    // files whose attributes couldn't be set, and why
+    let mut unrestored: Vec<(String, io::Error)> = Vec::new();
+
+    for entry in reader.entries.clone() {
+        let file_name = &normalize.apply(&entry.name);
+        if is_strict && is_extract && *file_name != entry.name {
+            println!("kzip: {} would be extracted as {file_name}", entry.name);
+            println!("kzip: stopping because of --strict");
+            exit(1);
+        }
+        let file_name = &if is_extract {
+            transform::apply_all(transforms, file_name)
+        } else {
+            file_name.to_string()
+        };
+        if file_name.is_empty() {
+            if verbosity > 0 {
+                println!(
+                    "kzip: leaving out {}, --transform removed its name",
+                    entry.name
+                );
+            }
+            continue;
+        }
+        let os_name = match entry.raw_name {
+            Some(_) => entry.os_name(),
+            None => OsString::from(file_name),
+        };
+
+        // names that aren't UTF-8 are extracted as they are stored
+        let extracted_name = match entry.raw_name {
+            Some(_) => &entry.name,
+            None => file_name,
+        };
+        if is_extract && !vfs::is_inside(extracted_name) {
+            progress.clear();
+            println!(
+                "kzip: leaving out {extracted_name}, it would be extracted outside of {output}"
+            );
+            if is_strict {
+                println!("kzip: stopping because of --strict");
+                exit(1);
+            }
+            left_out_outside += 1;
+            progress.add(0);
+            continue;
+        }
+
+        if is_extract {
+            if verbosity > 0 {
+                println!("kzip: extracting file: {file_name}");
+            }
+
+            if let Some((_, stream)) = entry.meta.iter().find(|(key, _)| key == ads::KEY) {
+                if !ads || !cfg!(windows) {
+                    left_out_streams += 1;
+                    progress.add(0);
+                    continue;
+                }
+                let file_name = file_name
+                    .strip_suffix(&format!(":{stream}"))
+                    .unwrap_or(file_name);
+                let path = ads::path(&output_path(output, OsStr::new(file_name)), stream);
+                if let Err(err) = reader
+                    .read(&entry)
+                    .and_then(|content| fs::write(&path, content))
+                {
+                    progress.clear();
+                    println!("kzip: could not extract {}", path.to_string_lossy());
+                    println!("{:#?}", err);
+                    exit(1);
+                }
+                progress.add(entry.unpacked_length);
+                continue;
+            }
+
+            // special files have no content to hash
+            if entry.special && hashes.is_some() {
+                continue;
+            }
+            if entry.special {
+                match reader.read_special(&entry) {
+                    Ok(special) if special.is_device() && !devices => {
+                        if verbosity > 0 {
+                            println!("kzip: leaving out {} {file_name}", special.kind.name());
+                        }
+                        left_out_devices += 1;
+                        progress.add(0);
+                    }
+                    Ok(special) => {
+                        write_special(output, &os_name, special, backup);
+                        let path = output_path(output, &os_name);
+                        let restored = if can_chown {
+                            restore_owner(output, &os_name, &entry, numeric_owner)
+                        } else {
+                            Ok(())
+                        }
+                        .and_then(|_| {
+                            mode::apply(Path::new(&path), special.mode, preserve_permissions)
+                        });
+                        if let Err(err) = restored {
+                            unrestored.push((file_name.to_string(), err));
+                        }
+                        progress.add(0);
+                        log::event(
+                            "extracted",
+                            &[
+                                ("name", file_name.to_string()),
+                                ("kind", special.kind.name().to_string()),
+                            ],
+                        );
+                    }
+                    Err(err) => {
+                        progress.clear();
+                        println!("kzip: could not read {file_name} from {input}");
+                        println!("{:#?}", err);
+                        exit(1);
+                    }
+                }
+                continue;
+            }
+
+            // the index knows the hash without anything being decompressed
+            if let (Some(hashes), Some(hash)) = (hashes, reader.hash(&entry)) {
+                if !hashes.contains(&hash) {
+                    progress.add(0);
+                    continue;
+                }
+            }
+
+            let started = Instant::now();
+            match reader.read(&entry) {
+                Ok(content) => {
+                    if let Some(hashes) = hashes {
+                        let hashing = Instant::now();
+                        let hash = sha256::digest(&content[..]);
+                        reader.timings.hash += hashing.elapsed();
+                        if !hashes.contains(&hash) {
+                            progress.add(0);
+                            continue;
+                        }
+                        found_hashes.insert(hash);
+                    }
+                    let content = match eol {
+                        Some(eol) => eol.apply(&content),
+                        None => Cow::Borrowed(&content[..]),
+                    };
+                    let writing = Instant::now();
+                    if let Err(err) = write_file(output, &os_name, &content, backup) {
+                        progress.clear();
+                        log::event(
+                            "error",
+                            &[("name", file_name.to_string()), ("error", err.to_string())],
+                        );
+                        println!("kzip: could not write {file_name} to {output}");
+                        println!("{:#?}", err);
+                        exit(1);
+                    }
+                    reader.timings.write += writing.elapsed();
+                    let restored = times::apply(
+                        Path::new(&output_path(output, &os_name)),
+                        &entry,
+                        preserve_times,
+                    )
+                    .and_then(|_| restore_attributes(output, &os_name, &entry, &attributes))
+                    .and_then(|_| {
+                        if can_chown {
+                            restore_owner(output, &os_name, &entry, numeric_owner)
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    // giving a file away takes away its setuid bit
+                    .and_then(|_| restore_mode(output, &os_name, &entry, preserve_permissions));
+                    if let Err(err) = restored {
+                        unrestored.push((file_name.to_string(), err));
+                    }
+                    progress.add(content.len() as u64);
+                    log::event(
+                        "extracted",
+                        &[
+                            ("name", file_name.to_string()),
+                            ("size", content.len().to_string()),
+                        ],
                     );
-                } else {
-                    println!("{file_name}");
+                    if verbosity >= 2 {
+                        println!(
+                            "  {} -> {} ({}) in {} ms",
+                            format_byte(entry.length as f64),
+                            format_byte(content.len() as f64),
+                            format_ratio(entry.length, entry.unpacked_length),
+                            started.elapsed().as_millis()
+                        );
+                    }
+                    if verbosity >= 3 {
+                        match entry.duplicate_of {
+                            Some(original) => println!(
+                                "  data at offset {}, same content as file {original}",
+                                entry.offset
+                            ),
+                            None => println!("  data at offset {}", entry.offset),
+                        }
+                    }
+                }
+                Err(err) => {
+                    progress.clear();
+                    log::event(
+                        "error",
+                        &[("name", file_name.to_string()), ("error", err.to_string())],
+                    );
+                    println!("kzip: could not read {file_name} from {input}");
+                    println!("{:#?}", err);
+                    exit(1);
                 }
             }
+        } else if show_ratio && !entry.special && !entry.is_duplicate() {
+            // what encryption adds isn't the codec's fault
+            let overhead = if entry.encrypted { crypto::OVERHEAD } else { 0 };
+            let length = entry.length.saturating_sub(overhead as u64);
+            let flag = if length > entry.unpacked_length {
+                expanded += 1;
+                expanded_bytes += length - entry.unpacked_length;
+                " (expanded)"
+            } else {
+                ""
+            };
+            println!(
+                "{file_name}  {} -> {} ({}){flag}",
+                format_byte(entry.unpacked_length as f64),
+                format_byte(length as f64),
+                format_ratio(length, entry.unpacked_length)
+            );
+        } else if entry.special {
+            match reader.read_special(&entry) {
+                Ok(special) if special.is_device() => {
+                    let (major, minor) = special.device_numbers();
+                    println!("{file_name} ({} {major}, {minor})", special.kind.name());
+                }
+                Ok(special) => println!("{file_name} ({})", special.kind.name()),
+                Err(_) => println!("{file_name} (special file)"),
+            }
+        } else if entry.is_duplicate() {
+            println!("{file_name} (duplicate)");
+        } else if entry.encrypted && verbosity == 0 {
+            println!("{file_name} (encrypted)");
+        } else if verbosity > 0 {
+            println!(
+                "{file_name}\n  Created At: {}, Last Modified: {}\n  Packed: {}, Unpacked: {}, Codec: {}",
+                // fall back to the modified time when the creation time wasn't stored
+                OffsetDateTime::from_unix_timestamp(
+                    entry.created_at.unwrap_or(entry.modified) as i64
+                )
+                .unwrap()
+                .date(),
+                OffsetDateTime::from_unix_timestamp(entry.modified as i64)
+                    .unwrap()
+                    .date(),
+                format_byte(entry.length as f64),
+                format_byte(entry.unpacked_length as f64),
+                entry.codec.name() + if entry.encrypted { ", encrypted" } else { "" }
+            );
+            for (key, value) in &entry.meta {
+                println!("  {key}: {value}");
+            }
+        } else {
+            println!("{file_name}");
         }
 
-        buffer.clear();
-        buffer.flush().unwrap();
-        nof -= 1;
+        if !entry.is_duplicate() {
+            total_length += entry.length;
+            total_unpacked_length += entry.unpacked_length;
+        }
+    }
+    progress.clear();
+
+    if let Some(hashes) = hashes.filter(|_| is_extract) {
+        let missing = hashes.len() - found_hashes.len();
+        if missing > 0 {
+            println!(
+                "kzip: {missing} of the {} hashes matched no file",
+                hashes.len()
+            );
+        }
+    }
+    if let Some((name, err)) = unrestored.first() {
+        println!(
+            "kzip: could not set the times, permissions, labels, ACLs or owners of {} file(s), i.e. {name}: {err}",
+            unrestored.len()
+        );
+    }
+    if left_out_devices > 0 {
+        println!("kzip: left out {left_out_devices} device node(s), use --devices as root to create them");
+    }
+    if left_out_outside > 0 {
+        println!("kzip: left out {left_out_outside} file(s) whose names lead outside of {output}");
+    }
+    if left_out_streams > 0 {
+        println!("kzip: left out {left_out_streams} alternate data stream(s), they can only be extracted on Windows with --ads");
+    }
+    if is_extract && profile {
+        print_profile(&reader.timings, started.elapsed(), true);
+    }
+    if is_extract && report_memory {
+        print_peak_memory();
     }
 
     if !is_extract {
-        println!("Total Files: {og_nof}");
+        println!("Total Files: {}", reader.entries.len());
         println!("Total Packed Size: {}", format_byte(total_length as f64));
         println!(
             "Total Unpacked Size: {}",
             format_byte(total_unpacked_length as f64)
         );
-        println!("Compression: {}%", total_unpacked_length / total_length);
+        println!(
+            "Compression: {}",
+            format_ratio(total_length, total_unpacked_length)
+        );
+        if expanded > 0 {
+            println!(
+                "Expanded: {expanded} file(s) are {} bigger than uncompressed, --algo auto or store keeps them as they are",
+                format_byte(expanded_bytes as f64)
+            );
+        }
+        let (history, meta): (Vec<_>, Vec<_>) = reader
+            .meta
+            .iter()
+            .partition(|(key, _)| key.starts_with(history::RESERVED));
+        if !meta.is_empty() {
+            println!("Meta:");
+            for (key, value) in meta {
+                println!("  {key}: {value}");
+            }
+        }
+        if !history.is_empty() {
+            println!("Changes: {}, see kzip history {input}", history.len());
+        }
     }
+}
 
-    exit(0);
+/// Stops when the files in the archive need more space than `output` has
+/// free. Files that would be overwritten count in full, so this errs on not
+/// extracting.
+fn check_free_space(reader: &ArchiveReader, input: &str, output: &str) {
+    let required: u64 = reader
+        .entries
+        .iter()
+        .filter(|entry| !entry.special)
+        .map(|entry| entry.unpacked_length)
+        .sum();
+    let free = match utils::free_space(Path::new(output)) {
+        Ok(free) => free,
+        Err(err) => {
+            println!("kzip: could not find out how much space is free for {output}, extract without --check-space");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    if required > free {
+        log::event(
+            "error",
+            &[
+                ("input", input.to_string()),
+                ("required", required.to_string()),
+                ("free", free.to_string()),
+            ],
+        );
+        println!(
+            "kzip: {input} needs {} to extract, but only {} is free for {output}",
+            format_byte(required as f64),
+            format_byte(free as f64)
+        );
+        exit(1);
+    }
+}
+
+/// Stops when extracting would go past --max-entries, --max-unpacked-size
+/// or --max-ratio. Reading an entry fails when it doesn't unpack to the size
+/// in its header, so checking the headers is enough.
+fn check_limits(
+    reader: &ArchiveReader,
+    input: &str,
+    max_unpacked_size: Option<u64>,
+    max_ratio: Option<f64>,
+    max_entries: Option<u64>,
+) {
+    let stop = |message: String| {
+        log::event(
+            "error",
+            &[("input", input.to_string()), ("error", message.clone())],
+        );
+        println!("kzip: {message}");
+        exit(1);
+    };
+
+    let entries = reader.entries.len() as u64;
+    if let Some(max_entries) = max_entries.filter(|max_entries| entries > *max_entries) {
+        stop(format!(
+            "{input} has {entries} files, more than --max-entries {max_entries}"
+        ));
+    }
+
+    let unpacked_size: u64 = reader
+        .entries
+        .iter()
+        .filter(|entry| !entry.special)
+        .map(|entry| entry.unpacked_length)
+        .fold(0, u64::saturating_add);
+    if let Some(max_size) = max_unpacked_size.filter(|max_size| unpacked_size > *max_size) {
+        stop(format!(
+            "{input} unpacks to {unpacked_size} bytes, more than the {max_size} --max-unpacked-size allows"
+        ));
+    }
+
+    if let Some(max_ratio) = max_ratio {
+        for entry in reader.entries.iter().filter(|entry| !entry.special) {
+            let ratio = entry.unpacked_length as f64 / entry.length.max(1) as f64;
+            if ratio > max_ratio {
+                stop(format!(
+                    "{} in {input} unpacks to {ratio:.0} times its stored size, more than --max-ratio {max_ratio}",
+                    entry.name
+                ));
+            }
+        }
+    }
+}
+
+/// Lists what extracting would create, and which files already exist.
+fn dry_run_extract(reader: &mut ArchiveReader, output: &str, options: &ReadOptions) {
+    let mut required: u64 = 0;
+    let mut conflicts = 0;
+    let mut files = 0;
+
+    for entry in reader.entries.clone() {
+        if let Some(hashes) = options.hashes {
+            // the content has to be read to know its hash, unless there is an index
+            let matches = !entry.special
+                && match reader.hash(&entry) {
+                    Some(hash) => hashes.contains(&hash),
+                    None => reader
+                        .read(&entry)
+                        .is_ok_and(|content| hashes.contains(&sha256::digest(&content[..]))),
+                };
+            if !matches {
+                continue;
+            }
+        }
+
+        let os_name = match entry.raw_name {
+            Some(_) if !vfs::is_inside(&entry.name) => continue,
+            Some(_) => entry.os_name(),
+            None => {
+                let file_name = options.normalize.apply(&entry.name);
+                let file_name = transform::apply_all(options.transforms, &file_name);
+                if file_name.is_empty() || !vfs::is_inside(&file_name) {
+                    continue;
+                }
+                OsString::from(file_name)
+            }
+        };
+        let path = output_path(output, &os_name);
+        let path = path.to_string_lossy();
+
+        match fs::symlink_metadata(&*path) {
+            Ok(_) if options.backup => {
+                conflicts += 1;
+                println!("{path} (exists, would be backed up)");
+            }
+            Ok(_) => {
+                conflicts += 1;
+                println!("{path} (exists, would be overwritten)");
+            }
+            Err(_) => println!("{path}"),
+        }
+        required += entry.unpacked_length;
+        files += 1;
+    }
+
+    println!("kzip: Dry run, nothing was written");
+    println!("  Files: {files}");
+    println!("  Already existing: {conflicts}");
+    println!("  Space needed: {}", format_byte(required as f64));
 }
 
-fn write_file(output: &str, file_name: &String, content: &Vec<u8>) {
-    let formatted_output = format!("{output}{}{file_name}", path::MAIN_SEPARATOR);
-    let split_paths: Vec<&str> = formatted_output.split(path::MAIN_SEPARATOR).collect();
-    let dir_name = &split_paths[0..split_paths.len() - 1].join(path::MAIN_SEPARATOR_STR);
+/// Where an entry ends up when it is extracted to `output`.
+fn output_path(output: &str, file_name: &OsStr) -> OsString {
+    if cfg!(windows) {
+        let file_name = windows_safe_name(&file_name.to_string_lossy());
+        OsString::from(long_path(format!(
+            "{output}{}{file_name}",
+            path::MAIN_SEPARATOR
+        )))
+    } else {
+        join_name(Path::new(output), file_name)
+    }
+}
+
+fn write_file(output: &str, file_name: &OsStr, content: &[u8], backup: bool) -> io::Result<()> {
+    let formatted_output = output_path(output, file_name);
 
-    create_dir_if_not_exists(&dir_name);
+    if let Some(dir_name) = Path::new(&formatted_output).parent() {
+        create_dir_if_not_exists(dir_name);
+    }
+
+    if backup && fs::symlink_metadata(&formatted_output).is_ok() {
+        backup_file(&formatted_output);
+    }
 
-    let mut file = File::create(format!("{output}{}{file_name}", path::MAIN_SEPARATOR)).unwrap();
+    // written next to the file first, so an interrupted extraction doesn't
+    // leave a file behind that looks complete
+    let mut part_output = formatted_output.clone();
+    part_output.push(".kzip-part");
+    signals::remove_on_interrupt(&part_output);
+
+    let written = File::create(&part_output)
+        .and_then(|mut file| file.write_all(content))
+        .and_then(|_| fs::rename(&part_output, formatted_output));
+    if written.is_err() {
+        let _ = fs::remove_file(&part_output);
+    }
 
-    file.write(&content).unwrap();
+    signals::keep();
+    written
 }
 
-fn parse_file_path(mut path: String) -> String {
-    path = path.clone().replace("/", path::MAIN_SEPARATOR_STR);
-    path = path.clone().replace(r"\", path::MAIN_SEPARATOR_STR);
-    if path.starts_with(&format!("..{}", path::MAIN_SEPARATOR).to_string()) {
-        path = path.replace(&format!("..{}", path::MAIN_SEPARATOR).to_string(), "");
+/// Creates a fifo or device node, replacing what is there unless it is
+/// backed up.
+fn write_special(output: &str, file_name: &OsStr, special: Special, backup: bool) {
+    let formatted_output = output_path(output, file_name);
+
+    if let Some(dir_name) = Path::new(&formatted_output).parent() {
+        create_dir_if_not_exists(dir_name);
+    }
+
+    if fs::symlink_metadata(&formatted_output).is_ok() {
+        if backup {
+            backup_file(&formatted_output);
+        } else {
+            let _ = fs::remove_file(&formatted_output);
+        }
+    }
+
+    if let Err(err) = special.create(&formatted_output) {
+        println!(
+            "kzip: could not create {} {}",
+            special.kind.name(),
+            formatted_output.to_string_lossy()
+        );
+        println!("{:#?}", err);
+        exit(1);
     }
+}
 
-    if path.starts_with(&format!(".{}", path::MAIN_SEPARATOR).to_string()) {
-        path = path.replace(&format!(".{}", path::MAIN_SEPARATOR).to_string(), "");
+/// Renames a file to the first free `name.~N~`, like GNU tools do with
+/// `--backup=numbered`.
+fn backup_file(file_name: &OsStr) {
+    let backup_name = (1..)
+        .map(|i| {
+            let mut backup_name = file_name.to_os_string();
+            backup_name.push(format!(".~{i}~"));
+            backup_name
+        })
+        .find(|backup_name| fs::symlink_metadata(backup_name).is_err())
+        .unwrap();
+
+    if let Err(err) = fs::rename(file_name, &backup_name) {
+        println!("kzip: could not back up {}", file_name.to_string_lossy());
+        println!("{:#?}", err);
+        exit(1);
     }
+    log::event(
+        "backup",
+        &[
+            ("name", file_name.to_string_lossy().to_string()),
+            ("backup", backup_name.to_string_lossy().to_string()),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    return path;
+    #[test]
+    fn write_file_cleans_up_when_it_fails() {
+        let output = std::env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        let output = output.to_string_lossy().to_string();
+        fs::create_dir_all(Path::new(&output).join("taken")).unwrap();
+
+        write_file(&output, OsStr::new("file"), b"content", false).unwrap();
+        assert_eq!(
+            fs::read(Path::new(&output).join("file")).unwrap(),
+            b"content"
+        );
+
+        // a directory is in the way of the file
+        assert!(write_file(&output, OsStr::new("taken"), b"content", false).is_err());
+        assert!(!Path::new(&output).join("taken.kzip-part").exists());
+
+        fs::remove_file(Path::new(&output).join("file")).unwrap();
+        fs::remove_dir(Path::new(&output).join("taken")).unwrap();
+    }
 }
@@ -0,0 +1,197 @@
+use std::{collections::HashMap, ffi::OsString, fs, path, process::exit};
+
+use crate::{
+    archive::{ArchiveReader, ArchiveWriter, Compressed, Entry, Header},
+    crypto::Password,
+    history,
+    options::CreateOptions,
+    signals,
+    utils::{option_value, password, positionals},
+};
+
+#[derive(PartialEq)]
+enum Conflict {
+    Fail,
+    KeepFirst,
+    KeepLast,
+    Rename,
+}
+
+/// `kzip merge a.kzip b.kzip... -o combined.kzip [--conflict fail]`
+///
+/// Files are copied over still compressed, they are only decompressed to
+/// find duplicates between the archives.
+pub fn run(args: &[String]) {
+    let value_options = ["--output", "-o", "--conflict", "--password-file"];
+    let inputs = positionals(&args[2..], &value_options);
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    let mut output = match option_value(args, &["--output", "-o"]) {
+        Some(output) if !inputs.is_empty() => output,
+        _ => {
+            println!(
+                "kzip: merge needs archives and an output, i.e. kzip merge a.kzip b.kzip -o c.kzip"
+            );
+            exit(1);
+        }
+    };
+    if !output.ends_with(".kzip") {
+        output += ".kzip";
+    }
+
+    let conflict = match option_value(args, &["--conflict"]).as_deref() {
+        None | Some("fail") => Conflict::Fail,
+        Some("keep-first") => Conflict::KeepFirst,
+        Some("keep-last") => Conflict::KeepLast,
+        Some("rename") => Conflict::Rename,
+        Some(other) => {
+            println!(
+                "kzip: unknown conflict policy {other}, use fail, keep-first, keep-last or rename"
+            );
+            exit(1);
+        }
+    };
+
+    if fs::metadata(&output).is_ok() {
+        println!("kzip: {output} already exists");
+        exit(1);
+    }
+
+    // encrypted files that are renamed are encrypted again for their name
+    let password = match password(args) {
+        Ok(password) => password,
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+    let mut readers: Vec<ArchiveReader> = inputs
+        .iter()
+        .map(|input| match ArchiveReader::open(input) {
+            Ok(mut reader) => {
+                reader.password = password.as_deref().map(Password::new);
+                reader
+            }
+            Err(err) => {
+                println!("kzip: {input}: {err}");
+                exit(1);
+            }
+        })
+        .collect();
+
+    // (archive, entry, name in the merged archive)
+    let mut plan: Vec<(usize, Entry, String)> = Vec::new();
+    let mut names: HashMap<String, usize> = HashMap::new();
+
+    for (archive, reader) in readers.iter().enumerate() {
+        for entry in &reader.entries {
+            let name = match names.get(&entry.name) {
+                None => entry.name.to_string(),
+                Some(existing) => match conflict {
+                    Conflict::Fail => {
+                        println!(
+                            "kzip: {} exists in both {} and {}, use --conflict to pick what happens",
+                            entry.name, inputs[plan[*existing].0], inputs[archive]
+                        );
+                        exit(1);
+                    }
+                    Conflict::KeepFirst => continue,
+                    Conflict::KeepLast => {
+                        plan[*existing] = (archive, entry.clone(), entry.name.to_string());
+                        continue;
+                    }
+                    Conflict::Rename => free_name(&entry.name, &names),
+                },
+            };
+
+            names.insert(name.to_string(), plan.len());
+            plan.push((archive, entry.clone(), name));
+        }
+    }
+
+    // a key in a later archive replaces the same key of an earlier one
+    let mut meta: Vec<(String, String)> = Vec::new();
+    for reader in &readers {
+        for (key, value) in &reader.meta {
+            match meta.iter_mut().find(|(other, _)| other == key) {
+                Some((_, old)) => *old = value.to_string(),
+                None => meta.push((key.to_string(), value.to_string())),
+            }
+        }
+    }
+    // the archives' histories are of other files, this one starts anew
+    history::forget(&mut meta);
+    history::record(&mut meta, "merge", plan.len());
+
+    let options = CreateOptions::new().meta(&meta);
+    let mut writer = match ArchiveWriter::create_with(&output, plan.len() as u32, &options) {
+        Ok(writer) => writer,
+        Err(err) => {
+            println!("kzip: There was an error writing to {output}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    signals::remove_on_interrupt(&output);
+
+    for (archive, entry, name) in &plan {
+        if is_verbose {
+            println!("kzip: merging {name} from {}", inputs[*archive]);
+        }
+
+        // keep names that aren't UTF-8 as they were, unless they were renamed
+        let os_name = if *name == entry.name {
+            entry.os_name()
+        } else {
+            OsString::from(name)
+        };
+        let result = readers[*archive]
+            .read_raw_as(entry, &os_name)
+            .and_then(|compressed| {
+                writer.add_compressed(
+                    &Header {
+                        name: &os_name,
+                        created_at: entry.created_at,
+                        modified: entry.modified,
+                        meta: &entry.meta,
+                    },
+                    Compressed::copy(entry, &compressed)?,
+                )
+            });
+
+        if let Err(err) = result {
+            println!("kzip: could not merge {name} from {}", inputs[*archive]);
+            println!("{:#?}", err);
+            let _ = fs::remove_file(&output);
+            exit(1);
+        }
+    }
+
+    if let Err(err) = writer.finish() {
+        println!("kzip: There was an error writing to {output}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+    signals::keep();
+
+    println!("kzip: Done merging {} files into {output}", plan.len());
+    exit(0);
+}
+
+/// Numbers a name the same way kzip numbers archives, `file.1.txt`.
+fn free_name(name: &str, names: &HashMap<String, usize>) -> String {
+    let (dir, file_name) = match name.rsplit_once(path::MAIN_SEPARATOR) {
+        Some((dir, file_name)) => (format!("{dir}{}", path::MAIN_SEPARATOR), file_name),
+        None => (String::new(), name),
+    };
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (file_name, String::new()),
+    };
+
+    (1..)
+        .map(|i| format!("{dir}{stem}.{i}{extension}"))
+        .find(|candidate| !names.contains_key(candidate))
+        .unwrap()
+}
@@ -0,0 +1,119 @@
+use std::process::exit;
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader, Entry},
+    history,
+    rewrite::{self, Change},
+    utils::positionals,
+};
+
+const USAGE: &str = "kzip: meta needs a command, an archive and a path, i.e.
+  kzip meta set archive.kzip path key=value...
+  kzip meta unset archive.kzip path key...
+  kzip meta get archive.kzip path key
+  kzip meta list archive.kzip path";
+
+/// `kzip meta set|unset|get|list archive.kzip path ...`
+///
+/// Key/value pairs stored with a single file, i.e. the commit it was built
+/// from. Setting and unsetting writes the archive again.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &[]);
+    let [command, input, name, rest @ ..] = &positionals[..] else {
+        println!("{USAGE}");
+        exit(1);
+    };
+    let name = parse_file_path(name.to_string());
+
+    // only setting and unsetting write the archive again
+    let opened = match command.as_str() {
+        "set" | "unset" => ArchiveReader::open_exclusive(input),
+        _ => ArchiveReader::open(input),
+    };
+    let reader = match opened {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    let entry = match reader.get(&name) {
+        Some(entry) => entry.clone(),
+        None => {
+            println!("kzip: {name} does not exist in {input}");
+            exit(1);
+        }
+    };
+
+    match (command.as_str(), rest) {
+        ("list", []) => {
+            for (key, value) in &entry.meta {
+                println!("{key}={value}");
+            }
+        }
+        ("get", [key]) => match entry.meta.iter().find(|(other, _)| other == key) {
+            Some((_, value)) => println!("{value}"),
+            None => {
+                println!("kzip: {name} has no {key}");
+                exit(1);
+            }
+        },
+        ("set", pairs) if !pairs.is_empty() => {
+            let mut meta = entry.meta.clone();
+            if let Err(err) = set(&mut meta, pairs) {
+                println!("kzip: {err}");
+                exit(1);
+            }
+            save(input, reader, &entry, meta);
+        }
+        ("unset", keys) if !keys.is_empty() => {
+            let mut meta = entry.meta.clone();
+            meta.retain(|(key, _)| !keys.contains(key));
+            save(input, reader, &entry, meta);
+        }
+        _ => {
+            println!("{USAGE}");
+            exit(1);
+        }
+    }
+
+    exit(0);
+}
+
+/// Sets every `key=value` pair, replacing the value of keys that exist.
+pub fn set(meta: &mut Vec<(String, String)>, pairs: &[String]) -> Result<(), String> {
+    for pair in pairs {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) if !key.is_empty() => (key, value),
+            _ => return Err(format!("{pair} is not a key=value pair")),
+        };
+        match meta.iter_mut().find(|(other, _)| other == key) {
+            Some((_, old)) => *old = value.to_string(),
+            None => meta.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+fn save(input: &str, mut reader: ArchiveReader, entry: &Entry, meta: Vec<(String, String)>) {
+    let mut meta = Some(meta);
+    let changes = reader
+        .entries
+        .iter()
+        .map(|other| {
+            let mut change = Change::keep(other);
+            if other.name == entry.name {
+                change.meta = meta.take().unwrap_or_default();
+            }
+            change
+        })
+        .collect();
+
+    history::record(&mut reader.meta, "meta", 1);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fs::Metadata, io, path::Path};
+
+/*
+    The permission bits of every file are stored in its meta as octal, so
+    scripts stay executable. Extracting applies them like creating the file
+    would, without what the umask takes away and without setuid, setgid and
+    sticky bits, --preserve-permissions applies them exactly.
+*/
+
+pub const KEY: &str = "kzip.mode";
+
+/// The meta an entry gets for the permissions of the file.
+#[cfg(unix)]
+pub fn meta(metadata: &Metadata) -> Option<(String, String)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((KEY.to_string(), format!("{:o}", metadata.mode() & 0o7777)))
+}
+
+/// Other systems have no permission bits to store.
+#[cfg(not(unix))]
+pub fn meta(_metadata: &Metadata) -> Option<(String, String)> {
+    None
+}
+
+/// The permissions stored in an entry's meta.
+pub fn stored(meta: &[(String, String)]) -> Option<u32> {
+    let (_, mode) = meta.iter().find(|(key, _)| key == KEY)?;
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+/// Sets the permissions of `path` to `mode`, through the umask unless
+/// `preserve`.
+#[cfg(unix)]
+pub fn apply(path: &Path, mode: u32, preserve: bool) -> io::Result<()> {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let mode = if preserve {
+        mode
+    } else {
+        mode & 0o777 & !umask()
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn apply(_path: &Path, _mode: u32, _preserve: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// The umask of kzip, which can only be found out by setting it.
+#[cfg(unix)]
+fn umask() -> u32 {
+    #[cfg(target_os = "macos")]
+    type ModeT = u16;
+    #[cfg(not(target_os = "macos"))]
+    type ModeT = u32;
+
+    extern "C" {
+        fn umask(mask: ModeT) -> ModeT;
+    }
+
+    let mask = unsafe { umask(0o022) };
+    unsafe { umask(mask) };
+    mask as u32
+}
@@ -0,0 +1,91 @@
+use std::{fs, process::exit};
+
+use crate::{
+    archive::ArchiveReader,
+    history,
+    rewrite::{self, Change},
+    utils::{directory_order, format_byte, option_value, positionals},
+};
+
+/// `kzip optimize archive.kzip [--order dir|name]`
+///
+/// Writes an archive again with its files sorted by directory, so extracting
+/// writes one directory at a time, and content that is stored more than
+/// once, i.e. after merging, turned into duplicates.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--order"]);
+    let by_name = match option_value(args, &["--order"]).as_deref() {
+        None | Some("dir") => false,
+        Some("name") => true,
+        Some(order) => {
+            println!("kzip: unknown order {order}, use dir or name");
+            exit(1);
+        }
+    };
+    let [input] = &positionals[..] else {
+        println!("kzip: optimize needs an archive, i.e. kzip optimize archive.kzip");
+        exit(1);
+    };
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    let size_before = archive_size(input);
+    let duplicates_before = duplicates(&reader);
+
+    let mut entries = reader.entries.clone();
+    if by_name {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+    } else {
+        entries.sort_by(|a, b| directory_order(&a.name, &b.name));
+    }
+    let changes = entries.iter().map(Change::keep).collect();
+
+    history::record(&mut reader.meta, "optimize", entries.len());
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    let size_after = archive_size(input);
+    let duplicates_after = match ArchiveReader::open(input) {
+        Ok(reader) => duplicates(&reader),
+        Err(err) => {
+            println!("kzip: could not read {input} back");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    println!("kzip: Done optimizing {input}");
+    println!(
+        "  Size: {} -> {}",
+        format_byte(size_before as f64),
+        format_byte(size_after as f64)
+    );
+    println!("  Duplicates: {duplicates_before} -> {duplicates_after}");
+    println!(
+        "  Reclaimed: {}",
+        format_byte(size_before.saturating_sub(size_after) as f64)
+    );
+    exit(0);
+}
+
+fn archive_size(input: &str) -> u64 {
+    fs::metadata(input)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default()
+}
+
+fn duplicates(reader: &ArchiveReader) -> usize {
+    reader
+        .entries
+        .iter()
+        .filter(|entry| entry.is_duplicate())
+        .count()
+}
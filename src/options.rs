@@ -0,0 +1,109 @@
+//! How archives are written and extracted by the library, set up like
+//! `CreateOptions::new().codec(Codec::Store).exclude("target")`.
+
+use crate::{codec::Codec, VERSION};
+
+/// For `ArchiveWriter::create_with`.
+#[derive(Clone, Debug)]
+pub struct CreateOptions {
+    /// What `add_tree` compresses files with.
+    pub codec: Codec,
+    /// Stores files with the same content only once, the default.
+    pub dedupe: bool,
+    /// Names of files and directories `add_tree` leaves out, i.e. `.git`.
+    pub excludes: Vec<String>,
+    /// The version in the header, only informational, `--deterministic`
+    /// leaves it empty so archives made by different versions of kzip can
+    /// be the same.
+    pub version: String,
+    /// Key/value pairs about the whole archive, keys can't have `=` and
+    /// neither can have new lines.
+    pub meta: Vec<(String, String)>,
+}
+
+impl Default for CreateOptions {
+    fn default() -> CreateOptions {
+        CreateOptions {
+            codec: Codec::default(),
+            dedupe: true,
+            excludes: Vec::new(),
+            version: VERSION.to_string(),
+            meta: Vec::new(),
+        }
+    }
+}
+
+impl CreateOptions {
+    pub fn new() -> CreateOptions {
+        CreateOptions::default()
+    }
+
+    pub fn codec(mut self, codec: Codec) -> CreateOptions {
+        self.codec = codec;
+        self
+    }
+
+    pub fn dedupe(mut self, dedupe: bool) -> CreateOptions {
+        self.dedupe = dedupe;
+        self
+    }
+
+    pub fn exclude(mut self, name: &str) -> CreateOptions {
+        self.excludes.push(name.to_string());
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> CreateOptions {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn meta(mut self, meta: &[(String, String)]) -> CreateOptions {
+        self.meta.extend_from_slice(meta);
+        self
+    }
+}
+
+/// For `ArchiveReader::extract_into`.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    /// Replaces files that are already there, the default, otherwise
+    /// finding one is an error.
+    pub overwrite: bool,
+    /// How many directories to take off the start of every name, entries
+    /// with no more than that are left out.
+    pub strip_components: usize,
+    /// Sets when files were last modified to the time stored with them.
+    pub preserve_times: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            overwrite: true,
+            strip_components: 0,
+            preserve_times: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> ExtractOptions {
+        ExtractOptions::default()
+    }
+
+    pub fn overwrite(mut self, overwrite: bool) -> ExtractOptions {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn strip_components(mut self, strip_components: usize) -> ExtractOptions {
+        self.strip_components = strip_components;
+        self
+    }
+
+    pub fn preserve_times(mut self, preserve_times: bool) -> ExtractOptions {
+        self.preserve_times = preserve_times;
+        self
+    }
+}
@@ -0,0 +1,150 @@
+use std::{fs::Metadata, io, path::Path};
+
+/*
+    With --owner the user and group of every file are stored in its meta,
+    as ids and as names, since the same names can have other ids on the
+    system the archive is extracted on. Only root, or a process allowed to
+    chown, can give files away, so extracting as anyone else leaves them
+    owned by whoever extracts them.
+*/
+
+pub const UID_KEY: &str = "kzip.uid";
+pub const GID_KEY: &str = "kzip.gid";
+pub const USER_KEY: &str = "kzip.user";
+pub const GROUP_KEY: &str = "kzip.group";
+
+/// The meta an entry gets for who owns the file, without the names with
+/// `numeric`.
+#[cfg(unix)]
+pub fn meta(metadata: &Metadata, numeric: bool) -> Vec<(String, String)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (uid, gid) = (metadata.uid(), metadata.gid());
+    let mut meta = vec![
+        (UID_KEY.to_string(), uid.to_string()),
+        (GID_KEY.to_string(), gid.to_string()),
+    ];
+    if !numeric {
+        if let Some(user) = sys::user_name(uid) {
+            meta.push((USER_KEY.to_string(), user));
+        }
+        if let Some(group) = sys::group_name(gid) {
+            meta.push((GROUP_KEY.to_string(), group));
+        }
+    }
+    meta
+}
+
+/// Files have no owner kzip can store on other systems.
+#[cfg(not(unix))]
+pub fn meta(_metadata: &Metadata, _numeric: bool) -> Vec<(String, String)> {
+    Vec::new()
+}
+
+/// The ids to give an extracted file from its entry's meta, the ones of the
+/// names on this system unless `numeric`, or when it doesn't have them.
+pub fn ids(meta: &[(String, String)], numeric: bool) -> Option<(u32, u32)> {
+    let value = |key: &str| {
+        meta.iter()
+            .find(|(other, _)| other == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let uid = value(UID_KEY)?.parse().ok()?;
+    let gid = value(GID_KEY)?.parse().ok()?;
+    if numeric {
+        return Some((uid, gid));
+    }
+
+    let uid = value(USER_KEY).and_then(sys::user_id).unwrap_or(uid);
+    let gid = value(GROUP_KEY).and_then(sys::group_id).unwrap_or(gid);
+    Some((uid, gid))
+}
+
+/// Whether this process may give files to other users.
+pub fn can_chown() -> bool {
+    crate::utils::is_privileged(crate::utils::CAP_CHOWN)
+}
+
+/// Gives `path` to `uid` and `gid`, the link itself for symlinks.
+#[cfg(unix)]
+pub fn chown(path: &Path, (uid, gid): (u32, u32)) -> io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+pub fn chown(_path: &Path, _ids: (u32, u32)) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "owners can't be set on this system",
+    ))
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{c_char, CStr, CString};
+
+    // the fields of struct passwd and struct group up to the ids, which
+    // are the same on linux and macos
+    #[repr(C)]
+    struct Passwd {
+        pw_name: *const c_char,
+        pw_passwd: *const c_char,
+        pw_uid: u32,
+        pw_gid: u32,
+    }
+
+    #[repr(C)]
+    struct Group {
+        gr_name: *const c_char,
+        gr_passwd: *const c_char,
+        gr_gid: u32,
+    }
+
+    extern "C" {
+        fn getpwuid(uid: u32) -> *const Passwd;
+        fn getpwnam(name: *const c_char) -> *const Passwd;
+        fn getgrgid(gid: u32) -> *const Group;
+        fn getgrnam(name: *const c_char) -> *const Group;
+    }
+
+    // kzip reads the input on one thread, so the static results of these
+    // aren't overwritten while they are used
+    pub fn user_name(uid: u32) -> Option<String> {
+        let passwd = unsafe { getpwuid(uid).as_ref() }?;
+        name(passwd.pw_name)
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        let group = unsafe { getgrgid(gid).as_ref() }?;
+        name(group.gr_name)
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        unsafe { getpwnam(name.as_ptr()).as_ref() }.map(|passwd| passwd.pw_uid)
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        unsafe { getgrnam(name.as_ptr()).as_ref() }.map(|group| group.gr_gid)
+    }
+
+    fn name(name: *const c_char) -> Option<String> {
+        if name.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(name) };
+        Some(name.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
@@ -0,0 +1,769 @@
+/*
+    A small regex engine, enough for searching and renaming entries without
+    pulling in a dependency.
+
+    Supported: literals, `.`, `[...]` / `[^...]` classes, `\d \w \s \D \W \S`,
+    `^`, `$`, `\b`, groups `(...)`, `(?:...)`, alternation `|` and the
+    quantifiers `* + ? {n} {n,} {n,m}` (add `?` for the lazy versions).
+
+    Patterns are compiled into a small program that is run over the text
+    once, with every way it could match being followed side by side (a Pike
+    VM). That takes time in proportion to the text times the pattern, and
+    no stack, so lines of any length and patterns like `(a*)*b` can't blow
+    up. Matches are the ones a backtracking engine would find.
+*/
+
+// the most instructions a pattern can compile to, `{n,m}` copies what it repeats
+const MAX_INSTRUCTIONS: usize = 100_000;
+// the highest count `{n,m}` can have
+const MAX_REPEAT: usize = 1000;
+// how deep groups can be nested
+const MAX_NESTING: usize = 256;
+// a capture slot that wasn't set
+const UNSET: usize = usize::MAX;
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    WordBoundary,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>, bool),
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+/// What a compiled pattern is made of. The first three consume a character,
+/// `Split` tries its first target before its second.
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    WordBoundary,
+    Save(usize),
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+    ignore_case: bool,
+}
+
+/// Byte offsets of the whole match (index 0) and of every capture group.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    depth: usize,
+    pattern: &'a str,
+}
+
+/// A way the pattern could still match, with where its groups are so far.
+struct Thread {
+    pc: usize,
+    slots: Box<[usize]>,
+}
+
+/// Work left while following the instructions that don't consume anything.
+enum Step {
+    Visit(usize),
+    Restore(usize, usize),
+}
+
+impl Regex {
+    pub fn with_options(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+            depth: 0,
+            pattern,
+        };
+        let node = parser.parse_alternate()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ) in {pattern}"));
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&node, &mut program, pattern)?;
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+
+        Ok(Regex {
+            program,
+            groups: parser.groups,
+            ignore_case,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.captures(text).is_some()
+    }
+
+    /// Finds the first match and returns where it and its groups are.
+    pub fn captures(&self, text: &str) -> Option<Captures> {
+        self.captures_from(text, 0)
+    }
+
+    /// Like `captures`, but only starts looking at byte offset `from`.
+    pub fn captures_from(&self, text: &str, from: usize) -> Option<Captures> {
+        let mut at = from;
+        while at <= text.len() && !text.is_char_boundary(at) {
+            at += 1;
+        }
+        if at > text.len() {
+            return None;
+        }
+
+        let empty = vec![UNSET; 2 * (self.groups + 1)];
+        let mut current: Vec<Thread> = Vec::new();
+        let mut next: Vec<Thread> = Vec::new();
+        let mut stack = Vec::new();
+        // which instructions were reached at the current position, by
+        // the generation they were reached in
+        let mut visited = vec![UNSET; self.program.len()];
+        let mut generation = 0;
+        let mut found: Option<Box<[usize]>> = None;
+
+        loop {
+            // a match starting here is worse than any that started earlier
+            if found.is_none() {
+                let mut slots = empty.clone();
+                self.add_thread(
+                    &mut current,
+                    0,
+                    text,
+                    at,
+                    &mut slots,
+                    &mut visited,
+                    generation,
+                    &mut stack,
+                );
+            }
+            if current.is_empty() && found.is_some() {
+                break;
+            }
+
+            let c = text[at..].chars().next();
+            generation += 1;
+            for thread in current.drain(..) {
+                let consumes = match &self.program[thread.pc] {
+                    Inst::Match => {
+                        // the threads after this one are worse matches
+                        found = Some(thread.slots);
+                        break;
+                    }
+                    Inst::Char(expected) => c.is_some_and(|c| self.chars_equal(*expected, c)),
+                    Inst::Any => c.is_some_and(|c| c != '\n'),
+                    Inst::Class(items, negated) => {
+                        c.is_some_and(|c| self.class_matches(items, c) != *negated)
+                    }
+                    _ => false,
+                };
+                if let (true, Some(c)) = (consumes, c) {
+                    let mut slots = thread.slots;
+                    self.add_thread(
+                        &mut next,
+                        thread.pc + 1,
+                        text,
+                        at + c.len_utf8(),
+                        &mut slots,
+                        &mut visited,
+                        generation,
+                        &mut stack,
+                    );
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+
+            match text[at..].chars().next() {
+                Some(c) => at += c.len_utf8(),
+                None => break,
+            }
+        }
+
+        let slots = found?;
+        Some(
+            slots
+                .chunks(2)
+                .map(|pair| match pair {
+                    [start, end] if *start != UNSET && *end != UNSET => Some((*start, *end)),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Adds the thread at `pc` to `threads`, following jumps, splits and
+    /// assertions at byte offset `at` until it reaches instructions that
+    /// consume a character or match.
+    #[allow(clippy::too_many_arguments)]
+    fn add_thread(
+        &self,
+        threads: &mut Vec<Thread>,
+        pc: usize,
+        text: &str,
+        at: usize,
+        slots: &mut [usize],
+        visited: &mut [usize],
+        generation: usize,
+        stack: &mut Vec<Step>,
+    ) {
+        stack.push(Step::Visit(pc));
+        while let Some(step) = stack.pop() {
+            let pc = match step {
+                Step::Visit(pc) => pc,
+                Step::Restore(slot, offset) => {
+                    slots[slot] = offset;
+                    continue;
+                }
+            };
+            // a thread that got here first has priority
+            if visited[pc] == generation {
+                continue;
+            }
+            visited[pc] = generation;
+
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push(Step::Visit(*to)),
+                Inst::Split(first, second) => {
+                    stack.push(Step::Visit(*second));
+                    stack.push(Step::Visit(*first));
+                }
+                Inst::Save(slot) => {
+                    stack.push(Step::Restore(*slot, slots[*slot]));
+                    slots[*slot] = at;
+                    stack.push(Step::Visit(pc + 1));
+                }
+                Inst::Start => {
+                    if at == 0 {
+                        stack.push(Step::Visit(pc + 1));
+                    }
+                }
+                Inst::End => {
+                    if at == text.len() {
+                        stack.push(Step::Visit(pc + 1));
+                    }
+                }
+                Inst::WordBoundary => {
+                    let before = text[..at].chars().next_back().is_some_and(is_word);
+                    let after = text[at..].chars().next().is_some_and(is_word);
+                    if before != after {
+                        stack.push(Step::Visit(pc + 1));
+                    }
+                }
+                Inst::Char(_) | Inst::Any | Inst::Class(..) | Inst::Match => threads.push(Thread {
+                    pc,
+                    slots: slots.into(),
+                }),
+            }
+        }
+    }
+
+    fn chars_equal(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn class_matches(&self, items: &[ClassItem], ch: char) -> bool {
+        let matches = |ch: char| {
+            items.iter().any(|item| match item {
+                ClassItem::Range(from, to) => *from <= ch && ch <= *to,
+                ClassItem::Digit(negated) => ch.is_ascii_digit() != *negated,
+                ClassItem::Word(negated) => is_word(ch) != *negated,
+                ClassItem::Space(negated) => ch.is_whitespace() != *negated,
+            })
+        };
+
+        matches(ch)
+            || (self.ignore_case
+                && (ch.to_lowercase().any(matches) || ch.to_uppercase().any(matches)))
+    }
+}
+
+/// Appends the instructions for `node` to `program`.
+fn compile(node: &Node, program: &mut Vec<Inst>, pattern: &str) -> Result<(), String> {
+    if program.len() > MAX_INSTRUCTIONS {
+        return Err(format!("{pattern} is too large"));
+    }
+
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(items, negated) => program.push(Inst::Class(items.clone(), *negated)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::WordBoundary => program.push(Inst::WordBoundary),
+        Node::Group(inner, index) => match index {
+            Some(index) => {
+                program.push(Inst::Save(2 * index));
+                compile(inner, program, pattern)?;
+                program.push(Inst::Save(2 * index + 1));
+            }
+            None => compile(inner, program, pattern)?,
+        },
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program, pattern)?;
+            }
+        }
+        Node::Alternate(nodes) => {
+            // every branch but the last is tried through a split, and jumps
+            // past the others when it matched
+            let mut jumps = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 == nodes.len() {
+                    compile(node, program, pattern)?;
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(0, 0));
+                compile(node, program, pattern)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(inner, min, max, greedy) => {
+            let split = |from: usize, to: usize| match greedy {
+                true => Inst::Split(from, to),
+                false => Inst::Split(to, from),
+            };
+            for _ in 0..*min {
+                compile(inner, program, pattern)?;
+            }
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Split(0, 0));
+                    compile(inner, program, pattern)?;
+                    program.push(Inst::Jump(start));
+                    program[start] = split(start + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(inner, program, pattern)?;
+                    }
+                    let end = program.len();
+                    for start in splits {
+                        program[start] = split(start + 1, end);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Parser<'_> {
+    fn parse_alternate(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.next().unwrap();
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                let index = if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                self.depth += 1;
+                if self.depth > MAX_NESTING {
+                    return Err(format!("groups are nested too deep in {}", self.pattern));
+                }
+                let inner = self.parse_alternate()?;
+                self.depth -= 1;
+                if self.next() != Some(')') {
+                    return Err(format!("missing ) in {}", self.pattern));
+                }
+                Node::Group(Box::new(inner), index)
+            }
+            '[' => self.parse_class()?,
+            '\\' => match self.next() {
+                Some('b') => Node::WordBoundary,
+                Some(c) => match escape_class(c) {
+                    Some(item) => Node::Class(vec![item], false),
+                    None => Node::Char(escape_char(c)),
+                },
+                None => return Err(format!("trailing \\ in {}", self.pattern)),
+            },
+            '*' | '+' | '?' => {
+                return Err(format!("nothing to repeat before {c} in {}", self.pattern))
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                Some(']') if !first => break,
+                Some(c) => c,
+                None => return Err(format!("missing ] in {}", self.pattern)),
+            };
+            first = false;
+
+            let from = if c == '\\' {
+                let escaped = self
+                    .next()
+                    .ok_or_else(|| format!("trailing \\ in {}", self.pattern))?;
+                if let Some(item) = escape_class(escaped) {
+                    items.push(item);
+                    continue;
+                }
+                escape_char(escaped)
+            } else {
+                c
+            };
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                self.pos += 1;
+                let mut to = self.next().unwrap();
+                if to == '\\' {
+                    to = escape_char(
+                        self.next()
+                            .ok_or_else(|| format!("trailing \\ in {}", self.pattern))?,
+                    );
+                }
+                if to < from {
+                    return Err(format!("invalid range {from}-{to} in {}", self.pattern));
+                }
+                items.push(ClassItem::Range(from, to));
+            } else {
+                items.push(ClassItem::Range(from, from));
+            }
+        }
+
+        Ok(Node::Class(items, negated))
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.parse_braces() {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+
+        if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(format!(
+                "repeats have to be {{n,m}} with n <= m <= {MAX_REPEAT} in {}",
+                self.pattern
+            ));
+        }
+        if matches!(atom, Node::Start | Node::End | Node::WordBoundary) {
+            return Err(format!("nothing to repeat in {}", self.pattern));
+        }
+
+        let greedy = self.peek() != Some('?');
+        if !greedy {
+            self.pos += 1;
+        }
+
+        Ok(Node::Repeat(Box::new(atom), min, max, greedy))
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`, leaving the position on the `}`.
+    fn parse_braces(&mut self) -> Option<(usize, Option<usize>)> {
+        let close = self.chars[self.pos..].iter().position(|c| *c == '}')? + self.pos;
+        let inner: String = self.chars[self.pos + 1..close].iter().collect();
+        let bounds = match inner.split_once(',') {
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+            None => {
+                let count = inner.parse().ok()?;
+                (count, Some(count))
+            }
+        };
+
+        self.pos = close;
+        Some(bounds)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+}
+
+fn escape_class(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(false)),
+        'D' => Some(ClassItem::Digit(true)),
+        'w' => Some(ClassItem::Word(false)),
+        'W' => Some(ClassItem::Word(true)),
+        's' => Some(ClassItem::Space(false)),
+        'S' => Some(ClassItem::Space(true)),
+        _ => None,
+    }
+}
+
+fn escape_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Matches a name against a shell style glob. `*` and `?` stay inside of a
+/// single path component, `**` matches across them.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.replace('\\', "/").chars().collect();
+    let name: Vec<char> = name.replace('\\', "/").chars().collect();
+
+    glob_match_from(&pattern, &name)
+}
+
+fn glob_match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // `a/**/b` should also match `a/b`
+            if rest.first() == Some(&'/') && glob_match_from(&rest[1..], name) {
+                return true;
+            }
+            (0..=name.len()).any(|i| glob_match_from(rest, &name[i..]))
+        }
+        Some('*') => {
+            for i in 0..=name.len() {
+                if glob_match_from(&pattern[1..], &name[i..]) {
+                    return true;
+                }
+                if i < name.len() && name[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => {
+            !name.is_empty() && name[0] != '/' && glob_match_from(&pattern[1..], &name[1..])
+        }
+        Some('[') => match pattern.iter().skip(1).position(|c| *c == ']') {
+            Some(close) if !name.is_empty() => {
+                let class = &pattern[1..close + 1];
+                let (negated, class) = match class.first() {
+                    Some('!') | Some('^') => (true, &class[1..]),
+                    _ => (false, class),
+                };
+
+                let mut matched = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == '-' {
+                        matched |= class[i] <= name[0] && name[0] <= class[i + 2];
+                        i += 3;
+                    } else {
+                        matched |= class[i] == name[0];
+                        i += 1;
+                    }
+                }
+
+                matched != negated && glob_match_from(&pattern[close + 2..], &name[1..])
+            }
+            Some(_) => false,
+            None => name.first() == Some(&'[') && glob_match_from(&pattern[1..], &name[1..]),
+        },
+        Some(c) => name.first() == Some(c) && glob_match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::with_options(pattern, false)
+            .unwrap()
+            .captures(text)
+            .map(|captures| captures[0].unwrap())
+    }
+
+    #[test]
+    fn matches_literals_classes_and_anchors() {
+        assert_eq!(find("b+", "abbbc"), Some((1, 4)));
+        assert_eq!(find("^a", "ba"), None);
+        assert_eq!(find("a$", "ba"), Some((1, 2)));
+        assert_eq!(find("[^a-c]+", "abcdef"), Some((3, 6)));
+        assert_eq!(find(r"\d{2,3}", "a12345"), Some((1, 4)));
+        assert_eq!(find(r"\bcat\b", "concat cat"), Some((7, 10)));
+        assert_eq!(find(r"\s\S", "ab cd"), Some((2, 4)));
+        assert_eq!(find("a.c", "a\nc abc"), Some((4, 7)));
+        assert_eq!(find("", "abc"), Some((0, 0)));
+    }
+
+    #[test]
+    fn prefers_what_backtracking_would() {
+        // the leftmost match, then the first branch and greedy or lazy repeats
+        assert_eq!(find("a|ab", "xab"), Some((1, 2)));
+        assert_eq!(find("ab|a", "xab"), Some((1, 3)));
+        assert_eq!(find("a.*b", "aXbXb"), Some((0, 5)));
+        assert_eq!(find("a.*?b", "aXbXb"), Some((0, 3)));
+        assert_eq!(find("a??", "a"), Some((0, 0)));
+        assert_eq!(find("x*", "ax"), Some((0, 0)));
+    }
+
+    #[test]
+    fn captures_groups() {
+        let regex = Regex::with_options(r"(\w+)@(\w+)(x)?", false).unwrap();
+        let captures = regex.captures("mail me@host now").unwrap();
+        assert_eq!(captures[0], Some((5, 12)));
+        assert_eq!(captures[1], Some((5, 7)));
+        assert_eq!(captures[2], Some((8, 12)));
+        assert_eq!(captures[3], None);
+
+        // the last time a group matched counts
+        let regex = Regex::with_options("(?:(a)|(b))+", false).unwrap();
+        let captures = regex.captures("ab").unwrap();
+        assert_eq!(captures[1], Some((0, 1)));
+        assert_eq!(captures[2], Some((1, 2)));
+    }
+
+    #[test]
+    fn offsets_are_bytes() {
+        let regex = Regex::with_options("é+", false).unwrap();
+        assert_eq!(regex.captures("aéé").unwrap()[0], Some((1, 5)));
+        // a start inside of a character moves on to the next one
+        assert_eq!(regex.captures_from("éaé", 1).unwrap()[0], Some((3, 5)));
+        assert_eq!(regex.captures_from("é", 5), None);
+    }
+
+    #[test]
+    fn ignores_case() {
+        let regex = Regex::with_options("[a-c]+X", true).unwrap();
+        assert!(regex.is_match("ABCx"));
+        assert!(!Regex::with_options("abc", false).unwrap().is_match("ABC"));
+    }
+
+    #[test]
+    fn searches_long_lines() {
+        let line = "a".repeat(1024 * 1024);
+        assert!(!Regex::with_options("a.*b", false).unwrap().is_match(&line));
+        assert!(Regex::with_options("a+$", false).unwrap().is_match(&line));
+        assert_eq!(
+            find("(a|b)*c", &format!("{line}c")),
+            Some((0, line.len() + 1))
+        );
+    }
+
+    #[test]
+    fn doesnt_blow_up_on_pathological_patterns() {
+        let text = "a".repeat(10_000);
+        assert!(!Regex::with_options("(a*)*b", false)
+            .unwrap()
+            .is_match(&text));
+        assert!(!Regex::with_options("(a|aa)+b", false)
+            .unwrap()
+            .is_match(&text));
+        assert!(!Regex::with_options("(a?){30}a{30}b", false)
+            .unwrap()
+            .is_match(&text));
+        assert_eq!(find("(?:)*", "ab"), Some((0, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["(a", "a)", "[a", "*a", "a\\", "[z-a]", "a{3,1}", "a{1001}"] {
+            assert!(Regex::with_options(pattern, false).is_err(), "{pattern}");
+        }
+        assert!(Regex::with_options(&"(".repeat(1000), false).is_err());
+        assert!(Regex::with_options("(a{1000}){1000}", false).is_err());
+        // not a repeat, so just the characters
+        assert_eq!(find("a{x}", "a{x}"), Some((0, 4)));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*.txt", "a.txt"));
+        assert!(!glob_match("*.txt", "dir/a.txt"));
+        assert!(glob_match("**/*.txt", "dir/sub/a.txt"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("file?.[ch]", "file1.c"));
+        assert!(!glob_match("file?.[!ch]", "file1.c"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(glob_match("a\\b", "a/b"));
+        assert!(glob_match("[", "["));
+        assert!(!glob_match("?", "/"));
+    }
+}
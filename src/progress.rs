@@ -0,0 +1,86 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use crate::utils::format_byte;
+
+const WIDTH: usize = 30;
+// how often the bar is drawn at most, drawing it for every file slows kzip down
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// A progress bar on stderr, only shown when stderr is a terminal.
+pub struct Progress {
+    enabled: bool,
+    total_files: u64,
+    files: u64,
+    bytes: u64,
+    started: Instant,
+    drawn_at: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total_files: u64, enabled: bool) -> Progress {
+        Progress {
+            enabled: enabled && io::stderr().is_terminal(),
+            total_files,
+            files: 0,
+            bytes: 0,
+            started: Instant::now(),
+            drawn_at: None,
+        }
+    }
+
+    /// Counts one more file as done.
+    pub fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+
+        if self.enabled
+            && self
+                .drawn_at
+                .is_none_or(|drawn_at| drawn_at.elapsed() >= REDRAW)
+        {
+            self.draw();
+        }
+    }
+
+    /// Removes the bar, so something else can be printed on its line.
+    pub fn clear(&mut self) {
+        if self.enabled && self.drawn_at.is_some() {
+            eprint!("\r\x1b[K");
+            self.drawn_at = None;
+        }
+    }
+
+    fn draw(&mut self) {
+        let total = self.total_files.max(self.files);
+        let done = (self.files * WIDTH as u64)
+            .checked_div(total)
+            .map_or(WIDTH, |done| done as usize);
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            self.bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if self.files > 0 {
+            (elapsed / self.files as f64 * (total - self.files) as f64) as u64
+        } else {
+            0
+        };
+
+        eprint!(
+            "\r\x1b[K[{}{}] {}/{total} files  {}/s  ETA {}:{:02}",
+            "#".repeat(done),
+            "-".repeat(WIDTH - done),
+            self.files,
+            format_byte(throughput),
+            eta / 60,
+            eta % 60
+        );
+        let _ = io::stderr().flush();
+        self.drawn_at = Some(Instant::now());
+    }
+}
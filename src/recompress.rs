@@ -0,0 +1,89 @@
+use std::{fs, process::exit};
+
+use crate::{
+    archive::ArchiveReader,
+    codec::Codec,
+    history,
+    pattern::glob_match,
+    rewrite::{self, Change, Content},
+    utils::{format_byte, option_value, positionals},
+};
+
+/// `kzip recompress archive.kzip 'glob' --algo store [--level 9]`
+///
+/// Compresses the files matching the glob again with another codec, i.e. to
+/// stop compressing images twice. Everything else is copied as it is stored.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--algo", "--level"]);
+    let [input, glob] = &positionals[..] else {
+        println!("kzip: recompress needs an archive and a glob, i.e. kzip recompress archive.kzip 'assets/**' --algo store");
+        exit(1);
+    };
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    // None picks a codec for every file
+    let codec = match option_value(args, &["--algo"]).as_deref() {
+        None => {
+            println!("kzip: recompress needs --algo, i.e. --algo store");
+            exit(1);
+        }
+        Some("auto") => None,
+        Some(algo) => {
+            let level = option_value(args, &["--level"]);
+            match Codec::parse(algo, level.as_deref()) {
+                Ok(codec) => Some(codec),
+                Err(err) => {
+                    println!("kzip: {err}");
+                    exit(1);
+                }
+            }
+        }
+    };
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+    let size_before = fs::metadata(input)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    let mut recompressed = 0;
+    let mut changes = Vec::new();
+    for entry in &reader.entries {
+        let mut change = Change::keep(entry);
+        if glob_match(glob, &entry.name) {
+            if is_verbose {
+                println!("kzip: recompressing {}", entry.name);
+            }
+            change.content = Content::Recompress(entry.clone(), codec);
+            recompressed += 1;
+        }
+        changes.push(change);
+    }
+
+    if recompressed == 0 {
+        println!("kzip: nothing in {input} matches {glob}");
+        exit(1);
+    }
+
+    history::record(&mut reader.meta, "recompress", recompressed);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    let size_after = fs::metadata(input)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    println!(
+        "kzip: Recompressed {recompressed} file(s) in {input}, {} -> {}",
+        format_byte(size_before as f64),
+        format_byte(size_after as f64)
+    );
+    exit(0);
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    path::Path,
+    process::exit,
+};
+
+use crate::{
+    archive::{parse_file_path, ArchiveReader},
+    crypto::Password,
+    history,
+    rewrite::{self, Change},
+    utils::{password, positionals},
+};
+
+/// `kzip rename archive.kzip old/path new/path`
+///
+/// Renames a file, or a directory with everything in it. Only the names
+/// change, the content is copied over as it is stored.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--password-file"]);
+    let [input, old, new] = &positionals[..] else {
+        println!("kzip: rename needs an archive and two names, i.e. kzip rename archive.kzip old/path new/path");
+        exit(1);
+    };
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    // encrypted files are encrypted again for their new name
+    reader.password = match password(args) {
+        Ok(password) => password.as_deref().map(Password::new),
+        Err(err) => {
+            println!("kzip: {err}");
+            exit(1);
+        }
+    };
+
+    let old = parse_file_path(old.trim_end_matches(['/', '\\']).to_string());
+    let new = parse_file_path(new.trim_end_matches(['/', '\\']).to_string());
+
+    let mut renamed = 0;
+    let mut changes = Vec::new();
+    for entry in &reader.entries {
+        let mut change = Change::keep(entry);
+        if let Some(name) = rename(&change.name, &old, &new) {
+            if is_verbose {
+                println!(
+                    "kzip: renaming {} to {}",
+                    entry.name,
+                    name.to_string_lossy()
+                );
+            }
+            if entry.encrypted && reader.password.is_none() {
+                println!("kzip: {} is encrypted, renaming it needs the password, set KZIP_PASSWORD or use --password-file", entry.name);
+                exit(1);
+            }
+            change.name = name;
+            renamed += 1;
+        }
+        changes.push(change);
+    }
+
+    if renamed == 0 {
+        println!("kzip: {old} is not in {input}");
+        exit(1);
+    }
+
+    let mut names = HashSet::new();
+    for change in &changes {
+        if !names.insert(&change.name) {
+            println!(
+                "kzip: {} is already in {input}",
+                change.name.to_string_lossy()
+            );
+            exit(1);
+        }
+    }
+
+    history::record(&mut reader.meta, "rename", renamed);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    println!("kzip: Renamed {renamed} file(s) in {input}");
+    exit(0);
+}
+
+/// The new name of `name` if it is `old` or inside of it, when renaming `old`
+/// to `new`. Only the `old` part is replaced, the rest of a name that isn't
+/// UTF-8 keeps its bytes.
+pub fn rename(name: &OsStr, old: &str, new: &str) -> Option<OsString> {
+    if name == old {
+        return Some(OsString::from(new));
+    }
+
+    let rest = Path::new(name).strip_prefix(old).ok()?;
+    Some(Path::new(new).join(rest).into_os_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::MAIN_SEPARATOR_STR as SEP;
+
+    #[test]
+    fn renames_files_and_directories() {
+        let name = |name: &str| OsString::from(name.replace('/', SEP));
+        assert_eq!(rename(&name("a"), "a", "b"), Some(name("b")));
+        assert_eq!(rename(&name("a/x/y"), "a", "b"), Some(name("b/x/y")));
+        assert_eq!(
+            rename(&name("a/x/y"), &name("a/x").to_string_lossy(), "z"),
+            Some(name("z/y"))
+        );
+        assert_eq!(rename(&name("ab/x"), "a", "b"), None);
+        assert_eq!(rename(&name("b/a"), "a", "b"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let name = OsStr::from_bytes(b"old/caf\xe9/x");
+        assert_eq!(
+            rename(name, "old", "new").unwrap().into_vec(),
+            b"new/caf\xe9/x"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    mem,
+};
+
+use crate::{
+    archive::{ArchiveReader, ArchiveWriter, Compressed, Entry, Header, MAGIC},
+    codec::Codec,
+    options::CreateOptions,
+    signals,
+};
+
+/*
+    Entries are stored one after another with their headers in between, so
+    changing one means writing the archive again. The new archive is written
+    next to the old one and renamed over it once it is complete, content that
+    doesn't change is copied over still compressed.
+*/
+
+/// What ends up in the new archive for an entry.
+pub enum Content {
+    /// The content of an entry as it is stored in the old archive.
+    Copy(Entry),
+    /// New content, compressed with the codec.
+    New(Vec<u8>, Codec),
+    /// The content of an entry compressed again, None picks a codec like
+    /// `--algo auto`.
+    Recompress(Entry, Option<Codec>),
+}
+
+/// An entry of the new archive.
+pub struct Change {
+    pub name: OsString,
+    pub created_at: Option<u64>,
+    pub modified: u64,
+    pub meta: Vec<(String, String)>,
+    pub content: Content,
+}
+
+impl Change {
+    /// Keeps an entry as it is.
+    pub fn keep(entry: &Entry) -> Change {
+        Change {
+            name: entry.os_name(),
+            created_at: entry.created_at,
+            modified: entry.modified,
+            meta: entry.meta.clone(),
+            content: Content::Copy(entry.clone()),
+        }
+    }
+
+    /// Adds a file that wasn't in the archive yet.
+    pub fn add(name: OsString, created_at: Option<u64>, modified: u64, content: Vec<u8>) -> Change {
+        Change {
+            name,
+            created_at,
+            modified,
+            meta: Vec::new(),
+            content: Content::New(content, Codec::default()),
+        }
+    }
+}
+
+/// Replaces `input` with an archive of `changes`, in that order. `reader`
+/// should come from `ArchiveReader::open_exclusive`, so nothing else can
+/// change the archive between reading it and replacing it.
+pub fn rewrite(input: &str, mut reader: ArchiveReader, changes: Vec<Change>) -> io::Result<()> {
+    let mut part = OsString::from(input);
+    part.push(".kzip-part");
+    let part_name = part.to_string_lossy().to_string();
+
+    let options = CreateOptions::new().meta(&reader.meta);
+    let mut writer = ArchiveWriter::create_with(&part_name, changes.len() as u32, &options)?;
+    signals::remove_on_interrupt(&part);
+
+    if let Err(err) = write_changes(&mut reader, &mut writer, changes) {
+        let _ = fs::remove_file(&part);
+        signals::keep();
+        return Err(err);
+    }
+    writer.finish()?;
+
+    // windows can't replace a file that is still open, elsewhere the lock is
+    // kept until the new archive is in place
+    #[cfg(windows)]
+    drop(reader);
+    fs::rename(&part, input)?;
+    signals::keep();
+
+    Ok(())
+}
+
+fn write_changes(
+    reader: &mut ArchiveReader,
+    writer: &mut ArchiveWriter,
+    changes: Vec<Change>,
+) -> io::Result<()> {
+    for change in changes {
+        let header = Header {
+            name: &change.name,
+            created_at: change.created_at,
+            modified: change.modified,
+            meta: &change.meta,
+        };
+        if change.name.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "an entry would have no name",
+            ));
+        }
+
+        match change.content {
+            Content::Copy(entry) => {
+                let compressed = reader.read_raw_as(&entry, &change.name)?;
+                writer.add_compressed(&header, Compressed::copy(&entry, &compressed)?)?;
+            }
+            Content::New(content, codec) => {
+                writer.add(&header, &content, codec)?;
+            }
+            // without the password encrypted files can only be copied
+            Content::Recompress(entry, _) if entry.encrypted => {
+                let compressed = reader.read_raw_as(&entry, &change.name)?;
+                writer.add_compressed(&header, Compressed::copy(&entry, &compressed)?)?;
+            }
+            // special files have nothing to compress
+            Content::Recompress(entry, _) if entry.special => {
+                let special = reader.read_special(&entry)?;
+                writer.add_special(&header, special)?;
+            }
+            Content::Recompress(entry, codec) => {
+                let content = reader.read(&entry)?;
+                let codec = codec.unwrap_or_else(|| Codec::auto(&content));
+                writer.add(&header, &content, codec)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The header `vacuum` writes, with the archive's version and `reader.meta`,
+/// and how much bigger it is than the one the archive has.
+fn vacuum_header(reader: &ArchiveReader) -> (Vec<u8>, i64) {
+    let mut version = reader.version.clone();
+    for (key, value) in &reader.meta {
+        version.push_str(&format!("\n{key}={value}"));
+    }
+    let mut header = MAGIC.to_vec();
+    header.extend((version.len() as u32).to_be_bytes());
+    header.extend(version.as_bytes());
+
+    // the amount of files comes right before the first entry
+    let old_length = reader
+        .entries
+        .first()
+        .map_or(0, |entry| entry.header_offset.saturating_sub(4));
+    let growth = header.len() as i64 - old_length as i64;
+    (header, growth)
+}
+
+/// Whether `vacuum` can remove the entries. A header that got bigger takes
+/// up the space of the first removed entries, which has to be enough.
+pub fn can_vacuum(reader: &ArchiveReader, deleted: &[bool]) -> bool {
+    let (_, growth) = vacuum_header(reader);
+    let Some(first) = deleted.iter().position(|deleted| *deleted) else {
+        return true;
+    };
+    let next_kept = (first..reader.entries.len()).find(|index| !deleted[*index]);
+    growth >= 0
+        && next_kept.is_none_or(|next| {
+            reader.entries[next].header_offset - reader.entries[first].header_offset
+                >= growth as u64
+        })
+}
+
+/// Removes entries by moving the ones after them down inside of the archive
+/// itself, without writing a copy. `reader` has to come from
+/// `ArchiveReader::open_exclusive`, so it can write to the archive.
+/// Duplicates are pointed at the new place of their file, which has to stay.
+///
+/// Unlike `rewrite` an interrupted vacuum leaves a broken archive behind, so
+/// it is only worth it when little is removed from a large archive.
+pub fn vacuum(mut reader: ArchiveReader, deleted: &[bool]) -> io::Result<()> {
+    if !can_vacuum(&reader, deleted) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the header doesn't fit in the space of the removed entries",
+        ));
+    }
+    let (header, growth) = vacuum_header(&reader);
+    let growth = growth as u64;
+
+    let entries = mem::take(&mut reader.entries);
+    let mut file = reader.into_file();
+
+    let orphan = entries.iter().enumerate().find(|(index, entry)| {
+        !deleted[*index] && entry.duplicate_of.is_some_and(|original| deleted[original])
+    });
+    if let Some((_, entry)) = orphan {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is a duplicate of a file that is removed", entry.name),
+        ));
+    }
+
+    let file_length = file.metadata()?.len();
+    let end_of = |index: usize| {
+        entries
+            .get(index + 1)
+            .map_or(file_length, |next| next.header_offset)
+    };
+
+    // duplicates point at the n-th entry that has content
+    let mut new_index: HashMap<usize, u32> = HashMap::new();
+    let mut originals = 0;
+    let Some(first) = deleted.iter().position(|deleted| *deleted) else {
+        return Ok(());
+    };
+    let mut buffer = vec![0; 1 << 20];
+
+    // the header grows into the first removed entry, moving the entries
+    // before it up, from the back so nothing is overwritten before it is
+    // copied
+    let old_header_end = entries[0].header_offset;
+    let mut end = entries[first].header_offset;
+    while growth > 0 && end > old_header_end {
+        let chunk = buffer.len().min((end - old_header_end) as usize);
+        end -= chunk as u64;
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut buffer[..chunk])?;
+        file.seek(SeekFrom::Start(end + growth))?;
+        file.write_all(&buffer[..chunk])?;
+    }
+    let mut position = entries[first].header_offset + growth;
+
+    for (index, entry) in entries.iter().enumerate() {
+        let is_kept = !deleted[index];
+        if !entry.is_duplicate() && is_kept {
+            new_index.insert(index, originals);
+            originals += 1;
+        }
+        if !is_kept || index < first {
+            continue;
+        }
+
+        // the entry only ever moves down, so copying from the front never
+        // overwrites what is still to be copied
+        let start = position;
+        let mut from = entry.header_offset;
+        let end = end_of(index);
+        while from < end {
+            let chunk = buffer.len().min((end - from) as usize);
+            file.seek(SeekFrom::Start(from))?;
+            file.read_exact(&mut buffer[..chunk])?;
+            file.seek(SeekFrom::Start(position))?;
+            file.write_all(&buffer[..chunk])?;
+            from += chunk as u64;
+            position += chunk as u64;
+        }
+
+        if let Some(original) = entry.duplicate_of {
+            // the pointer is the last thing in a duplicate
+            file.seek(SeekFrom::Start(start + (end - entry.header_offset) - 4))?;
+            file.write_all(&new_index[&original].to_be_bytes())?;
+        }
+    }
+
+    // the amount of files comes right after the header
+    let kept = deleted.iter().filter(|deleted| !**deleted).count() as u32;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.write_all(&kept.to_be_bytes())?;
+    file.set_len(position)?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, ffi::OsStr};
+
+    fn create(path: &str, files: &[(&str, &[u8])]) {
+        let mut writer = ArchiveWriter::create(path, files.len() as u32).unwrap();
+        for (name, content) in files {
+            let header = Header {
+                name: OsStr::new(name),
+                created_at: None,
+                modified: 1_700_000_000,
+                meta: &[],
+            };
+            writer.add(&header, content, Codec::default()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn contents(path: &str) -> Vec<(String, Vec<u8>)> {
+        let mut reader = ArchiveReader::open_strict(path).unwrap();
+        reader
+            .entries
+            .clone()
+            .into_iter()
+            .map(|entry| (entry.name.clone(), reader.read(&entry).unwrap()))
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().to_string()
+    }
+
+    const FILES: [(&str, &[u8]); 5] = [
+        ("a", b"first"),
+        ("b", b"removed"),
+        ("c", b"first"),
+        ("d", b"second"),
+        ("e", b"second"),
+    ];
+
+    #[test]
+    fn vacuum_moves_entries_down_and_keeps_duplicates() {
+        let path = temp_path("vacuum.kzip");
+        create(&path, &FILES);
+        let length = fs::metadata(&path).unwrap().len();
+
+        let reader = ArchiveReader::open_exclusive(&path).unwrap();
+        let deleted = [false, true, false, false, false];
+        assert!(can_vacuum(&reader, &deleted));
+        vacuum(reader, &deleted).unwrap();
+
+        assert!(fs::metadata(&path).unwrap().len() < length);
+        let expected: Vec<_> = [
+            ("a", "first"),
+            ("c", "first"),
+            ("d", "second"),
+            ("e", "second"),
+        ]
+        .iter()
+        .map(|(name, content)| (name.to_string(), content.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(contents(&path), expected);
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.entries[1].duplicate_of, Some(0));
+        assert_eq!(reader.entries[3].duplicate_of, Some(2));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn vacuum_makes_room_for_a_bigger_header() {
+        let path = temp_path("vacuum-header.kzip");
+        create(&path, &FILES);
+
+        let mut reader = ArchiveReader::open_exclusive(&path).unwrap();
+        let deleted = [false, true, false, false, false];
+        reader.meta.push(("k".to_string(), "x".repeat(1000)));
+        assert!(!can_vacuum(&reader, &deleted));
+        reader.meta[0].1 = "v".to_string();
+        assert!(can_vacuum(&reader, &deleted));
+        vacuum(reader, &deleted).unwrap();
+
+        let reader = ArchiveReader::open_strict(&path).unwrap();
+        assert_eq!(reader.meta, [("k".to_string(), "v".to_string())]);
+        drop(reader);
+        let names: Vec<_> = contents(&path).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a", "c", "d", "e"]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn vacuum_keeps_files_that_have_duplicates() {
+        let path = temp_path("vacuum-original.kzip");
+        create(&path, &FILES);
+        let bytes = fs::read(&path).unwrap();
+
+        let reader = ArchiveReader::open_exclusive(&path).unwrap();
+        let err = vacuum(reader, &[true, false, false, false, false]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        // removing the duplicate with it is fine
+        let reader = ArchiveReader::open_exclusive(&path).unwrap();
+        vacuum(reader, &[false, false, false, true, true]).unwrap();
+        let names: Vec<_> = contents(&path).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashSet, fs, process::exit, time::UNIX_EPOCH};
+
+use time::OffsetDateTime;
+
+use crate::{
+    pattern::glob_match,
+    utils::{iso_date, option_value},
+};
+
+// what identifies the day, week or month an archive was made in
+type Period = fn(OffsetDateTime) -> (i32, u32);
+
+/// `kzip rotate --dir /backups --keep-daily 7 --keep-weekly 4 [--keep-monthly 12]`
+///
+/// Removes old archives from a directory of backups. For every kind of
+/// period the newest archive of the latest N periods that have one is kept,
+/// i.e. `--keep-daily 7` keeps the last archive of each of the last 7 days
+/// with a backup. Archives are dated by when they were last modified, in UTC,
+/// and `--pattern` picks which files are backups, `*.kzip` by default.
+pub fn run(args: &[String]) {
+    let Some(dir) = option_value(args, &["--dir"]) else {
+        println!("kzip: rotate needs a directory, i.e. kzip rotate --dir /backups --keep-daily 7");
+        exit(1);
+    };
+    let pattern = option_value(args, &["--pattern"]).unwrap_or("*.kzip".to_string());
+    let is_dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    let keep = |option: &str| match option_value(args, &[option]).map(|keep| keep.parse()) {
+        None => 0,
+        Some(Ok(keep)) => keep,
+        Some(Err(_)) => {
+            println!("kzip: {option} has to be a number");
+            exit(1);
+        }
+    };
+    let periods: [(usize, Period); 3] = [
+        (keep("--keep-daily"), |date| {
+            (date.year(), date.ordinal() as u32)
+        }),
+        (keep("--keep-weekly"), |date| {
+            let (year, week, _) = date.to_iso_week_date();
+            (year, week as u32)
+        }),
+        (keep("--keep-monthly"), |date| {
+            (date.year(), date.month() as u32)
+        }),
+    ];
+    if periods.iter().all(|(keep, _)| *keep == 0) {
+        // nothing would be kept
+        println!("kzip: rotate needs --keep-daily, --keep-weekly or --keep-monthly");
+        exit(1);
+    }
+
+    let dir_result = match fs::read_dir(&dir) {
+        Ok(dir_result) => dir_result,
+        Err(err) => {
+            println!("kzip: could not read directory {dir}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    let mut archives = Vec::new();
+    for entry in dir_result.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !glob_match(&pattern, &name) {
+            continue;
+        }
+        let modified = entry
+            .metadata()
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        if let Some(modified) = modified {
+            archives.push((modified.as_secs(), entry.path()));
+        }
+    }
+    // newest first, so the first archive of a period is the one kept
+    archives.sort_by(|a, b| b.cmp(a));
+
+    let mut kept = vec![false; archives.len()];
+    for (keep, period) in periods {
+        let mut seen = HashSet::new();
+        for (index, (modified, _)) in archives.iter().enumerate() {
+            let date = OffsetDateTime::from_unix_timestamp(*modified as i64)
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+            if seen.len() < keep && seen.insert(period(date)) {
+                kept[index] = true;
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for ((modified, path), kept) in archives.iter().zip(kept) {
+        if kept {
+            if is_verbose {
+                println!("kzip: keeping {} ({})", path.display(), iso_date(*modified));
+            }
+            continue;
+        }
+
+        if is_dry_run {
+            println!(
+                "kzip: would remove {} ({})",
+                path.display(),
+                iso_date(*modified)
+            );
+        } else {
+            if is_verbose {
+                println!(
+                    "kzip: removing {} ({})",
+                    path.display(),
+                    iso_date(*modified)
+                );
+            }
+            if let Err(err) = fs::remove_file(path) {
+                println!("kzip: could not remove {}", path.display());
+                println!("{:#?}", err);
+                exit(1);
+            }
+        }
+        removed += 1;
+    }
+
+    let kept = archives.len() - removed;
+    if is_dry_run {
+        println!("kzip: Would remove {removed} archive(s) and keep {kept} in {dir}");
+    } else {
+        println!("kzip: Removed {removed} archive(s) and kept {kept} in {dir}");
+    }
+    exit(0);
+}
@@ -0,0 +1,319 @@
+//! `Serialize` and `Deserialize` for what the library hands out about an
+//! archive, with the `serde` feature. Entries keep every field under the
+//! same name, codecs are stored as the id and level entries store, so a
+//! custom codec has to be registered before entries using it can be read
+//! back.
+
+use std::{fmt, marker::PhantomData, time::Duration};
+
+use serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    archive::{Entry, Timings},
+    codec::Codec,
+    special::{Kind, Special},
+};
+
+const ENTRY_FIELDS: &[&str] = &[
+    "name",
+    "raw_name",
+    "created_at",
+    "modified",
+    "unpacked_length",
+    "length",
+    "offset",
+    "header_offset",
+    "duplicate_of",
+    "codec",
+    "special",
+    "encrypted",
+    "meta",
+];
+
+impl Serialize for Entry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("Entry", ENTRY_FIELDS.len())?;
+        entry.serialize_field("name", &self.name)?;
+        entry.serialize_field("raw_name", &self.raw_name)?;
+        entry.serialize_field("created_at", &self.created_at)?;
+        entry.serialize_field("modified", &self.modified)?;
+        entry.serialize_field("unpacked_length", &self.unpacked_length)?;
+        entry.serialize_field("length", &self.length)?;
+        entry.serialize_field("offset", &self.offset)?;
+        entry.serialize_field("header_offset", &self.header_offset)?;
+        entry.serialize_field("duplicate_of", &self.duplicate_of)?;
+        entry.serialize_field("codec", &self.codec)?;
+        entry.serialize_field("special", &self.special)?;
+        entry.serialize_field("encrypted", &self.encrypted)?;
+        entry.serialize_field("meta", &self.meta)?;
+        entry.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Entry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Entry, D::Error> {
+        deserializer.deserialize_struct("Entry", ENTRY_FIELDS, StructVisitor::<Entry>::new())
+    }
+}
+
+impl<'de> FromMap<'de> for Entry {
+    const NAME: &'static str = "an entry";
+    const FIELDS: &'static [&'static str] = ENTRY_FIELDS;
+
+    fn from_map<A: MapAccess<'de>>(mut map: A) -> Result<Entry, A::Error> {
+        let mut entry = Entry {
+            name: String::new(),
+            raw_name: None,
+            created_at: None,
+            modified: 0,
+            unpacked_length: 0,
+            length: 0,
+            offset: 0,
+            header_offset: 0,
+            duplicate_of: None,
+            codec: Codec::default(),
+            special: false,
+            encrypted: false,
+            meta: Vec::new(),
+        };
+        let mut has_name = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => {
+                    entry.name = map.next_value()?;
+                    has_name = true;
+                }
+                "raw_name" => entry.raw_name = map.next_value()?,
+                "created_at" => entry.created_at = map.next_value()?,
+                "modified" => entry.modified = map.next_value()?,
+                "unpacked_length" => entry.unpacked_length = map.next_value()?,
+                "length" => entry.length = map.next_value()?,
+                "offset" => entry.offset = map.next_value()?,
+                "header_offset" => entry.header_offset = map.next_value()?,
+                "duplicate_of" => entry.duplicate_of = map.next_value()?,
+                "codec" => entry.codec = map.next_value()?,
+                "special" => entry.special = map.next_value()?,
+                "encrypted" => entry.encrypted = map.next_value()?,
+                "meta" => entry.meta = map.next_value()?,
+                key => return Err(de::Error::unknown_field(key, ENTRY_FIELDS)),
+            }
+        }
+        if !has_name {
+            return Err(de::Error::missing_field("name"));
+        }
+        Ok(entry)
+    }
+}
+
+const CODEC_FIELDS: &[&str] = &["id", "level"];
+
+impl Serialize for Codec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (id, level) = self.id();
+        let mut codec = serializer.serialize_struct("Codec", 2)?;
+        codec.serialize_field("id", &id)?;
+        codec.serialize_field("level", &level)?;
+        codec.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Codec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Codec, D::Error> {
+        deserializer.deserialize_struct("Codec", CODEC_FIELDS, StructVisitor::<Codec>::new())
+    }
+}
+
+impl<'de> FromMap<'de> for Codec {
+    const NAME: &'static str = "a codec";
+    const FIELDS: &'static [&'static str] = CODEC_FIELDS;
+
+    fn from_map<A: MapAccess<'de>>(mut map: A) -> Result<Codec, A::Error> {
+        let mut id = None;
+        let mut level = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "id" => id = Some(map.next_value()?),
+                "level" => level = map.next_value()?,
+                key => return Err(de::Error::unknown_field(key, CODEC_FIELDS)),
+            }
+        }
+        let id = id.ok_or_else(|| de::Error::missing_field("id"))?;
+        Codec::from_id(id, level)
+            .ok_or_else(|| de::Error::custom(format!("codec {id} isn't built in or registered")))
+    }
+}
+
+const SPECIAL_FIELDS: &[&str] = &["kind", "mode", "device"];
+
+impl Serialize for Special {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut special = serializer.serialize_struct("Special", 3)?;
+        special.serialize_field("kind", &self.kind)?;
+        special.serialize_field("mode", &self.mode)?;
+        special.serialize_field("device", &self.device)?;
+        special.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Special {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Special, D::Error> {
+        deserializer.deserialize_struct("Special", SPECIAL_FIELDS, StructVisitor::<Special>::new())
+    }
+}
+
+impl<'de> FromMap<'de> for Special {
+    const NAME: &'static str = "a special file";
+    const FIELDS: &'static [&'static str] = SPECIAL_FIELDS;
+
+    fn from_map<A: MapAccess<'de>>(mut map: A) -> Result<Special, A::Error> {
+        let mut kind = None;
+        let mut mode = 0;
+        let mut device = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "kind" => kind = Some(map.next_value()?),
+                "mode" => mode = map.next_value()?,
+                "device" => device = map.next_value()?,
+                key => return Err(de::Error::unknown_field(key, SPECIAL_FIELDS)),
+            }
+        }
+        Ok(Special {
+            kind: kind.ok_or_else(|| de::Error::missing_field("kind"))?,
+            mode,
+            device,
+        })
+    }
+}
+
+// kinds are stored by the names kzip shows them with
+impl Serialize for Kind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Kind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Kind, D::Error> {
+        const KINDS: &[&str] = &["fifo", "socket", "character device", "block device"];
+
+        let name = String::deserialize(deserializer)?;
+        [
+            Kind::Fifo,
+            Kind::Socket,
+            Kind::CharDevice,
+            Kind::BlockDevice,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+        .ok_or_else(|| de::Error::unknown_variant(&name, KINDS))
+    }
+}
+
+const TIMINGS_FIELDS: &[&str] = &["read", "hash", "compress", "write"];
+
+impl Serialize for Timings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut timings = serializer.serialize_struct("Timings", TIMINGS_FIELDS.len())?;
+        timings.serialize_field("read", &self.read)?;
+        timings.serialize_field("hash", &self.hash)?;
+        timings.serialize_field("compress", &self.compress)?;
+        timings.serialize_field("write", &self.write)?;
+        timings.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Timings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Timings, D::Error> {
+        deserializer.deserialize_struct("Timings", TIMINGS_FIELDS, StructVisitor::<Timings>::new())
+    }
+}
+
+impl<'de> FromMap<'de> for Timings {
+    const NAME: &'static str = "timings";
+    const FIELDS: &'static [&'static str] = TIMINGS_FIELDS;
+
+    fn from_map<A: MapAccess<'de>>(mut map: A) -> Result<Timings, A::Error> {
+        let mut timings = Timings::default();
+        while let Some(key) = map.next_key::<String>()? {
+            let time: Duration = map.next_value()?;
+            match key.as_str() {
+                "read" => timings.read = time,
+                "hash" => timings.hash = time,
+                "compress" => timings.compress = time,
+                "write" => timings.write = time,
+                key => return Err(de::Error::unknown_field(key, TIMINGS_FIELDS)),
+            }
+        }
+        Ok(timings)
+    }
+}
+
+// the structs are read as maps by name, leaving out fields that have a
+// default, so listings made by older versions can still be read. Formats
+// without names, like bincode, give the fields in order instead.
+trait FromMap<'de>: Sized {
+    const NAME: &'static str;
+    const FIELDS: &'static [&'static str];
+
+    fn from_map<A: MapAccess<'de>>(map: A) -> Result<Self, A::Error>;
+}
+
+struct StructVisitor<T>(PhantomData<T>);
+
+impl<T> StructVisitor<T> {
+    fn new() -> StructVisitor<T> {
+        StructVisitor(PhantomData)
+    }
+}
+
+impl<'de, T: FromMap<'de>> Visitor<'de> for StructVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(T::NAME)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+        T::from_map(map)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<T, A::Error> {
+        T::from_map(SeqAsMap {
+            seq,
+            fields: T::FIELDS,
+            index: 0,
+        })
+    }
+}
+
+// gives each element of a sequence the name of the field at its position
+struct SeqAsMap<A> {
+    seq: A,
+    fields: &'static [&'static str],
+    index: usize,
+}
+
+impl<'de, A: SeqAccess<'de>> MapAccess<'de> for SeqAsMap<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.fields.get(self.index) {
+            Some(field) => seed.deserialize((*field).into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.index += 1;
+        self.seq
+            .next_element_seed(seed)?
+            .ok_or_else(|| de::Error::invalid_length(self.index - 1, &"every field"))
+    }
+}
@@ -0,0 +1,761 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path,
+    process::exit,
+    thread,
+    time::Duration,
+};
+
+use time::OffsetDateTime;
+
+use crate::{
+    archive::{ArchiveReader, Entry},
+    utils::{iso_date, option_value, positionals},
+};
+
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+    depth: Option<String>,
+}
+
+enum Resource {
+    File(Entry),
+    Dir(String),
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    /// `length` bytes of the content of an entry after the first `skip`,
+    /// decompressed while it is sent.
+    Entry {
+        entry: Entry,
+        skip: u64,
+        length: u64,
+    },
+}
+
+impl Body {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::Entry { length, .. } => *length,
+        }
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    fn new(status: u16, reason: &'static str, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            reason,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: Body::Bytes(body),
+        }
+    }
+
+    fn error(status: u16, reason: &'static str) -> Response {
+        Response::new(
+            status,
+            reason,
+            "text/plain; charset=utf-8",
+            format!("{status} {reason}\n").into_bytes(),
+        )
+    }
+}
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+// how many connections are answered at once, every one on a thread of its
+// own with its own handle to the archive
+const WORKERS: usize = 8;
+// so a client that stops sending or reading doesn't keep a worker forever
+const TIMEOUT: Duration = Duration::from_secs(30);
+// the longest request line or header, the most headers and the biggest
+// body read, anything bigger is refused before it is read
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// `kzip serve archive.kzip [--port 8080] [--host 127.0.0.1]`
+///
+/// Besides plain HTTP this answers the read-only part of WebDAV, so the
+/// archive can be mounted as a network drive by file managers.
+pub fn run(args: &[String]) {
+    let input = match positionals(&args[2..], &["--port", "--host"]).first() {
+        Some(input) => input.to_string(),
+        None => {
+            println!("kzip: serve needs an archive, i.e. kzip serve archive.kzip --port 8080");
+            exit(1);
+        }
+    };
+    let port: u16 = match option_value(args, &["--port"]) {
+        Some(port) => port.parse().unwrap_or_else(|_| {
+            println!("kzip: {port} is not a valid port");
+            exit(1);
+        }),
+        None => 8080,
+    };
+    let host = option_value(args, &["--host"]).unwrap_or("127.0.0.1".to_string());
+
+    let reader = match ArchiveReader::open(&input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let listener = match TcpListener::bind((host.as_str(), port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("kzip: could not listen on {host}:{port}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    println!("kzip: serving {input} on http://{host}:{port}");
+
+    // every worker waits for connections on the same socket
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let worker = listener
+                .try_clone()
+                .and_then(|listener| Ok((listener, reader.try_clone()?)));
+            match worker {
+                Ok((listener, reader)) => thread::spawn(move || serve(listener, reader)),
+                Err(err) => {
+                    println!("kzip: could not start answering requests");
+                    println!("{:#?}", err);
+                    exit(1);
+                }
+            }
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn serve(listener: TcpListener, mut reader: ArchiveReader) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            handle_connection(&mut reader, stream)
+        });
+        if let Err(err) = result {
+            println!("kzip: there was an error answering a request");
+            println!("{:#?}", err);
+        }
+    }
+}
+
+fn handle_connection(reader: &mut ArchiveReader, mut stream: TcpStream) -> io::Result<()> {
+    let request = match read_request(&stream)? {
+        Some(Ok(request)) => request,
+        Some(Err(response)) => return write_response(reader, &mut stream, response, false),
+        None => return Ok(()),
+    };
+
+    let response = match request.method.as_str() {
+        "GET" | "HEAD" => respond(reader, &request)?,
+        "OPTIONS" => {
+            let mut response = Response::new(200, "OK", "text/plain", Vec::new());
+            response.headers.push(("DAV", "1".to_string()));
+            response.headers.push(("MS-Author-Via", "DAV".to_string()));
+            response.headers.push(("Allow", ALLOW.to_string()));
+            response
+        }
+        "PROPFIND" => propfind(reader, &request),
+        _ => {
+            // the archive is read-only, so anything that would change it is refused
+            let mut response = Response::error(405, "Method Not Allowed");
+            response.headers.push(("Allow", ALLOW.to_string()));
+            response
+        }
+    };
+
+    write_response(reader, &mut stream, response, request.method == "HEAD")
+}
+
+fn write_response(
+    reader: &mut ArchiveReader,
+    stream: &mut impl Write,
+    response: Response,
+    is_head: bool,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n",
+        response.status, response.reason
+    )?;
+    for (name, value) in &response.headers {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    write!(stream, "Content-Length: {}\r\n", response.body.len())?;
+    write!(stream, "Connection: close\r\n\r\n")?;
+    if is_head {
+        return stream.flush();
+    }
+
+    match response.body {
+        Body::Bytes(bytes) => stream.write_all(&bytes)?,
+        Body::Entry {
+            entry,
+            skip,
+            length,
+        } => {
+            // what comes before a range still has to be decompressed
+            let mut content = reader.stream(&entry)?;
+            io::copy(&mut (&mut content).take(skip), &mut io::sink())?;
+            io::copy(&mut content.take(length), stream)?;
+        }
+    }
+    stream.flush()
+}
+
+/// Reads the request line and headers, None if the connection was closed
+/// before anything was sent, or the response refusing a request that is too
+/// big to be read.
+fn read_request(stream: impl Read) -> io::Result<Option<Result<Request, Response>>> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    match read_line(&mut reader, &mut request_line)? {
+        Line::Read => {}
+        Line::Closed => return Ok(None),
+        Line::TooLong => return Ok(Some(Err(Response::error(414, "URI Too Long")))),
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or("/");
+    let target = target.split(['?', '#']).next().unwrap_or_default();
+
+    let mut range = None;
+    let mut depth = None;
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let mut line = String::new();
+        match read_line(&mut reader, &mut line)? {
+            Line::Read => {}
+            Line::Closed => break,
+            Line::TooLong => {
+                return Ok(Some(Err(Response::error(
+                    431,
+                    "Request Header Fields Too Large",
+                ))))
+            }
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Some(Err(Response::error(
+                431,
+                "Request Header Fields Too Large",
+            ))));
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim().to_string();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "range" => range = Some(value),
+                "depth" => depth = Some(value),
+                "content-length" => content_length = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Ok(Some(Err(Response::error(413, "Content Too Large"))));
+    }
+    // PROPFIND sends a body we don't need, but closing the connection with
+    // unread data would reset it before the client gets the response
+    io::copy(&mut reader.take(content_length), &mut io::sink())?;
+
+    Ok(Some(Ok(Request {
+        method,
+        path: percent_decode(target),
+        range,
+        depth,
+    })))
+}
+
+enum Line {
+    Read,
+    Closed,
+    TooLong,
+}
+
+/// Reads a line of at most `MAX_LINE` bytes.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<Line> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    Ok(if read == 0 {
+        Line::Closed
+    } else if !line.ends_with('\n') && read as u64 == MAX_LINE {
+        Line::TooLong
+    } else {
+        Line::Read
+    })
+}
+
+/// Finds the file or directory an url path points to.
+fn resolve(reader: &ArchiveReader, request_path: &str) -> Option<Resource> {
+    let name = request_path
+        .trim_start_matches('/')
+        .replace('/', path::MAIN_SEPARATOR_STR);
+
+    if let Some(entry) = reader.get(&name) {
+        return Some(Resource::File(entry.clone()));
+    }
+
+    let prefix = if name.is_empty() || name.ends_with(path::MAIN_SEPARATOR) {
+        name
+    } else {
+        format!("{name}{}", path::MAIN_SEPARATOR)
+    };
+
+    if prefix.is_empty()
+        || reader
+            .entries
+            .iter()
+            .any(|entry| entry.name.starts_with(&prefix))
+    {
+        Some(Resource::Dir(prefix))
+    } else {
+        None
+    }
+}
+
+fn respond(reader: &mut ArchiveReader, request: &Request) -> io::Result<Response> {
+    let prefix = match resolve(reader, &request.path) {
+        Some(Resource::File(entry)) => {
+            return Ok(file_response(entry, request.range.as_deref()));
+        }
+        Some(Resource::Dir(prefix)) => prefix,
+        None => return Ok(Response::error(404, "Not Found")),
+    };
+
+    if !request.path.ends_with('/') {
+        // make relative links inside of the directory work
+        let mut response = Response::error(301, "Moved Permanently");
+        response
+            .headers
+            .push(("Location", percent_encode(&format!("{}/", request.path))));
+        return Ok(response);
+    }
+
+    if let Some(entry) = reader.get(&format!("{prefix}index.html")).cloned() {
+        return Ok(file_response(entry, request.range.as_deref()));
+    }
+
+    Ok(listing_response(reader, &request.path, &prefix))
+}
+
+fn file_response(entry: Entry, range: Option<&str>) -> Response {
+    let content_type = content_type(&entry.name);
+    let total = entry.unpacked_length;
+    let modified = entry.modified;
+    let body = |entry, skip, length| Body::Entry {
+        entry,
+        skip,
+        length,
+    };
+
+    let mut response = match range {
+        Some(range) => match parse_range(range, total) {
+            Some((start, end)) => {
+                let mut response = Response::new(206, "Partial Content", content_type, Vec::new());
+                response.body = body(entry, start, end - start + 1);
+                response
+                    .headers
+                    .push(("Content-Range", format!("bytes {start}-{end}/{total}")));
+                response
+            }
+            None => {
+                let mut response = Response::error(416, "Range Not Satisfiable");
+                response
+                    .headers
+                    .push(("Content-Range", format!("bytes */{total}")));
+                response
+            }
+        },
+        None => {
+            let mut response = Response::new(200, "OK", content_type, Vec::new());
+            response.body = body(entry, 0, total);
+            response
+        }
+    };
+
+    response
+        .headers
+        .push(("Accept-Ranges", "bytes".to_string()));
+    response
+        .headers
+        .push(("Last-Modified", http_date(modified)));
+    response
+}
+
+/// Answers a WebDAV PROPFIND with the properties of a file, or of a
+/// directory and (with `Depth: 1`) its children.
+fn propfind(reader: &ArchiveReader, request: &Request) -> Response {
+    let resource = match resolve(reader, &request.path) {
+        Some(resource) => resource,
+        None => return Response::error(404, "Not Found"),
+    };
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    match resource {
+        Resource::File(entry) => xml.push_str(&file_properties(&entry)),
+        Resource::Dir(prefix) => {
+            xml.push_str(&dir_properties(&prefix));
+            if request.depth.as_deref() != Some("0") {
+                for (child, is_dir) in children(reader, &prefix) {
+                    if is_dir {
+                        xml.push_str(&dir_properties(&format!(
+                            "{prefix}{child}{}",
+                            path::MAIN_SEPARATOR
+                        )));
+                    } else if let Some(entry) = reader.get(&format!("{prefix}{child}")) {
+                        xml.push_str(&file_properties(entry));
+                    }
+                }
+            }
+        }
+    }
+    xml.push_str("</D:multistatus>\n");
+
+    Response::new(
+        207,
+        "Multi-Status",
+        "application/xml; charset=utf-8",
+        xml.into_bytes(),
+    )
+}
+
+fn file_properties(entry: &Entry) -> String {
+    let href = percent_encode(&format!(
+        "/{}",
+        entry.name.replace(path::MAIN_SEPARATOR, "/")
+    ));
+    let display_name = entry
+        .name
+        .rsplit(path::MAIN_SEPARATOR)
+        .next()
+        .unwrap_or_default();
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+<D:displayname>{}</D:displayname><D:resourcetype/>\
+<D:getcontentlength>{}</D:getcontentlength>\
+<D:getcontenttype>{}</D:getcontenttype>\
+<D:getlastmodified>{}</D:getlastmodified>\
+<D:creationdate>{}</D:creationdate>\
+</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        html_escape(&href),
+        html_escape(display_name),
+        entry.unpacked_length,
+        content_type(&entry.name),
+        http_date(entry.modified),
+        iso_date(entry.created_at.unwrap_or(entry.modified))
+    )
+}
+
+fn dir_properties(prefix: &str) -> String {
+    let href = percent_encode(&format!("/{}", prefix.replace(path::MAIN_SEPARATOR, "/")));
+    let display_name = prefix
+        .trim_end_matches(path::MAIN_SEPARATOR)
+        .rsplit(path::MAIN_SEPARATOR)
+        .next()
+        .unwrap_or_default();
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+<D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>\
+</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        html_escape(&href),
+        html_escape(display_name)
+    )
+}
+
+/// The names directly inside of a directory, and whether they are directories.
+fn children(reader: &ArchiveReader, prefix: &str) -> BTreeSet<(String, bool)> {
+    let mut children = BTreeSet::new();
+    for entry in &reader.entries {
+        if let Some(rest) = entry.name.strip_prefix(prefix) {
+            match rest.split_once(path::MAIN_SEPARATOR) {
+                Some((dir, _)) => children.insert((dir.to_string(), true)),
+                None => children.insert((rest.to_string(), false)),
+            };
+        }
+    }
+
+    children
+}
+
+fn listing_response(reader: &ArchiveReader, request_path: &str, prefix: &str) -> Response {
+    let title = html_escape(request_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n"
+    );
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (child, is_dir) in children(reader, prefix) {
+        let child = if is_dir { format!("{child}/") } else { child };
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            percent_encode(&child),
+            html_escape(&child)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    Response::new(200, "OK", "text/html; charset=utf-8", html.into_bytes())
+}
+
+/// Parses a single `bytes=start-end` range into inclusive offsets.
+fn parse_range(range: &str, total: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    if total == 0 || start.contains(',') || end.contains(',') {
+        return None;
+    }
+
+    let (start, end) = if start.is_empty() {
+        // suffix range, the last n bytes
+        let suffix: u64 = end.trim().parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        (total.saturating_sub(suffix), total - 1)
+    } else {
+        let start: u64 = start.trim().parse().ok()?;
+        let end: u64 = if end.trim().is_empty() {
+            total - 1
+        } else {
+            end.trim().parse::<u64>().ok()?.min(total - 1)
+        };
+        (start, end)
+    };
+
+    if start > end || start >= total {
+        return None;
+    }
+
+    Some((start, end))
+}
+
+fn content_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" | "log" | "rs" | "toml" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+fn html_escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats a unix timestamp like `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(timestamp: u64) -> String {
+    let date =
+        OffsetDateTime::from_unix_timestamp(timestamp as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let weekday = date.weekday().to_string();
+    let month = date.month().to_string();
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        &weekday[..3],
+        date.day(),
+        &month[..3],
+        date.year(),
+        date.hour(),
+        date.minute(),
+        date.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::{ArchiveWriter, Header},
+        codec::Codec,
+    };
+    use std::{env, ffi::OsStr, fs};
+
+    fn parse(request: &str) -> Result<Request, u16> {
+        match read_request(request.as_bytes()).unwrap() {
+            Some(Ok(request)) => Ok(request),
+            Some(Err(response)) => Err(response.status),
+            None => Err(0),
+        }
+    }
+
+    #[test]
+    fn reads_requests() {
+        let request = parse(
+            "GET /docs/a%20b.txt?x=1 HTTP/1.1\r\nHost: x\r\nRange: bytes=2-\r\nDepth: 1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/docs/a b.txt");
+        assert_eq!(request.range.as_deref(), Some("bytes=2-"));
+        assert_eq!(request.depth.as_deref(), Some("1"));
+
+        let request = parse("PROPFIND / HTTP/1.1\r\nContent-Length: 5\r\n\r\n<xml>").unwrap();
+        assert_eq!(request.method, "PROPFIND");
+        assert_eq!(parse("").err(), Some(0));
+    }
+
+    #[test]
+    fn refuses_requests_that_are_too_big() {
+        let long = "a".repeat(MAX_LINE as usize);
+        assert_eq!(
+            parse(&format!("GET /{long} HTTP/1.1\r\n\r\n")).err(),
+            Some(414)
+        );
+        assert_eq!(
+            parse(&format!("GET / HTTP/1.1\r\nX: {long}\r\n\r\n")).err(),
+            Some(431)
+        );
+        let many = "X: y\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(
+            parse(&format!("GET / HTTP/1.1\r\n{many}\r\n")).err(),
+            Some(431)
+        );
+        assert_eq!(
+            parse("PUT / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n").err(),
+            Some(413)
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(parse_range("bytes=8-100", 10), Some((8, 9)));
+        assert_eq!(parse_range("bytes=10-", 10), None);
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+
+    #[test]
+    fn streams_ranges_of_entries() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("serve.kzip").to_string_lossy().to_string();
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = ArchiveWriter::create(&path, 1).unwrap();
+        let header = Header {
+            name: OsStr::new("big.bin"),
+            created_at: None,
+            modified: 0,
+            meta: &[],
+        };
+        writer.add(&header, &content, Codec::default()).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let entry = reader.get("big.bin").unwrap().clone();
+        let mut sent = Vec::new();
+        let response = file_response(entry.clone(), Some("bytes=150000-150009"));
+        assert_eq!(response.status, 206);
+        write_response(&mut reader, &mut sent, response, false).unwrap();
+        let body = sent.split_at(sent.len() - 10).1;
+        assert_eq!(body, &content[150_000..150_010]);
+
+        // HEAD answers with the length without reading anything
+        let mut sent = Vec::new();
+        write_response(&mut reader, &mut sent, file_response(entry, None), true).unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.contains("Content-Length: 200000\r\n") && sent.ends_with("\r\n\r\n"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
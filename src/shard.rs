@@ -0,0 +1,140 @@
+use std::{
+    env, fs,
+    path::Path,
+    process::{exit, Command},
+};
+
+use crate::{filter::Filter, join_name, utils::option_value, EXIT_PARTIAL};
+
+/// `kzip -i project -o out --shard-by-dir`
+///
+/// Makes one archive per directory in the input, out/src.kzip, out/docs.kzip
+/// and so on, with the files right in the input in out/project.kzip. Every
+/// shard is made by running kzip again, so the other options apply to each
+/// of them and their names are the same as in one big archive.
+pub fn run(args: &[String], input: &str, output: &str, filter: &Filter) {
+    let dir_result = match fs::read_dir(input) {
+        Ok(dir_result) => dir_result,
+        Err(err) => {
+            println!("kzip: could not read directory {input}");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+    let mut names: Vec<_> = dir_result
+        .flatten()
+        .map(|entry| entry.file_name())
+        .collect();
+    names.sort();
+
+    // the shards are one level further in, so they read one level less
+    let max_depth =
+        option_value(args, &["--max-depth"]).and_then(|depth| depth.parse::<usize>().ok());
+    let input_name = Path::new(input)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or("files".to_string());
+
+    let mut shards = Vec::new();
+    let mut has_files = false;
+    for name in names {
+        let path = join_name(Path::new(input), &name);
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                if filter.include_dir(Path::new(&path), &metadata, 1) {
+                    let shard = name.to_string_lossy().to_string();
+                    let depth = max_depth.map(|depth| depth - 1);
+                    shards.push((path, shard, depth));
+                }
+            }
+            Ok(_) => has_files = true,
+            Err(_) => {}
+        }
+    }
+    if shards.iter().any(|(_, shard, _)| *shard == input_name) && has_files {
+        println!("kzip: {input} has a directory called {input_name}, its files and the directory would both go to {input_name}.kzip");
+        exit(1);
+    }
+    if has_files {
+        shards.push((input.into(), input_name, Some(1)));
+    }
+
+    if let Err(err) = fs::create_dir_all(output) {
+        println!("kzip: could not create directory {output}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+    let kzip = match env::current_exe() {
+        Ok(kzip) => kzip,
+        Err(err) => {
+            println!("kzip: could not find the kzip executable to make the shards with");
+            println!("{:#?}", err);
+            exit(1);
+        }
+    };
+
+    let mut failed = Vec::new();
+    let mut is_partial = false;
+    for (shard_input, shard, depth) in &shards {
+        let shard_output = Path::new(output).join(format!("{shard}.kzip"));
+        println!(
+            "kzip: sharding {} into {}",
+            shard_input.to_string_lossy(),
+            shard_output.display()
+        );
+
+        let mut command = Command::new(&kzip);
+        command
+            .args(pass_on(args))
+            .arg("-i")
+            .arg(shard_input)
+            .arg("-o")
+            .arg(&shard_output);
+        if let Some(depth) = depth {
+            command.arg("--max-depth").arg(depth.to_string());
+        }
+        match command.status() {
+            Ok(status) if status.success() => {}
+            Ok(status) if status.code() == Some(EXIT_PARTIAL) => is_partial = true,
+            _ => failed.push(shard),
+        }
+    }
+
+    if !failed.is_empty() {
+        println!(
+            "kzip: {} of {} shard(s) failed: {}",
+            failed.len(),
+            shards.len(),
+            failed
+                .iter()
+                .map(|shard| shard.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        exit(1);
+    }
+    println!("kzip: Made {} shard(s) in {output}", shards.len());
+    if is_partial {
+        exit(EXIT_PARTIAL);
+    }
+    exit(0);
+}
+
+/// The options every shard is made with, without the ones that are set
+/// for each shard.
+fn pass_on(args: &[String]) -> Vec<&String> {
+    let mut passed = Vec::new();
+    let mut skip_next = false;
+    for arg in &args[1..] {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        match arg.to_ascii_lowercase().as_str() {
+            "-i" | "--input" | "-o" | "--output" | "--max-depth" => skip_next = true,
+            "--shard-by-dir" => {}
+            _ => passed.push(arg),
+        }
+    }
+    passed
+}
@@ -0,0 +1,504 @@
+//! The index kzip can keep next to an archive as `archive.kzip.idx`, with
+//! the header of every entry and the SHA-256 of its content, so listing an
+//! archive with many files or picking a few out of it doesn't have to go
+//! through all of their headers. `ArchiveReader::open_indexed` uses it.
+//!
+//! An index only counts for the archive it was written for: its length,
+//! when it was last modified and a checksum of its first and last 64 KB
+//! have to match, otherwise the archive is read as if there was none.
+//! Hashing the whole archive would take longer than reading its headers.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom},
+    time::UNIX_EPOCH,
+};
+
+use sha256::digest;
+
+use crate::{
+    archive::{self, ArchiveReader, Entry},
+    codec::Codec,
+    vfs,
+};
+
+const MAGIC: &[u8; 4] = b"KZI1";
+// how much of the start and end of the archive is checksummed
+const SAMPLE: u64 = 64 * 1024;
+// the smallest an entry can take up in an index, for checking counts
+const MIN_ENTRY_LENGTH: u64 = 4 + 1 + 8 * 5 + 2 + 4;
+
+// entry flags
+const RAW_NAME: u8 = 1;
+const CREATED_AT: u8 = 2;
+const DUPLICATE: u8 = 4;
+const SPECIAL: u8 = 8;
+const ENCRYPTED: u8 = 16;
+const HASH: u8 = 32;
+
+/// Where the index of `archive` is kept.
+pub fn path(archive: &str) -> String {
+    format!("{archive}.idx")
+}
+
+/// Writes the index of the archive `reader` has open, which is at
+/// `archive`. Every entry is read to hash it, except encrypted ones, whose
+/// hash is only known with the password.
+pub fn write(reader: &mut ArchiveReader, archive: &str) -> io::Result<()> {
+    let fingerprint = Fingerprint::of(&File::open(archive)?)?;
+
+    let mut hashes: Vec<String> = Vec::with_capacity(reader.entries.len());
+    for entry in reader.entries.clone() {
+        let hash = match entry.duplicate_of {
+            Some(original) => hashes[original].clone(),
+            None if entry.encrypted => String::new(),
+            None => digest(reader.read(&entry)?),
+        };
+        hashes.push(hash);
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(fingerprint.length.to_be_bytes());
+    bytes.extend(fingerprint.modified.to_be_bytes());
+    write_string(&mut bytes, &fingerprint.checksum);
+    write_string(&mut bytes, &reader.version);
+    write_pairs(&mut bytes, &reader.meta);
+    bytes.extend((reader.warnings.len() as u32).to_be_bytes());
+    for warning in &reader.warnings {
+        write_string(&mut bytes, warning);
+    }
+
+    bytes.extend((reader.entries.len() as u32).to_be_bytes());
+    for (entry, hash) in reader.entries.iter().zip(&hashes) {
+        write_string(&mut bytes, &entry.name);
+        let mut flags = 0;
+        if entry.raw_name.is_some() {
+            flags |= RAW_NAME;
+        }
+        if entry.created_at.is_some() {
+            flags |= CREATED_AT;
+        }
+        if entry.duplicate_of.is_some() {
+            flags |= DUPLICATE;
+        }
+        if entry.special {
+            flags |= SPECIAL;
+        }
+        if entry.encrypted {
+            flags |= ENCRYPTED;
+        }
+        if !hash.is_empty() {
+            flags |= HASH;
+        }
+        bytes.push(flags);
+        if let Some(raw_name) = &entry.raw_name {
+            bytes.extend((raw_name.len() as u32).to_be_bytes());
+            bytes.extend(raw_name);
+        }
+        if let Some(created_at) = entry.created_at {
+            bytes.extend(created_at.to_be_bytes());
+        }
+        bytes.extend(entry.modified.to_be_bytes());
+        bytes.extend(entry.unpacked_length.to_be_bytes());
+        bytes.extend(entry.length.to_be_bytes());
+        bytes.extend(entry.offset.to_be_bytes());
+        bytes.extend(entry.header_offset.to_be_bytes());
+        if let Some(original) = entry.duplicate_of {
+            bytes.extend((original as u32).to_be_bytes());
+        }
+        let (id, level) = entry.codec.id();
+        bytes.extend([id, level]);
+        write_pairs(&mut bytes, &entry.meta);
+        // as the 32 bytes it is rather than 64 hex digits
+        for pair in hash.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap_or_default();
+            bytes.push(u8::from_str_radix(pair, 16).unwrap_or_default());
+        }
+    }
+
+    // written next to it first, so a reader never sees half an index
+    let path = path(archive);
+    let part = format!("{path}.part");
+    fs::write(&part, bytes)?;
+    fs::rename(&part, &path)
+}
+
+/// What an index holds besides the archive's own headers.
+pub(crate) struct Index {
+    pub entries: Vec<Entry>,
+    pub version: String,
+    pub meta: Vec<(String, String)>,
+    pub warnings: Vec<String>,
+    /// The SHA-256 of every entry, None for encrypted ones.
+    pub hashes: Vec<Option<[u8; 32]>>,
+}
+
+/// The index of `archive`, which `file` has open, if there is one and it
+/// was written for the archive as it is now. The entries are checked
+/// against the archive like its own headers are, so a crafted index can't
+/// make kzip read outside of it either.
+pub(crate) fn read(archive: &str, file: &File, strict: bool) -> io::Result<Option<Index>> {
+    let index = match File::open(path(archive)) {
+        Ok(index) => index,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let index_length = index.metadata()?.len();
+    let mut index = BufReader::new(index);
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+    let mut magic = [0; 4];
+    index.read_exact(&mut magic)?;
+    if magic != *MAGIC {
+        return Err(invalid("not a kzip index"));
+    }
+    let fingerprint = Fingerprint {
+        length: archive::read_u64(&mut index)?,
+        modified: archive::read_u64(&mut index)?,
+        checksum: archive::read_string(&mut index)?,
+    };
+    if fingerprint != Fingerprint::of(file)? {
+        return Ok(None);
+    }
+    let archive_length = fingerprint.length;
+
+    let version = archive::read_string(&mut index)?;
+    let meta = read_pairs(&mut index)?;
+    let mut warnings = Vec::new();
+    for _ in 0..archive::read_u32(&mut index)? {
+        warnings.push(archive::read_string(&mut index)?);
+    }
+    // unknown flags are errors when reading strictly, reading the archive
+    // itself says which
+    if strict && !warnings.is_empty() {
+        return Ok(None);
+    }
+
+    let nof = archive::read_u32(&mut index)?;
+    if nof as u64 * MIN_ENTRY_LENGTH > index_length {
+        return Err(invalid("the index has more entries than fit in it"));
+    }
+    if strict && nof > archive::STRICT_ENTRIES {
+        return Ok(None);
+    }
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut hashes = Vec::new();
+    for _ in 0..nof {
+        let name = archive::read_string(&mut index)?;
+        let flags = archive::read_u8(&mut index)?;
+        let raw_name = if flags & RAW_NAME != 0 {
+            Some(archive::read_name(&mut index)?)
+        } else {
+            None
+        };
+        let name_length = raw_name.as_ref().map_or(name.len(), Vec::len);
+        if strict && (name_length > archive::STRICT_NAME_LENGTH || !vfs::is_inside(&name)) {
+            return Ok(None);
+        }
+        let created_at = if flags & CREATED_AT != 0 {
+            Some(archive::read_u64(&mut index)?)
+        } else {
+            None
+        };
+        let modified = archive::read_u64(&mut index)?;
+        let unpacked_length = archive::read_u64(&mut index)?;
+        let length = archive::read_u64(&mut index)?;
+        let offset = archive::read_u64(&mut index)?;
+        let header_offset = archive::read_u64(&mut index)?;
+        let duplicate_of = if flags & DUPLICATE != 0 {
+            Some(archive::read_u32(&mut index)? as usize)
+        } else {
+            None
+        };
+        let id = archive::read_u8(&mut index)?;
+        let level = archive::read_u8(&mut index)?;
+        let codec = Codec::from_id(id, level)
+            .ok_or_else(|| invalid("the index has a codec kzip doesn't know"))?;
+        let meta = read_pairs(&mut index)?;
+        if strict && meta.len() > archive::STRICT_META_PAIRS as usize {
+            return Ok(None);
+        }
+        let mut hash = None;
+        if flags & HASH != 0 {
+            let mut bytes = [0; 32];
+            index.read_exact(&mut bytes)?;
+            hash = Some(bytes);
+        }
+        hashes.push(hash);
+
+        if offset > archive_length
+            || length > archive_length - offset
+            || header_offset >= archive_length
+            || !codec.can_unpack(length, unpacked_length)
+        {
+            return Err(invalid("the index points outside of the archive"));
+        }
+        if flags & ENCRYPTED != 0 && length < archive::ENCRYPTION_OVERHEAD {
+            return Err(invalid("the index has an entry too short to be encrypted"));
+        }
+        if let Some(original) = duplicate_of {
+            let points_at_original = entries.get(original).is_some_and(|original| {
+                !original.is_duplicate() && original.offset == offset && original.length == length
+            });
+            if !points_at_original {
+                return Err(invalid("the index has a duplicate of nothing"));
+            }
+        }
+
+        entries.push(Entry {
+            name,
+            raw_name,
+            created_at,
+            modified,
+            unpacked_length,
+            length,
+            offset,
+            header_offset,
+            duplicate_of,
+            codec,
+            special: flags & SPECIAL != 0,
+            encrypted: flags & ENCRYPTED != 0,
+            meta,
+        });
+    }
+
+    Ok(Some(Index {
+        entries,
+        version,
+        meta,
+        warnings,
+        hashes,
+    }))
+}
+
+/// What an index is checked against to know it belongs to the archive.
+#[derive(PartialEq)]
+struct Fingerprint {
+    length: u64,
+    // nanoseconds since the unix epoch
+    modified: u64,
+    // SHA-256 of the first and last 64 KB
+    checksum: String,
+}
+
+impl Fingerprint {
+    fn of(mut file: &File) -> io::Result<Fingerprint> {
+        let metadata = file.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+
+        let length = metadata.len();
+        let mut sample = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.take(SAMPLE).read_to_end(&mut sample)?;
+        if length > SAMPLE {
+            file.seek(SeekFrom::Start(length.saturating_sub(SAMPLE).max(SAMPLE)))?;
+            file.take(SAMPLE).read_to_end(&mut sample)?;
+        }
+
+        Ok(Fingerprint {
+            length,
+            modified,
+            checksum: digest(sample),
+        })
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend((string.len() as u32).to_be_bytes());
+    bytes.extend(string.as_bytes());
+}
+
+fn write_pairs(bytes: &mut Vec<u8>, pairs: &[(String, String)]) {
+    bytes.extend((pairs.len() as u32).to_be_bytes());
+    for (key, value) in pairs {
+        write_string(bytes, key);
+        write_string(bytes, value);
+    }
+}
+
+fn read_pairs(reader: &mut impl Read) -> io::Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for _ in 0..archive::read_u32(reader)? {
+        pairs.push((archive::read_string(reader)?, archive::read_string(reader)?));
+    }
+    Ok(pairs)
+}
+
+#[cfg(all(test, feature = "writer"))]
+mod tests {
+    use super::*;
+    use crate::{archive::ArchiveWriter, archive::Header, options::CreateOptions};
+    use std::{env, ffi::OsStr};
+
+    fn create(path: &str, files: &[(&str, &[u8])]) {
+        let meta = [("note".to_string(), "indexed".to_string())];
+        let options = CreateOptions::new().meta(&meta);
+        let mut writer = ArchiveWriter::create_with(path, files.len() as u32, &options).unwrap();
+        for (name, content) in files {
+            let header = Header {
+                name: OsStr::new(name),
+                created_at: Some(1_600_000_000),
+                modified: 1_700_000_000,
+                meta: &meta,
+            };
+            writer.add(&header, content, Codec::default()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn reads_entries_from_a_fresh_index_only() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("indexed.kzip").to_string_lossy().to_string();
+        create(
+            &archive,
+            &[("a", b"first"), ("b", b"second"), ("c", b"first")],
+        );
+
+        // without an index the archive is read as usual
+        let reader = ArchiveReader::open_indexed(&archive, true).unwrap();
+        assert_eq!(reader.hash(&reader.entries[0]), None);
+        drop(reader);
+        let mut reader = ArchiveReader::open(&archive).unwrap();
+        let expected = reader.entries.clone();
+        write(&mut reader, &archive).unwrap();
+        drop(reader);
+
+        let mut reader = ArchiveReader::open_indexed(&archive, true).unwrap();
+        assert_eq!(reader.version, crate::VERSION);
+        assert_eq!(reader.meta, [("note".to_string(), "indexed".to_string())]);
+        assert_eq!(reader.entries.len(), expected.len());
+        for (entry, expected) in reader.entries.clone().iter().zip(&expected) {
+            assert_eq!(entry.name, expected.name);
+            assert_eq!(entry.created_at, expected.created_at);
+            assert_eq!(entry.modified, expected.modified);
+            assert_eq!(entry.meta, expected.meta);
+            assert_eq!(entry.codec, expected.codec);
+            assert_eq!(entry.duplicate_of, expected.duplicate_of);
+            assert_eq!(
+                (entry.header_offset, entry.offset, entry.length),
+                (expected.header_offset, expected.offset, expected.length)
+            );
+            let content = reader.read(entry).unwrap();
+            assert_eq!(reader.hash(entry), Some(digest(content)));
+        }
+        assert_eq!(
+            reader.hash(&reader.entries[0]),
+            reader.hash(&reader.entries[2])
+        );
+        drop(reader);
+
+        // once the archive changes, the index no longer counts for it
+        create(
+            &archive,
+            &[("a", b"FIRST"), ("b", b"SECOND"), ("d", b"first")],
+        );
+        let mut reader = ArchiveReader::open_indexed(&archive, true).unwrap();
+        let names: Vec<_> = reader
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b", "d"]);
+        assert_eq!(reader.hash(&reader.entries[0]), None);
+        let entry = reader.entries[0].clone();
+        assert_eq!(reader.read(&entry).unwrap(), b"FIRST");
+        drop(reader);
+
+        // and neither does one that can't be read
+        fs::write(path(&archive), b"KZI1").unwrap();
+        let reader = ArchiveReader::open_indexed(&archive, true).unwrap();
+        assert_eq!(reader.entries.len(), 3);
+
+        fs::remove_file(path(&archive)).unwrap();
+        fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_encrypted_entries_that_are_too_short() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir
+            .join("short-encrypted.kzip")
+            .to_string_lossy()
+            .to_string();
+        create(&archive, &[("only-entry", b"")]);
+        let mut reader = ArchiveReader::open(&archive).unwrap();
+        write(&mut reader, &archive).unwrap();
+        drop(reader);
+
+        // the flags come right after the name
+        let mut index = fs::read(path(&archive)).unwrap();
+        let name = b"\0\0\0\x0aonly-entry";
+        let flags = index
+            .windows(name.len())
+            .position(|window| window == name)
+            .unwrap()
+            + name.len();
+        index[flags] |= ENCRYPTED;
+        fs::write(path(&archive), index).unwrap();
+
+        let file = File::open(&archive).unwrap();
+        let err = read(&archive, &file, false).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(path(&archive)).unwrap();
+        fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn lists_headers_from_a_fresh_index() {
+        let dir = env::temp_dir().join(format!("kzip-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("headers.kzip").to_string_lossy().to_string();
+        // stored, so the middle of the archive is outside of what the
+        // fingerprint checksums
+        let mut writer = ArchiveWriter::create(&archive, 3).unwrap();
+        for (i, name) in ["a", "b", "c"].iter().enumerate() {
+            let header = Header {
+                name: OsStr::new(name),
+                created_at: None,
+                modified: 1_700_000_000,
+                meta: &[],
+            };
+            let content = vec![i as u8; 100_000];
+            writer.add(&header, &content, Codec::Store).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let expected = ArchiveReader::headers(&archive).unwrap();
+        let mut reader = ArchiveReader::open(&archive).unwrap();
+        assert_eq!(expected.len(), 3);
+        write(&mut reader, &archive).unwrap();
+        drop(reader);
+
+        // with the header of the middle entry broken, only the index has it
+        let file = fs::OpenOptions::new().write(true).open(&archive).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        let mut bytes = fs::read(&archive).unwrap();
+        let start = expected[1].header_offset as usize;
+        bytes[start..start + 16].fill(0xff);
+        fs::write(&archive, bytes).unwrap();
+        file.set_modified(modified).unwrap();
+        drop(file);
+        assert!(ArchiveReader::open(&archive).is_err());
+
+        let entries = ArchiveReader::headers(&archive).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        for (entry, expected) in entries.iter().zip(&expected) {
+            assert_eq!(
+                (entry.header_offset, entry.offset, entry.length),
+                (expected.header_offset, expected.offset, expected.length)
+            );
+        }
+
+        fs::remove_file(path(&archive)).unwrap();
+        fs::remove_file(archive).unwrap();
+    }
+}
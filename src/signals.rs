@@ -0,0 +1,88 @@
+use std::ffi::OsStr;
+
+/*
+    Removes the file that is being written when kzip gets interrupted with
+    ctrl+c (SIGINT) or killed with SIGTERM, so a half written archive or
+    extracted file isn't left behind looking like a complete one.
+
+    Only one file is tracked at a time, the handler has to stay async signal
+    safe, so it only unlinks a path that was prepared beforehand and exits.
+*/
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        ffi::{c_char, c_int, c_void, CString, OsStr},
+        os::unix::ffi::OsStrExt,
+        ptr,
+        sync::atomic::{AtomicPtr, Ordering},
+    };
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn unlink(path: *const c_char) -> c_int;
+        fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    static CURRENT: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+
+    extern "C" fn handle(signum: c_int) {
+        let path = CURRENT.swap(ptr::null_mut(), Ordering::SeqCst);
+        let message: &[u8] = if path.is_null() {
+            b"\nkzip: interrupted\n"
+        } else {
+            unsafe { unlink(path) };
+            b"\nkzip: interrupted, removed the unfinished file\n"
+        };
+
+        unsafe {
+            write(2, message.as_ptr() as *const c_void, message.len());
+            _exit(128 + signum);
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            signal(SIGINT, handle);
+            signal(SIGTERM, handle);
+        }
+    }
+
+    pub fn track(path: Option<&OsStr>) {
+        let new = match path.and_then(|path| CString::new(path.as_bytes()).ok()) {
+            Some(path) => path.into_raw(),
+            None => ptr::null_mut(),
+        };
+
+        // the handler runs on this thread, so it either sees the old path or
+        // the new one, never one that is being freed
+        let old = CURRENT.swap(new, Ordering::SeqCst);
+        if !old.is_null() {
+            drop(unsafe { CString::from_raw(old) });
+        }
+    }
+}
+
+/// Starts removing tracked files on SIGINT and SIGTERM.
+pub fn install() {
+    #[cfg(unix)]
+    unix::install();
+}
+
+/// Sets the file to remove if kzip is interrupted now.
+pub fn remove_on_interrupt(path: impl AsRef<OsStr>) {
+    #[cfg(unix)]
+    unix::track(Some(path.as_ref()));
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// The tracked file is complete, stop removing it on interrupts.
+pub fn keep() {
+    #[cfg(unix)]
+    unix::track(None);
+}
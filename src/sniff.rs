@@ -0,0 +1,69 @@
+/// What a file is, told from the bytes it starts with rather than its name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Already compressed, compressing it again only costs time.
+    Compressed(&'static str),
+    /// Machine code, which compresses but could do better with a filter.
+    Executable(&'static str),
+    Unknown,
+}
+
+impl Kind {
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Kind::Compressed(name) | Kind::Executable(name) => Some(name),
+            Kind::Unknown => None,
+        }
+    }
+}
+
+// (offset, magic bytes, name)
+const COMPRESSED: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "png"),
+    (0, b"\xff\xd8\xff", "jpeg"),
+    (0, b"GIF87a", "gif"),
+    (0, b"GIF89a", "gif"),
+    (8, b"WEBP", "webp"),
+    (4, b"ftyp", "mp4"),
+    (0, b"ID3", "mp3"),
+    (0, b"OggS", "ogg"),
+    (0, b"fLaC", "flac"),
+    (0, b"wOFF", "woff"),
+    (0, b"wOF2", "woff2"),
+    // also jar, apk, docx and the other formats that are zips inside
+    (0, b"PK\x03\x04", "zip"),
+    (0, b"PK\x05\x06", "zip"),
+    (0, b"\x1f\x8b", "gzip"),
+    (0, b"BZh", "bzip2"),
+    (0, b"\xfd7zXZ\x00", "xz"),
+    (0, b"\x28\xb5\x2f\xfd", "zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z"),
+    (0, b"Rar!\x1a\x07", "rar"),
+    (0, &crate::archive::MAGIC, "kzip"),
+];
+
+const EXECUTABLE: &[(usize, &[u8], &str)] = &[
+    (0, b"\x7fELF", "elf"),
+    (0, b"MZ", "pe"),
+    (0, b"\xfe\xed\xfa\xce", "mach-o"),
+    (0, b"\xfe\xed\xfa\xcf", "mach-o"),
+    (0, b"\xce\xfa\xed\xfe", "mach-o"),
+    (0, b"\xcf\xfa\xed\xfe", "mach-o"),
+    // universal mach-o binaries and java classes start the same
+    (0, b"\xca\xfe\xba\xbe", "mach-o"),
+    (0, b"\x00asm", "wasm"),
+];
+
+pub fn sniff(content: &[u8]) -> Kind {
+    let matches = |(offset, magic, _): &&(usize, &[u8], &str)| {
+        content.get(*offset..offset + magic.len()) == Some(*magic)
+    };
+
+    if let Some((_, _, name)) = COMPRESSED.iter().find(matches) {
+        return Kind::Compressed(name);
+    }
+    if let Some((_, _, name)) = EXECUTABLE.iter().find(matches) {
+        return Kind::Executable(name);
+    }
+    Kind::Unknown
+}
@@ -0,0 +1,215 @@
+use std::{
+    ffi::OsStr,
+    fs::Metadata,
+    io::{self, ErrorKind},
+};
+
+/*
+    Fifos, sockets and device nodes. Reading them hangs or never ends, so they
+    are found up front and, with --special=store, zipped as the node itself:
+    its type, permissions and device number, which is enough to create it
+    again when extracting as root.
+*/
+
+/// What happens to special files while zipping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    Skip,
+    Store,
+    Fail,
+}
+
+impl Policy {
+    pub fn parse(policy: &str) -> Option<Policy> {
+        match policy {
+            "skip" => Some(Policy::Skip),
+            "store" => Some(Policy::Store),
+            "fail" => Some(Policy::Fail),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Fifo => "fifo",
+            Kind::Socket => "socket",
+            Kind::CharDevice => "character device",
+            Kind::BlockDevice => "block device",
+        }
+    }
+}
+
+/// A special file as it is stored, the content of its entry.
+#[derive(Clone, Copy, Debug)]
+pub struct Special {
+    pub kind: Kind,
+    /// The permission bits.
+    pub mode: u32,
+    /// The device number, 0 for fifos and sockets.
+    pub device: u64,
+}
+
+// kind, mode and device
+pub const LENGTH: usize = 1 + 4 + 8;
+
+impl Special {
+    /// The special file `metadata` describes, None for files and directories.
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Option<Special> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_fifo() {
+            Kind::Fifo
+        } else if file_type.is_socket() {
+            Kind::Socket
+        } else if file_type.is_char_device() {
+            Kind::CharDevice
+        } else if file_type.is_block_device() {
+            Kind::BlockDevice
+        } else {
+            return None;
+        };
+
+        Some(Special {
+            kind,
+            mode: metadata.mode() & 0o7777,
+            device: metadata.rdev(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &Metadata) -> Option<Special> {
+        None
+    }
+
+    /// Whether this is a character or block device, which only root can
+    /// create.
+    pub fn is_device(&self) -> bool {
+        matches!(self.kind, Kind::CharDevice | Kind::BlockDevice)
+    }
+
+    /// The major and minor numbers of the device, split the way the system
+    /// kzip runs on does.
+    pub fn device_numbers(&self) -> (u64, u64) {
+        let device = self.device;
+        if cfg!(target_os = "macos") {
+            ((device >> 24) & 0xff, device & 0xff_ffff)
+        } else {
+            (
+                ((device >> 32) & 0xffff_f000) | ((device >> 8) & 0xfff),
+                ((device >> 12) & 0xffff_ff00) | (device & 0xff),
+            )
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(LENGTH);
+        bytes.push(match self.kind {
+            Kind::Fifo => 0,
+            Kind::Socket => 1,
+            Kind::CharDevice => 2,
+            Kind::BlockDevice => 3,
+        });
+        bytes.extend(self.mode.to_be_bytes());
+        bytes.extend(self.device.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Special> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid special file");
+        if bytes.len() != LENGTH {
+            return Err(invalid());
+        }
+
+        let kind = match bytes[0] {
+            0 => Kind::Fifo,
+            1 => Kind::Socket,
+            2 => Kind::CharDevice,
+            3 => Kind::BlockDevice,
+            _ => return Err(invalid()),
+        };
+        Ok(Special {
+            kind,
+            mode: u32::from_be_bytes(bytes[1..5].try_into().unwrap()),
+            device: u64::from_be_bytes(bytes[5..].try_into().unwrap()),
+        })
+    }
+
+    /// Creates the node at `path`. Sockets only exist while a program listens
+    /// on them, so they can't be created, and devices need root.
+    pub fn create(&self, path: &OsStr) -> io::Result<()> {
+        if self.kind == Kind::Socket {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "sockets can't be extracted",
+            ));
+        }
+
+        create(path, self)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn create(path: &OsStr, special: &Special) -> io::Result<()> {
+    use std::{
+        ffi::{c_char, c_int, CString},
+        fs::{self, Permissions},
+        os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    };
+
+    #[cfg(target_os = "linux")]
+    type ModeT = u32;
+    #[cfg(target_os = "linux")]
+    type DevT = u64;
+    #[cfg(target_os = "macos")]
+    type ModeT = u16;
+    #[cfg(target_os = "macos")]
+    type DevT = i32;
+
+    const S_IFIFO: u32 = 0o010000;
+    const S_IFCHR: u32 = 0o020000;
+    const S_IFBLK: u32 = 0o060000;
+
+    extern "C" {
+        fn mknod(path: *const c_char, mode: ModeT, device: DevT) -> c_int;
+    }
+
+    let file_type = match special.kind {
+        Kind::Fifo => S_IFIFO,
+        Kind::CharDevice => S_IFCHR,
+        Kind::BlockDevice => S_IFBLK,
+        Kind::Socket => unreachable!(),
+    };
+    let c_path = CString::new(path.as_bytes())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+    let mode = (file_type | special.mode) as ModeT;
+    if unsafe { mknod(c_path.as_ptr(), mode, special.device as DevT) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // mknod leaves out what the umask takes away
+    fs::set_permissions(path, Permissions::from_mode(special.mode))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn create(_path: &OsStr, special: &Special) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "a {} can't be extracted on this system",
+            special.kind.name()
+        ),
+    ))
+}
@@ -0,0 +1,132 @@
+use std::{collections::HashMap, path, process::exit};
+
+use crate::{
+    archive::{ArchiveReader, Entry},
+    utils::{format_byte, format_ratio, positionals},
+};
+
+#[derive(Default)]
+struct Group {
+    files: u64,
+    unpacked: u64,
+    packed: u64,
+}
+
+/// `kzip stats archive.kzip`
+///
+/// Shows what takes up the space in an archive, grouped by file extension
+/// and by top-level directory, the biggest first. Duplicates count as files
+/// but take up no space of their own.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &[]);
+    let Some(input) = positionals.first() else {
+        println!("kzip: stats needs an archive, i.e. kzip stats archive.kzip");
+        exit(1);
+    };
+
+    let reader = match ArchiveReader::open(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let mut total = Group::default();
+    for entry in &reader.entries {
+        add(&mut total, entry);
+    }
+    println!("Files: {}", total.files);
+    println!("Unpacked: {}", format_byte(total.unpacked as f64));
+    println!(
+        "Packed: {} ({})",
+        format_byte(total.packed as f64),
+        format_ratio(total.packed, total.unpacked)
+    );
+
+    print_groups("Extension", &reader.entries, &total, extension);
+    let depth = top_level_depth(&reader.entries);
+    print_groups("Directory", &reader.entries, &total, |name| {
+        top_level(name, depth)
+    });
+    exit(0);
+}
+
+fn add(group: &mut Group, entry: &Entry) {
+    group.files += 1;
+    group.unpacked += entry.unpacked_length;
+    if !entry.is_duplicate() {
+        group.packed += entry.length;
+    }
+}
+
+fn print_groups(title: &str, entries: &[Entry], total: &Group, key: impl Fn(&str) -> String) {
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    for entry in entries {
+        add(groups.entry(key(&entry.name)).or_default(), entry);
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(a_key, a), (b_key, b)| b.packed.cmp(&a.packed).then(a_key.cmp(b_key)));
+
+    let width = groups
+        .iter()
+        .map(|(key, _)| key.chars().count())
+        .chain([title.len()])
+        .max()
+        .unwrap_or_default();
+    println!();
+    println!(
+        "{title:<width$}  {:>7}  {:>10}  {:>10}  {:>6}  {:>6}",
+        "Files", "Unpacked", "Packed", "Ratio", "Share"
+    );
+    for (key, group) in groups {
+        println!(
+            "{key:<width$}  {:>7}  {:>10}  {:>10}  {:>6}  {:>6}",
+            group.files,
+            format_byte(group.unpacked as f64),
+            format_byte(group.packed as f64),
+            format_ratio(group.packed, group.unpacked),
+            format_ratio(group.packed, total.packed)
+        );
+    }
+}
+
+fn extension(name: &str) -> String {
+    let file_name = name.rsplit(path::MAIN_SEPARATOR).next().unwrap_or(name);
+    match file_name.rsplit_once('.') {
+        // .bashrc is a hidden file, not an extension
+        Some((stem, extension)) if !stem.is_empty() => format!(".{}", extension.to_lowercase()),
+        _ => "(none)".to_string(),
+    }
+}
+
+/// How many directories every entry starts with, as archives usually have
+/// every file in the directory they were made of, the top-level directories
+/// are the ones in there.
+fn top_level_depth(entries: &[Entry]) -> usize {
+    let Some(first) = entries.first() else {
+        return 0;
+    };
+    let mut common: Vec<&str> = first.name.split(path::MAIN_SEPARATOR).collect();
+    common.pop();
+    for entry in entries {
+        let mut parts: Vec<&str> = entry.name.split(path::MAIN_SEPARATOR).collect();
+        parts.pop();
+        let same = common
+            .iter()
+            .zip(&parts)
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(same);
+    }
+    common.len()
+}
+
+fn top_level(name: &str, depth: usize) -> String {
+    let parts: Vec<&str> = name.split(path::MAIN_SEPARATOR).collect();
+    if parts.len() <= depth + 1 {
+        // a file right in the common directory
+        return "(files)".to_string();
+    }
+    parts[..=depth].join(path::MAIN_SEPARATOR_STR)
+}
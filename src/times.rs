@@ -0,0 +1,68 @@
+use std::{
+    fs::{File, FileTimes},
+    io,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use kzip::archive::Entry;
+
+/// The times extracted files get from their entries with `--preserve-times`,
+/// otherwise they have the time they were extracted at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preserve {
+    None,
+    Modified,
+    /// The modification and the creation time, which can only be set on
+    /// windows and macos.
+    All,
+}
+
+impl Preserve {
+    /// Reads `none`, `mtime` or `all`.
+    pub fn parse(preserve: &str) -> Option<Preserve> {
+        match preserve {
+            "none" => Some(Preserve::None),
+            "mtime" => Some(Preserve::Modified),
+            "all" => Some(Preserve::All),
+            _ => None,
+        }
+    }
+}
+
+/// Sets the times `preserve` keeps of `entry` on the file extracted to
+/// `path`. Entries without a creation time only get their modification time.
+pub fn apply(path: &Path, entry: &Entry, preserve: Preserve) -> io::Result<()> {
+    if preserve == Preserve::None {
+        return Ok(());
+    }
+
+    let time = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+    let mut times = FileTimes::new().set_modified(time(entry.modified));
+    if preserve == Preserve::All {
+        if let Some(created_at) = entry.created_at {
+            times = set_created(times, time(created_at));
+        }
+    }
+    File::options().write(true).open(path)?.set_times(times)
+}
+
+#[cfg(windows)]
+fn set_created(times: FileTimes, created_at: std::time::SystemTime) -> FileTimes {
+    use std::os::windows::fs::FileTimesExt;
+
+    times.set_created(created_at)
+}
+
+#[cfg(target_os = "macos")]
+fn set_created(times: FileTimes, created_at: std::time::SystemTime) -> FileTimes {
+    use std::os::macos::fs::FileTimesExt;
+
+    times.set_created(created_at)
+}
+
+/// Other systems don't let the creation time be changed.
+#[cfg(not(any(windows, target_os = "macos")))]
+fn set_created(times: FileTimes, _created_at: std::time::SystemTime) -> FileTimes {
+    times
+}
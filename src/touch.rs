@@ -0,0 +1,77 @@
+use std::process::exit;
+
+use crate::{
+    archive::ArchiveReader,
+    filter::parse_date,
+    history,
+    pattern::glob_match,
+    rewrite::{self, Change},
+    utils::{option_value, positionals},
+};
+
+/// `kzip touch archive.kzip 'glob' --mtime 2024-01-01T00:00:00Z [--created date]`
+///
+/// Sets the stored times of every file matching the glob, i.e. to make two
+/// archives of the same files compare equal.
+pub fn run(args: &[String]) {
+    let positionals = positionals(&args[2..], &["--mtime", "--created"]);
+    let [input, glob] = &positionals[..] else {
+        println!("kzip: touch needs an archive and a glob, i.e. kzip touch archive.kzip 'src/**' --mtime 2024-01-01");
+        exit(1);
+    };
+    let is_verbose = args.iter().any(|arg| arg == "--verbose" || arg == "-v");
+
+    let date = |option: &str| {
+        option_value(args, &[option]).map(|date| match parse_date(&date) {
+            Ok(date) => date,
+            Err(err) => {
+                println!("kzip: {err}");
+                exit(1);
+            }
+        })
+    };
+    let modified = date("--mtime");
+    let created_at = date("--created");
+    if modified.is_none() && created_at.is_none() {
+        println!("kzip: touch needs --mtime or --created, i.e. --mtime 2024-01-01T00:00:00Z");
+        exit(1);
+    }
+
+    let mut reader = match ArchiveReader::open_exclusive(input) {
+        Ok(reader) => reader,
+        Err(err) => {
+            println!("kzip: {input}: {err}");
+            exit(1);
+        }
+    };
+
+    let mut touched = 0;
+    let mut changes = Vec::new();
+    for entry in &reader.entries {
+        let mut change = Change::keep(entry);
+        if glob_match(glob, &entry.name) {
+            if is_verbose {
+                println!("kzip: touching {}", entry.name);
+            }
+            change.modified = modified.unwrap_or(change.modified);
+            change.created_at = created_at.or(change.created_at);
+            touched += 1;
+        }
+        changes.push(change);
+    }
+
+    if touched == 0 {
+        println!("kzip: nothing in {input} matches {glob}");
+        exit(1);
+    }
+
+    history::record(&mut reader.meta, "touch", touched);
+    if let Err(err) = rewrite::rewrite(input, reader, changes) {
+        println!("kzip: There was an error writing to {input}");
+        println!("{:#?}", err);
+        exit(1);
+    }
+
+    println!("kzip: Touched {touched} file(s) in {input}");
+    exit(0);
+}
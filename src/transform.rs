@@ -0,0 +1,131 @@
+use std::path;
+
+use crate::pattern::Regex;
+
+/// A sed style `s/regex/replacement/flags` rule for renaming entries, given
+/// with `--transform`. Any character can be the delimiter, i.e.
+/// `s#^build/#dist/#`. The regex is the same as for `kzip grep`, so groups
+/// are `(...)` and not `\(...\)` like in sed. `&` in the replacement is the
+/// whole match and `\1` to `\9` are groups, the flags are `g` for every match
+/// and `i` to ignore case.
+pub struct Transform {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Transform {
+    pub fn parse(expression: &str) -> Result<Transform, String> {
+        let invalid = || format!("{expression} is not a transform, use i.e. 's#^build/#dist/#'");
+
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid());
+        }
+        let delimiter = chars.next().ok_or_else(invalid)?;
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    // an escaped delimiter is just the character
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => {
+                        let part = parts.last_mut().unwrap();
+                        part.push('\\');
+                        part.push(next);
+                    }
+                    None => return Err(invalid()),
+                },
+                c if c == delimiter => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let [pattern, replacement, flags] = &parts[..] else {
+            return Err(invalid());
+        };
+
+        if let Some(flag) = flags.chars().find(|flag| !matches!(flag, 'g' | 'i')) {
+            return Err(format!(
+                "{expression} has an unknown flag {flag}, use g or i"
+            ));
+        }
+        let regex = Regex::with_options(pattern, flags.contains('i'))
+            .map_err(|err| format!("{expression}: {err}"))?;
+
+        Ok(Transform {
+            regex,
+            replacement: replacement.to_string(),
+            global: flags.contains('g'),
+        })
+    }
+
+    /// Renames `name`, which uses `/` between directories.
+    pub fn apply(&self, name: &str) -> String {
+        let mut result = String::new();
+        let mut position = 0;
+
+        while let Some(captures) = self.regex.captures_from(name, position) {
+            let (start, end) = captures[0].unwrap();
+            result.push_str(&name[position..start]);
+            self.expand(name, &captures, &mut result);
+            position = end;
+
+            if !self.global {
+                break;
+            }
+            if start == end {
+                // an empty match, move on by one character so it isn't found again
+                match name[end..].chars().next() {
+                    Some(c) => {
+                        result.push(c);
+                        position += c.len_utf8();
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        result.push_str(&name[position..]);
+        result
+    }
+
+    fn expand(&self, name: &str, captures: &[Option<(usize, usize)>], result: &mut String) {
+        let mut chars = self.replacement.chars();
+        while let Some(c) = chars.next() {
+            let group = match c {
+                '&' => Some(0),
+                '\\' => match chars.next() {
+                    Some(digit @ '1'..='9') => digit.to_digit(10).map(|digit| digit as usize),
+                    Some(escaped) => {
+                        result.push(escaped);
+                        None
+                    }
+                    None => None,
+                },
+                c => {
+                    result.push(c);
+                    None
+                }
+            };
+
+            if let Some(Some((start, end))) = group.and_then(|group| captures.get(group)) {
+                result.push_str(&name[*start..*end]);
+            }
+        }
+    }
+}
+
+/// Runs every rule over a name in order. Names use the platform separator,
+/// rules are always written with `/`.
+pub fn apply_all(transforms: &[Transform], name: &str) -> String {
+    if transforms.is_empty() {
+        return name.to_string();
+    }
+
+    let mut name = name.replace(path::MAIN_SEPARATOR, "/");
+    for transform in transforms {
+        name = transform.apply(&name);
+    }
+    name.replace('/', path::MAIN_SEPARATOR_STR)
+}
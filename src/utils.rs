@@ -0,0 +1,507 @@
+use std::{
+    cmp,
+    collections::HashSet,
+    env,
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, RandomState},
+    io::{self, ErrorKind},
+    path::{self, Path, PathBuf},
+    process::exit,
+};
+
+use time::OffsetDateTime;
+
+/// The arguments kzip was started with. They are read as `OsString`s so one
+/// that isn't valid Unicode, i.e. broken UTF-16 on windows, is reported
+/// instead of panicking.
+pub fn args() -> Vec<String> {
+    env::args_os()
+        .map(|arg| {
+            arg.into_string().unwrap_or_else(|arg| {
+                println!(
+                    "kzip: {} isn't valid Unicode, kzip can't read it",
+                    arg.to_string_lossy()
+                );
+                exit(1);
+            })
+        })
+        .collect()
+}
+
+/// Returns the value that follows an option, i.e. `8080` for `--port 8080`.
+pub fn option_value(args: &[String], names: &[&str]) -> Option<String> {
+    let index = args
+        .iter()
+        .position(|f| names.iter().any(|name| f.eq_ignore_ascii_case(name)))?;
+
+    args.get(index + 1).cloned()
+}
+
+/// The values of an option that can be given more than once.
+pub fn option_values(args: &[String], names: &[&str]) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| names.iter().any(|name| pair[0].eq_ignore_ascii_case(name)))
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// The password for encrypted files, from the first line of `--password-file`
+/// or else from KZIP_PASSWORD, so it doesn't end up in the shell history.
+pub fn password(args: &[String]) -> Result<Option<String>, String> {
+    if let Some(password_file) = option_value(args, &["--password-file"]) {
+        let password = fs::read_to_string(&password_file)
+            .map_err(|err| format!("could not read {password_file}: {err}"))?;
+        let password = password.lines().next().unwrap_or_default();
+        if password.is_empty() {
+            return Err(format!("{password_file} has no password in it"));
+        }
+        return Ok(Some(password.to_string()));
+    }
+
+    Ok(env::var("KZIP_PASSWORD")
+        .ok()
+        .filter(|password| !password.is_empty()))
+}
+
+/// The SHA-256 hashes in a file as sha256sum writes them, one per line with
+/// anything after the hash ignored. Empty lines and lines starting with `#`
+/// are left out.
+pub fn hash_list(path: &str) -> Result<HashSet<String>, String> {
+    let list = fs::read_to_string(path).map_err(|err| format!("could not read {path}: {err}"))?;
+
+    let mut hashes = HashSet::new();
+    for (number, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hash = line.split_whitespace().next().unwrap_or_default();
+        if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(format!(
+                "line {} of {path} doesn't start with a SHA-256 hash",
+                number + 1
+            ));
+        }
+        hashes.insert(hash.to_ascii_lowercase());
+    }
+
+    Ok(hashes)
+}
+
+/// Makes sure a file that was just created in `dir` stays there after a
+/// crash, which syncing the file alone doesn't on every file system. Windows
+/// can't open directories to sync them, NTFS keeps its metadata in a log.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// How many bytes can still be written to the file system `path` is on.
+/// `path` doesn't have to exist yet, the closest directory that does is asked.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let mut existing = path;
+    while !existing.exists() {
+        existing = match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+    statvfs(existing)
+}
+
+#[cfg(any(
+    all(target_os = "linux", target_pointer_width = "64"),
+    target_os = "macos"
+))]
+fn statvfs(path: &Path) -> io::Result<u64> {
+    use std::{
+        ffi::{c_char, c_int, c_ulong, CString},
+        os::unix::ffi::OsStrExt,
+    };
+
+    // the block counts are 64 bit on linux and 32 bit on macos
+    #[cfg(target_os = "linux")]
+    type FsBlkCnt = u64;
+    #[cfg(target_os = "macos")]
+    type FsBlkCnt = u32;
+
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: FsBlkCnt,
+        f_bfree: FsBlkCnt,
+        f_bavail: FsBlkCnt,
+        // the rest isn't needed, only room for it
+        rest: [u64; 16],
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let mut stat = StatVfs {
+        f_bsize: 0,
+        f_frsize: 0,
+        f_blocks: 0,
+        f_bfree: 0,
+        f_bavail: 0,
+        rest: [0; 16],
+    };
+    if unsafe { statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // what is left for users that aren't root, the casts are needed on macos
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(any(
+    all(target_os = "linux", target_pointer_width = "64"),
+    target_os = "macos"
+)))]
+fn statvfs(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "the free space can't be found out on this system",
+    ))
+}
+
+/// The most memory kzip had in use at once so far, its peak resident set.
+#[cfg(all(unix, target_pointer_width = "64"))]
+pub fn peak_memory() -> io::Result<u64> {
+    use std::ffi::{c_int, c_long};
+
+    const RUSAGE_SELF: c_int = 0;
+    extern "C" {
+        // struct rusage is two timevals and 14 longs, ru_maxrss comes first
+        fn getrusage(who: c_int, usage: *mut [c_long; 18]) -> c_int;
+    }
+
+    let mut usage = [0; 18];
+    if unsafe { getrusage(RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let max_rss = usage[4] as u64;
+    // macos counts bytes, everything else kilobytes
+    Ok(if cfg!(target_os = "macos") {
+        max_rss
+    } else {
+        max_rss * 1024
+    })
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+pub fn peak_memory() -> io::Result<u64> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "the memory used can't be found out on this system",
+    ))
+}
+
+/// Linux capabilities kzip checks for before it needs them.
+pub const CAP_CHOWN: u32 = 0;
+pub const CAP_MKNOD: u32 = 27;
+
+/// Whether kzip runs as root, or on linux with `capability` in its
+/// effective set.
+#[cfg(unix)]
+pub fn is_privileged(capability: u32) -> bool {
+    extern "C" {
+        fn geteuid() -> u32;
+    }
+
+    if unsafe { geteuid() } == 0 {
+        return true;
+    }
+    fs::read_to_string("/proc/self/status").is_ok_and(|status| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+            .is_some_and(|caps| caps & (1 << capability) != 0)
+    })
+}
+
+#[cfg(not(unix))]
+pub fn is_privileged(_capability: u32) -> bool {
+    false
+}
+
+/// Returns every argument that is neither an option nor the value of one of
+/// `value_options`.
+pub fn positionals(args: &[String], value_options: &[&str]) -> Vec<String> {
+    let mut positionals = Vec::new();
+    let mut skip_next = false;
+
+    for arg in args {
+        if skip_next {
+            skip_next = false;
+        } else if arg.starts_with('-') {
+            skip_next = value_options
+                .iter()
+                .any(|name| arg.eq_ignore_ascii_case(name));
+        } else {
+            positionals.push(arg.to_string());
+        }
+    }
+
+    positionals
+}
+
+/*
+    Stolen from: https://github.com/banyan/rust-pretty-bytes/blob/master/src/converter.rs
+*/
+pub fn format_byte(num: f64) -> String {
+    let negative = if num.is_sign_positive() { "" } else { "-" };
+    let num = num.abs();
+    let units = ["B", "kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+    if num < 1_f64 {
+        return format!("{}{} {}", negative, num, "B");
+    }
+    let delimiter = 1000_f64;
+    let exponent = cmp::min(
+        (num.ln() / delimiter.ln()).floor() as i32,
+        (units.len() - 1) as i32,
+    );
+    let pretty_bytes = format!("{:.2}", num / delimiter.powi(exponent))
+        .parse::<f64>()
+        .unwrap()
+        * 1_f64;
+    let unit = units[exponent as usize];
+    format!("{}{} {}", negative, pretty_bytes, unit)
+}
+
+/// How big the packed size is compared to the unpacked one, i.e. `35.2%`.
+pub fn format_ratio(packed: u64, unpacked: u64) -> String {
+    if unpacked == 0 {
+        return "-".to_string();
+    }
+
+    format!("{:.1}%", packed as f64 * 100.0 / unpacked as f64)
+}
+
+/// Orders entry names so the files of a directory come before its
+/// subdirectories, the same order `--order dir` zips them in.
+pub fn directory_order(a: &str, b: &str) -> cmp::Ordering {
+    let split = |name: &str| -> (Vec<String>, String) {
+        let mut parts: Vec<String> = name
+            .split(path::MAIN_SEPARATOR)
+            .map(|part| part.to_string())
+            .collect();
+        let file_name = parts.pop().unwrap_or_default();
+        (parts, file_name)
+    };
+
+    split(a).cmp(&split(b))
+}
+
+/// A file in a directory of its own in the temporary directory, that only
+/// the user running kzip can get into. Both are removed when it is dropped.
+pub struct TempFile {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates a file named like the last part of the entry `name`, so
+    /// programs can still tell the file type. The temporary directory is
+    /// shared, so the directory gets a random name and is only ever made
+    /// anew, never reused or written through a symlink someone else left
+    /// there, trying another name when one is taken.
+    pub fn create(name: &str) -> io::Result<(TempFile, File)> {
+        // the entry name could be `..` or have separators in it
+        let file_name = Path::new(name)
+            .file_name()
+            .filter(|file_name| Path::new(file_name).file_name() == Some(file_name))
+            .unwrap_or("entry".as_ref());
+
+        let mut tries = 0;
+        let dir = loop {
+            let random = RandomState::new().hash_one(tries);
+            let dir = env::temp_dir().join(format!("kzip-{}-{random:016x}", std::process::id()));
+            match create_private_dir(&dir) {
+                Ok(()) => break dir,
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && tries < 16 => tries += 1,
+                Err(err) => return Err(err),
+            }
+        };
+
+        let path = dir.join(file_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => Ok((TempFile { dir, path }, file)),
+            Err(err) => {
+                let _ = fs::remove_dir(&dir);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().mode(0o700).create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    // the temporary directory of every user is their own on windows
+    fs::create_dir(dir)
+}
+
+/// Formats a unix timestamp like `1994-11-06T08:49:37Z`.
+pub fn iso_date(timestamp: u64) -> String {
+    let date =
+        OffsetDateTime::from_unix_timestamp(timestamp as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        date.year(),
+        date.month() as u8,
+        date.day(),
+        date.hour(),
+        date.minute(),
+        date.second()
+    )
+}
+
+pub fn create_dir_if_not_exists(output: &Path) {
+    if let Err(err) = fs::metadata(output) {
+        if err.kind() == ErrorKind::NotFound {
+            // directory does not exist, so create it
+            if let Err(err_dir) = fs::create_dir_all(output) {
+                println!(
+                    "kzip: There was an error creating directory {}",
+                    output.display()
+                );
+                println!("{:#?}", err_dir);
+                exit(1);
+            }
+        } else {
+            println!("kzip: There was an error writing to {}", output.display());
+            println!("{:#?}", err);
+            exit(1);
+        }
+    }
+}
+
+/*
+    Windows can't create every name other systems can. When extracting on
+    windows, each part of an entry name is escaped like this:
+      - characters windows doesn't allow (< > : " | ? * and control
+        characters) become %XX, i.e. "a:b" becomes "a%3Ab"
+      - a trailing dot or space becomes %2E or %20
+      - reserved device names (CON, PRN, AUX, NUL, COM1-9, LPT1-9, also with
+        an extension) get their first letter escaped, i.e. "nul.txt" becomes
+        "%6Eul.txt"
+*/
+pub fn windows_safe_name(name: &str) -> String {
+    name.split(path::MAIN_SEPARATOR)
+        .map(windows_safe_component)
+        .collect::<Vec<String>>()
+        .join(path::MAIN_SEPARATOR_STR)
+}
+
+fn windows_safe_component(component: &str) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for c in component.chars() {
+        if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+
+    let trimmed_length = escaped.trim_end_matches(['.', ' ']).len();
+    if trimmed_length < escaped.len() && trimmed_length > 0 {
+        let trailing: String = escaped[trimmed_length..]
+            .chars()
+            .map(|c| format!("%{:02X}", c as u32))
+            .collect();
+        escaped.truncate(trimmed_length);
+        escaped.push_str(&trailing);
+    }
+
+    let stem = escaped
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let is_reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || (stem.len() == 4
+            && (stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.as_bytes()[3].is_ascii_digit()
+            && stem.as_bytes()[3] != b'0');
+    if is_reserved {
+        escaped = format!("%{:02X}{}", escaped.as_bytes()[0], &escaped[1..]);
+    }
+
+    escaped
+}
+
+/// Turns a path longer than the 260 characters windows allows by default
+/// into a `\\?\` path, which can be up to 32767 characters long.
+pub fn long_path(path: String) -> String {
+    if !cfg!(windows) || path.len() < 260 || path.starts_with(r"\\?\") {
+        return path;
+    }
+
+    let absolute = match path::absolute(&path) {
+        Ok(absolute) => absolute.to_string_lossy().to_string(),
+        Err(_) => return path,
+    };
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{unc}"),
+        None => format!(r"\\?\{absolute}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_files_are_private_and_new() {
+        let (first, _) = TempFile::create("dir/notes.txt").unwrap();
+        let (second, _) = TempFile::create("notes.txt").unwrap();
+        assert_ne!(first.path, second.path);
+        assert!(first.path.ends_with("notes.txt"));
+        assert!(first.path.starts_with(env::temp_dir()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = first.path.parent().unwrap();
+            assert_eq!(
+                fs::metadata(dir).unwrap().permissions().mode() & 0o777,
+                0o700
+            );
+        }
+
+        let dir = first.path.parent().unwrap().to_path_buf();
+        drop(first);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn temp_files_stay_in_their_directory() {
+        for name in ["../../etc/passwd", "a/..", "..", ""] {
+            let (temp_file, _) = TempFile::create(name).unwrap();
+            let dir = temp_file.path.parent().unwrap();
+            assert!(dir.starts_with(env::temp_dir()), "{name}");
+            assert_ne!(dir, env::temp_dir(), "{name}");
+        }
+    }
+}
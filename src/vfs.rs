@@ -0,0 +1,313 @@
+//! Where files come from when zipping and go to when extracting with
+//! `ArchiveWriter::add_tree` and `ArchiveReader::extract_into`. `OsFs` is
+//! the file system itself, `MemoryFs` a tree kept in memory, i.e. for tests,
+//! and other backends only need to implement `Vfs`.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// What an archive needs to know about a file or directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// The length of files, 0 for directories.
+    pub len: u64,
+    /// Seconds since the unix epoch.
+    pub modified: u64,
+    pub created_at: Option<u64>,
+    /// Tells directories apart, so symlinks that loop back to a directory
+    /// that is already being read are noticed. None where nothing can loop.
+    pub id: Option<(u64, u64)>,
+}
+
+/// A file system archives can be built from or extracted into.
+pub trait Vfs {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// The names in a directory, without `.` and `..`.
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Writes a file, creating the directories it is in.
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> io::Result<()>;
+
+    /// Sets when a file was last modified, in seconds since the unix epoch.
+    /// File systems without times don't have to do anything.
+    fn set_modified(&mut self, _path: &Path, _modified: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The file system of the computer, through `std::fs`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFs;
+
+impl Vfs for OsFs {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| PathBuf::from(entry.file_name())))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+        let seconds = |time: std::time::SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        };
+        Ok(Metadata {
+            is_dir: metadata.is_dir(),
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().map_or(0, seconds),
+            created_at: metadata.created().ok().map(seconds),
+            id: if metadata.is_dir() {
+                dir_id(path, &metadata)
+            } else {
+                None
+            },
+        })
+    }
+
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)
+    }
+
+    fn set_modified(&mut self, path: &Path, modified: u64) -> io::Result<()> {
+        let modified = UNIX_EPOCH + Duration::from_secs(modified);
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)
+    }
+}
+
+/// Files kept in memory by their path, directories are every path a file
+/// is in. Everything has the modification time 0.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    pub files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty()
+            || self
+                .files
+                .keys()
+                .any(|file| file.starts_with(path) && file != path)
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return Err(not_found(path));
+        }
+        let mut names: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter_map(|file| file.strip_prefix(path).ok()?.components().next())
+            .map(|name| PathBuf::from(name.as_os_str()))
+            .collect();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.files.get(path) {
+            Some(content) => Ok(Metadata {
+                len: content.len() as u64,
+                ..Metadata::default()
+            }),
+            None if self.is_dir(path) => Ok(Metadata {
+                is_dir: true,
+                ..Metadata::default()
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.files.insert(path.to_path_buf(), content.to_vec());
+        Ok(())
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::NotFound,
+        format!("{} doesn't exist", path.display()),
+    )
+}
+
+/// The device and inode of the directory `path`, or where it really is on
+/// systems without inodes, to notice symlinks that loop back to a directory
+/// that is already being read.
+#[cfg(unix)]
+pub fn dir_id(_path: &Path, metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn dir_id(path: &Path, _metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(path).ok()?.hash(&mut hasher);
+    Some((0, hasher.finish()))
+}
+
+/// Where an entry named `name` goes inside `root` with its first `strip`
+/// directories taken off, without anything that would lead out of `root`
+/// like `..` or a leading `/`. None if nothing is left of the name.
+pub fn entry_path(root: &Path, name: &str, strip: usize) -> Option<PathBuf> {
+    let parts: Vec<_> = name
+        .split(['/', '\\'])
+        .filter_map(normal_part)
+        .skip(strip)
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+    let mut path = root.to_path_buf();
+    path.extend(parts);
+    Some(path)
+}
+
+/// Whether an entry named `name` stays inside of the directory it is
+/// extracted to when it is joined onto it: it doesn't start with `/` and
+/// none of its parts is `..` or a drive.
+pub fn is_inside(name: &str) -> bool {
+    !name.starts_with(['/', '\\'])
+        && name
+            .split(['/', '\\'])
+            .all(|part| part.is_empty() || part == "." || normal_part(part).is_some())
+}
+
+// a part of an entry name that is a name of its own, rather than `..`, `.`,
+// a root or a drive
+fn normal_part(part: &str) -> Option<&OsStr> {
+    match Path::new(part).components().next() {
+        Some(Component::Normal(part)) => Some(part),
+        _ => None,
+    }
+}
+
+/// How many files `ArchiveWriter::add_tree` would add from `root`, leaving
+/// out the names in `excludes`.
+pub fn count_files(vfs: &dyn Vfs, root: &Path, excludes: &[String]) -> io::Result<u32> {
+    let mut count = 0;
+    walk(vfs, root, excludes, |_, _, _| {
+        count += 1;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Calls `file` with the path, the name below `root` and the metadata of
+/// every file in the tree under `root`, leaving out the names in `excludes`
+/// and directories a symlink leads back to. Directories are read from a
+/// stack rather than by recursing, so trees of any depth fit on the stack
+/// of the thread.
+pub(crate) fn walk(
+    vfs: &dyn Vfs,
+    root: &Path,
+    excludes: &[String],
+    mut file: impl FnMut(&Path, &str, Metadata) -> io::Result<()>,
+) -> io::Result<()> {
+    struct OpenDir {
+        path: PathBuf,
+        prefix: String,
+        id: Option<(u64, u64)>,
+        names: std::vec::IntoIter<PathBuf>,
+    }
+
+    let open = |path: PathBuf, prefix: String, id| -> io::Result<OpenDir> {
+        let mut names = vfs.list_dir(&path)?;
+        names.sort();
+        Ok(OpenDir {
+            path,
+            prefix,
+            id,
+            names: names.into_iter(),
+        })
+    };
+
+    let id = vfs.metadata(root)?.id;
+    let mut dirs = vec![open(root.to_path_buf(), String::new(), id)?];
+    while let Some(dir) = dirs.last_mut() {
+        let Some(name) = dir.names.next() else {
+            dirs.pop();
+            continue;
+        };
+        let path = dir.path.join(&name);
+        let name = name.to_string_lossy();
+        if excludes.iter().any(|exclude| *exclude == name) {
+            continue;
+        }
+        let name = format!("{}{name}", dir.prefix);
+        let metadata = vfs.metadata(&path)?;
+        if !metadata.is_dir {
+            file(&path, &name, metadata)?;
+        } else if !metadata
+            .id
+            .is_some_and(|id| dirs.iter().any(|dir| dir.id == Some(id)))
+        {
+            dirs.push(open(path, format!("{name}/"), metadata.id)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_inside() {
+        for name in ["a", "a/b", "a\\b", "a/./b", "a//b", "..a/b..", "a/...", "."] {
+            assert!(is_inside(name), "{name}");
+        }
+        for name in ["..", "a/../../x", "a/..", "a\\..\\x", "/etc/passwd", "\\x"] {
+            assert!(!is_inside(name), "{name}");
+        }
+        #[cfg(windows)]
+        assert!(!is_inside("C:/x") && !is_inside("a/C:x"));
+    }
+
+    #[test]
+    fn entry_paths_leave_out_what_leads_outside() {
+        let root = Path::new("out");
+        assert_eq!(
+            entry_path(root, "a/../../b", 0),
+            Some(root.join("a").join("b"))
+        );
+        assert_eq!(entry_path(root, "/x/y", 1), Some(root.join("y")));
+        assert_eq!(entry_path(root, "x/y", 2), None);
+        assert_eq!(entry_path(root, "..", 0), None);
+    }
+}
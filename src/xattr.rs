@@ -0,0 +1,149 @@
+use std::{io, path::Path};
+
+/// The SELinux label of a file, stored as text.
+pub const SELINUX: &str = "security.selinux";
+/// The POSIX ACL of a file, and the one new files in a directory get.
+/// Only files have entries, so the default one is only ever there for
+/// archives made by something else.
+pub const ACL: &str = "system.posix_acl_access";
+pub const DEFAULT_ACL: &str = "system.posix_acl_default";
+
+/// The attributes kzip keeps, with the meta key each is stored under.
+pub const KEPT: [(&str, &str); 3] = [
+    (SELINUX, "kzip.selinux"),
+    (ACL, "kzip.acl"),
+    (DEFAULT_ACL, "kzip.default-acl"),
+];
+
+/// An attribute as it is stored in meta, labels are text ending in a 0
+/// byte, everything else is stored as hex.
+pub fn to_meta(name: &str, value: &[u8]) -> String {
+    if name == SELINUX {
+        return String::from_utf8_lossy(value.strip_suffix(&[0]).unwrap_or(value)).to_string();
+    }
+    value.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The attribute `to_meta` made `value` of, None if it isn't valid hex.
+pub fn from_meta(name: &str, value: &str) -> Option<Vec<u8>> {
+    if name == SELINUX {
+        let mut label = value.as_bytes().to_vec();
+        label.push(0);
+        return Some(label);
+    }
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// The value of the extended attribute `name` of `path`, None when it has
+/// none. Symlinks aren't followed.
+#[cfg(target_os = "linux")]
+pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    use std::ffi::{c_char, c_void};
+
+    const ENODATA: i32 = 61;
+    const ERANGE: i32 = 34;
+    const ENOTSUP: i32 = 95;
+
+    extern "C" {
+        fn lgetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+        ) -> isize;
+    }
+
+    let (c_path, c_name) = c_strings(path, name)?;
+    // the size first, the attribute can change in between so it is asked
+    // again if it doesn't fit anymore
+    loop {
+        let size = unsafe { lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(ENODATA | ENOTSUP) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        let mut value = vec![0u8; size as usize];
+        let read = unsafe {
+            lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr() as *mut c_void,
+                value.len(),
+            )
+        };
+        if read >= 0 {
+            value.truncate(read as usize);
+            return Ok(Some(value));
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// Sets the extended attribute `name` of `path`. Symlinks aren't followed.
+#[cfg(target_os = "linux")]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    use std::ffi::{c_char, c_int, c_void};
+
+    extern "C" {
+        fn lsetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            flags: c_int,
+        ) -> c_int;
+    }
+
+    let (c_path, c_name) = c_strings(path, name)?;
+    let result = unsafe {
+        lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn c_strings(path: &Path, name: &str) -> io::Result<(std::ffi::CString, std::ffi::CString)> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let invalid = |err| io::Error::new(io::ErrorKind::InvalidInput, err);
+    Ok((
+        CString::new(path.as_os_str().as_bytes()).map_err(invalid)?,
+        CString::new(name).map_err(invalid)?,
+    ))
+}
+
+/// Only linux has the attributes kzip keeps.
+#[cfg(not(target_os = "linux"))]
+pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set(_path: &Path, name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{name} can't be set on this system"),
+    ))
+}